@@ -0,0 +1,14 @@
+// As an extension module, pyo3 leaves libpython for the interpreter
+// loading it to provide. The Rust tests are executables with no such
+// interpreter, so they link it themselves.
+fn main() {
+    if std::env::var_os("CARGO_FEATURE_EXTENSION_MODULE").is_none() {
+        return;
+    }
+    let config = pyo3_build_config::get();
+    if let (Some(dir), Some(name)) = (&config.lib_dir, &config.lib_name) {
+        println!("cargo:rustc-link-arg-tests=-L{}", dir);
+        println!("cargo:rustc-link-arg-tests=-l{}", name);
+        println!("cargo:rustc-link-arg-tests=-Wl,-rpath,{}", dir);
+    }
+}
@@ -1,248 +1,5824 @@
 use pyo3::prelude::*;
-use pyo3::exceptions::PyRuntimeError;
-use std::sync::{Arc, Mutex};
+use pyo3::create_exception;
+use pyo3::{ffi, AsPyPointer, PyTraverseError, PyVisit};
+use pyo3::pyclass::IterNextOutput;
+use pyo3::exceptions::{PyRuntimeError, PyTypeError, PyValueError};
+use pyo3::types::{PyBytes, PyCFunction, PyDict, PyIterator, PyList, PyTuple};
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex, Weak};
 use std::thread;
-use crossbeam_channel::{bounded, Sender, Receiver};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use crossbeam_channel::{unbounded, Sender, Receiver, RecvTimeoutError};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 
-// Job type that can hold a Python callback
-type Job = PyObject;
+// Python exceptions raised by the pool. They subclass RuntimeError so
+// existing `except RuntimeError` handlers keep working.
+create_exception!(nornir, QueueFullError, PyRuntimeError);
+create_exception!(nornir, PoolShutdownError, PyRuntimeError);
+// Reserved: failed jobs surface their own exceptions unchanged
+create_exception!(nornir, JobExecutionError, PyRuntimeError);
+
+// A submitted Python callback and the id it was assigned
+struct Job {
+    id: u64,
+    func: PyObject,
+    // List slot the result is written to, for submit_into
+    into: Option<(Py<PyList>, usize)>,
+    // Job group tallied for wait_group, unrelated to batching groups
+    tag: Option<u64>,
+    // concurrent.futures.Future resolved with the outcome, for submit
+    future: Option<PyObject>,
+    // Units the job counts as towards limiters and the weighted load
+    weight: usize,
+    // threading.Event set once the job reaches a terminal state
+    event: Option<PyObject>,
+    // Key from add_job_idempotent, released once the job settles
+    idempotency_key: Option<String>,
+    // Times the job has put itself back with reschedule()
+    reschedules: usize,
+    // Throttled by this category's rate, from set_category_rate, until the
+    // job has taken its token
+    category: Option<String>,
+    // What context_getter returned on the submitting thread, restored with
+    // context_setter while the job runs
+    context: Option<PyObject>,
+    // Applied to the return value on the worker, for submit's map_result
+    map_result: Option<PyObject>,
+    // What the final value must be an instance of, for submit's result_type
+    result_type: Option<PyObject>,
+    // Key from add_job_replace, released once the job settles
+    replace_key: Option<String>,
+    // Order among the waiting jobs of its add_job_keyed key, highest first
+    priority: i64,
+    // Times a higher-priority job was queued ahead of it, for max_overtakes
+    overtaken: usize,
+    // Dict from add_job or submit's metadata=, listed by pending_jobs and
+    // passed to the callbacks under callback_metadata
+    metadata: Option<PyObject>,
+    // Holds the queue slot of its NewJob message, given back as soon as the
+    // job leaves the queued table, whether a worker took it or it was
+    // cancelled
+    slot: bool,
+    // How it reaches a worker, which a job put back by reschedule() or its
+    // category's rate goes through again
+    route: Route,
+}
+
+// The queue and limits a job goes through on its way to a worker
+enum Route {
+    Plain,
+    // Holds permits from the semaphore while it runs, one per unit of weight
+    Limited(Arc<Semaphore>),
+    Grouped(u64),
+    Keyed(u64),
+    Weighted(usize),
+}
+
+impl Job {
+    fn traverse(&self, visit: &PyVisit<'_>) -> Result<(), PyTraverseError> {
+        visit.call(&self.func)?;
+        if let Some((results, _)) = &self.into {
+            visit.call(results)?;
+        }
+        if let Some(future) = &self.future {
+            visit.call(future)?;
+        }
+        if let Some(event) = &self.event {
+            visit.call(event)?;
+        }
+        if let Some(context) = &self.context {
+            visit.call(context)?;
+        }
+        if let Some(map_result) = &self.map_result {
+            visit.call(map_result)?;
+        }
+        if let Some(result_type) = &self.result_type {
+            visit.call(result_type)?;
+        }
+        if let Some(metadata) = &self.metadata {
+            visit.call(metadata)?;
+        }
+        Ok(())
+    }
+
+    // The job's metadata, or None, for the callbacks and pending_jobs
+    fn metadata(&self, py: Python<'_>) -> PyObject {
+        self.metadata.as_ref().map_or_else(|| py.None(), |metadata| metadata.clone_ref(py))
+    }
+
+    // Set the job's event, if it has one, whatever the outcome
+    fn signal(&self, py: Python<'_>) {
+        if let Some(event) = &self.event {
+            if let Err(e) = event.call_method0(py, "set") {
+                eprintln!("Failed to set event for job {}: {:?}", self.id, e);
+            }
+        }
+    }
+
+    // Fail the job's future, if it has one, for a job that will never run
+    fn abandon(&self, py: Python<'_>, reason: &str) {
+        if let Some(future) = &self.future {
+            let error = QueueFullError::new_err(reason.to_string());
+            if let Err(e) = future.call_method1(py, "set_exception", (error.value(py),)) {
+                eprintln!("Failed to resolve future for job {}: {:?}", self.id, e);
+            }
+        }
+        self.signal(py);
+    }
+}
+
+// A Python callback supplied at construction. It sits behind a lock so the
+// cyclic GC can clear it, and the lock is never held while calling Python.
+struct Callback(Mutex<Option<PyObject>>);
+
+impl Callback {
+    fn new(callback: Option<PyObject>) -> Callback {
+        Callback(Mutex::new(callback))
+    }
+
+    fn get(&self, py: Python<'_>) -> Option<PyObject> {
+        self.0.lock().unwrap().as_ref().map(|callback| callback.clone_ref(py))
+    }
+
+    fn is_set(&self) -> bool {
+        self.0.lock().unwrap().is_some()
+    }
+
+    fn traverse(&self, visit: &PyVisit<'_>) -> Result<(), PyTraverseError> {
+        // Never block the GC; a contended lock only delays collection
+        if let Ok(callback) = self.0.try_lock() {
+            if let Some(callback) = &*callback {
+                visit.call(callback)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn clear(&self) {
+        // Drop outside the lock, since a decref can run arbitrary Python
+        let callback = self.0.lock().unwrap().take();
+        drop(callback);
+    }
+}
+
+// What a worker is doing, for heartbeats() and worker_states()
+struct WorkerStatus {
+    // When it last woke between jobs
+    heartbeat: Instant,
+    // The id (None for map items), callable and start time of the job it
+    // is running
+    current: Option<(Option<u64>, PyObject, SystemTime)>,
+    jobs_executed: u64,
+    // Process RSS growth, in bytes, over the jobs this worker has run, for
+    // max_worker_rss
+    rss_growth: usize,
+    // Set by quiesce_worker; the worker parks once its current job is done
+    quiesced: bool,
+    // Whether on_slow_job has fired for the current job
+    slow_reported: bool,
+    // Kept as "dead" until the exited thread is reaped
+    exited: bool,
+}
+
+// Each worker thread's WorkerLocal value, present for as long as the
+// worker runs. None until on_worker_start returns a value or a job sets one.
+type WorkerSlots = Mutex<HashMap<thread::ThreadId, Option<PyObject>>>;
+
+// Smallest worker stack accepted, enough for the interpreter to run callbacks
+const MIN_STACK_SIZE: usize = 64 * 1024;
+
+// How often blocking waits wake up to let Python handle pending signals
+const SIGNAL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// Default number of same-group jobs a worker may run back to back
+const DEFAULT_GROUP_BATCH_LIMIT: usize = 8;
+
+// Default time a lazily spawned worker may sit idle before retiring
+const DEFAULT_IDLE_TIMEOUT_SECS: f64 = 60.0;
+
+// How often an autoscaling pool samples its queue
+const AUTOSCALE_INTERVAL: Duration = Duration::from_millis(100);
+
+// Consecutive samples the queue must stay backed up before the pool grows
+const SCALE_UP_SAMPLES: u32 = 3;
+
+// Weight of the newest job in the running average of exec_time
+const EXEC_TIME_SMOOTHING: f64 = 0.1;
+
+// Pause before the first on_worker_start retry, doubling with each one
+const INIT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+// Environment variable capping the pool size, for containers where the
+// CPU count is misreported
+const MAX_WORKERS_ENV: &str = "NORNIR_MAX_WORKERS";
+
+// Default longest a waiting worker goes without waking for housekeeping
+const DEFAULT_HOUSEKEEPING_INTERVAL_SECS: f64 = 5.0;
+
+// Default cap on how often one job may reschedule itself
+const DEFAULT_MAX_RESCHEDULES: usize = 100;
+
+// How many times per slow_job_threshold the monitor scans running jobs
+const SLOW_JOB_SCANS: u32 = 4;
+
+// Longest window throughput() can measure, and so how many per-second
+// buckets of completions are kept
+const THROUGHPUT_WINDOW_SECS: u64 = 60;
+
+// First and longest waits of add_job_async between attempts on a full queue
+const ASYNC_SUBMIT_RETRY: Duration = Duration::from_millis(1);
+const ASYNC_SUBMIT_MAX_RETRY: Duration = Duration::from_millis(50);
+
+// Upper bounds in nanoseconds of the dispatch_latency buckets, from 10µs to
+// 1s; one more bucket takes anything slower
+const DISPATCH_BUCKETS_NANOS: [u64; 6] = [10_000, 100_000, 1_000_000, 10_000_000, 100_000_000, 1_000_000_000];
+
+// Set by pause_all() to hold every pool's workers before their next job
+static PAUSED: AtomicBool = AtomicBool::new(false);
+// Wakes the workers held by PAUSED once resume_all() clears it
+static PAUSE_LOCK: Mutex<()> = Mutex::new(());
+static RESUMED: Condvar = Condvar::new();
+
+// Errors returned when submitting to the pool
+#[derive(Debug)]
+enum PoolError {
+    // The pool was shut down or its channel was disconnected
+    Shutdown,
+    // The queue was full and the overflow policy rejected the job
+    QueueFull,
+    // A custom queue's put() raised
+    Queue(PyErr),
+    // reserve asked for more slots than the queue holds
+    OverCapacity(usize, usize),
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolError::Shutdown => write!(f, "ThreadPool has been shutdown"),
+            PoolError::QueueFull => write!(f, "ThreadPool queue is full"),
+            PoolError::Queue(e) => write!(f, "Custom queue put() failed: {}", e),
+            PoolError::OverCapacity(n, capacity) => {
+                write!(f, "Cannot reserve {} slots; the queue holds {}", n, capacity)
+            }
+        }
+    }
+}
+
+impl From<PoolError> for PyErr {
+    fn from(err: PoolError) -> PyErr {
+        match err {
+            PoolError::Shutdown => PoolShutdownError::new_err(err.to_string()),
+            PoolError::QueueFull => QueueFullError::new_err(err.to_string()),
+            PoolError::Queue(e) => e,
+            PoolError::OverCapacity(..) => PyValueError::new_err(err.to_string()),
+        }
+    }
+}
+
+// What submission does when the queue is full
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OverflowPolicy {
+    // Wait for space
+    Block,
+    // Reject the incoming job
+    DropNew,
+    // Evict the oldest queued job to make room
+    DropOld,
+}
+
+impl OverflowPolicy {
+    fn parse(policy: &str) -> Option<OverflowPolicy> {
+        match policy {
+            "block" => Some(OverflowPolicy::Block),
+            "drop_new" => Some(OverflowPolicy::DropNew),
+            "drop_old" => Some(OverflowPolicy::DropOld),
+            _ => None,
+        }
+    }
+}
+
+// The kind of work the pool is sized for
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Workload {
+    // Jobs compete for CPU, so threads beyond the core count add little
+    Cpu,
+    // Jobs mostly wait on I/O, where oversubscribing is the point
+    Io,
+}
+
+impl Workload {
+    fn parse(workload: &str) -> Option<Workload> {
+        match workload {
+            "cpu" => Some(Workload::Cpu),
+            "io" | "blocking" => Some(Workload::Io),
+            _ => None,
+        }
+    }
+}
+
+// What a worker does when on_worker_start raises
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum InitFailure {
+    // Log the error and run without a worker_local value
+    Log,
+    // Call it again this many times, then give up like Fail
+    Retry(usize),
+    // Exit without running any jobs
+    Fail,
+}
+
+impl InitFailure {
+    fn parse(policy: &str, retries: usize) -> Option<InitFailure> {
+        match policy {
+            "log" => Some(InitFailure::Log),
+            "retry" => Some(InitFailure::Retry(retries)),
+            "fail" => Some(InitFailure::Fail),
+            _ => None,
+        }
+    }
+}
+
+// Outcomes of on_worker_start, so construction can wait for the first
+// workers and fail if none of them started
+struct InitLog {
+    // Workers that finished starting, and the errors of those that gave up
+    state: Mutex<(usize, Vec<String>)>,
+    done: Condvar,
+}
+
+impl InitLog {
+    fn record(&self, error: Option<String>) {
+        let mut state = self.state.lock().unwrap();
+        state.0 += 1;
+        state.1.extend(error);
+        self.done.notify_all();
+    }
+
+    // Block until `workers` have finished starting, returning the errors of
+    // the ones that gave up
+    fn wait(&self, workers: usize) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        let state = self.done.wait_while(state, |(started, _)| *started < workers).unwrap();
+        state.1.clone()
+    }
+}
 
 // Message type for communication between threads
+// Jobs wait in the shared queued table, so the cyclic GC can see them, and
+// their messages carry only the job id
 enum Message {
-    NewJob(Job),
+    NewJob(u64),
+    // The job itself waits in the group's sub-queue; this only wakes a worker
+    GroupedJob(u64),
+    // The job waits in its key's sub-queue; only one worker at a time runs
+    // jobs for a key
+    KeyedJob(u64),
+    // The job waits in one of the add_job_to sub-queues; the worker takes
+    // whichever job is next in round-robin order, not necessarily this one
+    WeightedJob(usize),
+    // A job that must hold permits from the semaphore while it runs, one
+    // per unit of its weight
+    LimitedJob(u64, Arc<Semaphore>, usize),
+    // A function applied to one item, with the outcome sent back to the caller
+    Apply(Apply),
+    // A Rust closure from NativePool, run without the GIL
+    Native(NativeJob),
+    // A job queued by id without a queue slot: one put back by
+    // reschedule(), so a worker can always do that even when the queue is
+    // full, which then goes through its route again, or a shutdown job,
+    // sent once submissions have stopped
+    Requeued(u64),
+    // Sent by the autoscaler, which has already given up the worker's live slot
+    Retire,
     Terminate,
 }
 
-// Worker struct to handle individual threads
-struct Worker {
-    id: usize,
-    thread: Option<thread::JoinHandle<()>>,
+impl Message {
+    // Whether the message holds a queue slot until a worker receives it.
+    // A NewJob's slot belongs to its job instead.
+    fn holds_slot(&self) -> bool {
+        !matches!(
+            self,
+            Message::NewJob(_) | Message::Requeued(_) | Message::Retire | Message::Terminate
+        )
+    }
+}
+
+thread_local! {
+    // The job running on this worker thread, with its reschedule count
+    static CURRENT_JOB: Cell<Option<(u64, usize)>> = const { Cell::new(None) };
+    // Set by reschedule() to put the running job back after the delay
+    static RESCHEDULE: Cell<Option<Duration>> = const { Cell::new(None) };
+}
+
+// A message stamped with when it was sent, for the dispatch_latency stat
+type Envelope = (Message, Instant);
+
+// A finished job's id with its return value or exception, for results()
+type Outcome = (u64, PyObject);
+
+// A queued job's id, callable and metadata, for pending_jobs
+type PendingJob = (u64, PyObject, PyObject);
+
+// A completion callback call handed to the callback thread, with the job's
+// metadata for callback_metadata
+enum Notification {
+    // on_complete_timed's (job_id, result, exec_time)
+    Completed(u64, PyObject, f64, PyObject),
+    // on_finally's (job_id, status)
+    Finally(u64, &'static str, PyObject),
+}
+
+// Holds back completion callbacks until every earlier job's have gone, for
+// ordered_callbacks
+#[derive(Default)]
+struct ReorderBuffer {
+    // Jobs submitted and not yet finished
+    outstanding: BTreeSet<u64>,
+    // Notifications waiting on an earlier job, by job id
+    held: BTreeMap<u64, Vec<Notification>>,
+    // Finished jobs whose notifications are still held
+    finished: BTreeSet<u64>,
+}
+
+struct Apply {
+    func: PyObject,
+    item: PyObject,
+    // The item's position in the input, sent back with its outcome
+    index: usize,
+    results: Sender<(usize, PyResult<PyObject>)>,
+}
+
+// Counting semaphore used to cap concurrency within a shared pool
+struct Semaphore {
+    permits: Mutex<usize>,
+    // Permits a shrink could not take back because they were in use, kept
+    // from the next releases instead; only changed with `permits` locked
+    owed: AtomicUsize,
+    released: Condvar,
+}
+
+impl Semaphore {
+    fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            permits: Mutex::new(permits),
+            owed: AtomicUsize::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    fn available(&self) -> usize {
+        *self.permits.lock().unwrap()
+    }
+
+    // Block until `count` permits are free. They are returned when the
+    // guard is dropped, so they are released even if the job fails.
+    fn acquire(&self, count: usize) -> Permit<'_> {
+        self.acquire_many(count);
+        Permit { semaphore: self, count }
+    }
+
+    // Take `n` permits at once if they are all free right now
+    fn try_acquire_many(&self, n: usize) -> bool {
+        let mut permits = self.permits.lock().unwrap();
+        if *permits < n {
+            return false;
+        }
+        *permits -= n;
+        true
+    }
+
+    // Block until `n` permits are free and take them together
+    fn acquire_many(&self, n: usize) {
+        self.acquire_many_unless(n, || false);
+    }
+
+    // acquire_many, giving up with false once `give_up` holds. It is checked
+    // whenever permits are released or withdrawn.
+    fn acquire_many_unless(&self, n: usize, give_up: impl Fn() -> bool) -> bool {
+        let permits = self.permits.lock().unwrap();
+        let mut permits = self
+            .released
+            .wait_while(permits, |permits| *permits < n && !give_up())
+            .unwrap();
+        if *permits < n {
+            return false;
+        }
+        *permits -= n;
+        true
+    }
+
+    fn release_many(&self, n: usize) {
+        let mut permits = self.permits.lock().unwrap();
+        let owed = self.owed.load(Ordering::SeqCst);
+        let kept = owed.min(n);
+        self.owed.store(owed - kept, Ordering::SeqCst);
+        *permits += n - kept;
+        drop(permits);
+        // Waiters may need different counts, so wake them all
+        self.released.notify_all();
+    }
+
+    // Withdraw `n` permits, the free ones now and the rest as they are
+    // released
+    fn shrink(&self, n: usize) {
+        let mut permits = self.permits.lock().unwrap();
+        let taken = (*permits).min(n);
+        *permits -= taken;
+        self.owed.fetch_add(n - taken, Ordering::SeqCst);
+        drop(permits);
+        // Waiters that can no longer be satisfied give up
+        self.released.notify_all();
+    }
+}
+
+struct Permit<'a> {
+    semaphore: &'a Semaphore,
+    count: usize,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.release_many(self.count);
+    }
+}
+
+// The subinterpreter a pool was created in. with_gil attaches a thread
+// with no thread state to the main interpreter, so each thread the pool
+// starts first makes itself one in this interpreter.
+#[derive(Clone, Copy)]
+struct Interpreter(*mut ffi::PyInterpreterState);
+
+// SAFETY: the pointer is only passed to PyThreadState_New, which any
+// thread may call without the GIL
+unsafe impl Send for Interpreter {}
+unsafe impl Sync for Interpreter {}
+
+impl Interpreter {
+    // The caller's interpreter, unless that is the main one
+    fn current(_py: Python<'_>) -> Option<Interpreter> {
+        // SAFETY: holding the GIL means there is a current interpreter
+        let (current, main) = unsafe { (ffi::PyInterpreterState_Get(), ffi::PyInterpreterState_Main()) };
+        (current != main).then_some(Interpreter(current))
+    }
+
+    // Give the calling thread a thread state in this interpreter. Being the
+    // thread's first, it is the one PyGILState_Ensure picks up from then on.
+    fn enter(self) -> InterpreterThread {
+        // SAFETY: the interpreter outlives the pool, which joins its
+        // threads at shutdown
+        InterpreterThread(unsafe { ffi::PyThreadState_New(self.0) })
+    }
+}
+
+// A pool thread's thread state in its subinterpreter, deleted when the
+// thread is done with Python
+struct InterpreterThread(*mut ffi::PyThreadState);
+
+impl Drop for InterpreterThread {
+    fn drop(&mut self) {
+        if self.0.is_null() {
+            return;
+        }
+        // SAFETY: the thread state is this thread's and no with_gil is
+        // active, so the GIL is not held here
+        unsafe {
+            ffi::PyEval_RestoreThread(self.0);
+            ffi::PyThreadState_Clear(self.0);
+            ffi::PyThreadState_DeleteCurrent();
+        }
+    }
+}
+
+// Marks a job as running for in_flight(), holding a max_in_flight permit
+// if the pool has a cap
+struct InFlight<'a> {
+    shared: &'a Shared,
+    _permit: Option<Permit<'a>>,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.shared.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Per-group sub-queues for grouped jobs
+struct GroupQueues {
+    queues: Mutex<HashMap<u64, VecDeque<Job>>>,
+    // Groups with queued jobs, in the order they take turns. Only locked
+    // while holding queues.
+    turns: Mutex<VecDeque<u64>>,
+    // Fairness cap on consecutive jobs from one group
+    batch_limit: usize,
+    // How many times push_by_priority may queue a job ahead of another
+    max_overtakes: Option<usize>,
+}
+
+impl GroupQueues {
+    fn new(batch_limit: usize, max_overtakes: Option<usize>) -> GroupQueues {
+        GroupQueues {
+            queues: Mutex::new(HashMap::new()),
+            turns: Mutex::new(VecDeque::new()),
+            batch_limit,
+            max_overtakes,
+        }
+    }
+
+    fn push(&self, group: u64, job: Job) {
+        let mut queues = self.queues.lock().unwrap();
+        queues.entry(group).or_default().push_back(job);
+        let mut turns = self.turns.lock().unwrap();
+        if !turns.contains(&group) {
+            turns.push_back(group);
+        }
+    }
+
+    // The group whose turn it is to run a batch, which goes to the back of
+    // the line so the other groups are served before it again
+    fn next_turn(&self) -> Option<u64> {
+        let queues = self.queues.lock().unwrap();
+        let mut turns = self.turns.lock().unwrap();
+        // Groups emptied since their last turn drop out
+        turns.retain(|group| queues.contains_key(group));
+        let group = turns.pop_front()?;
+        turns.push_back(group);
+        Some(group)
+    }
+
+    // Queue the job ahead of any with a lower priority and behind the
+    // rest, so equal priorities keep submission order. Jobs overtaken
+    // max_overtakes times are not passed again, and the queue behind the
+    // last of them is still in priority order.
+    fn push_by_priority(&self, group: u64, job: Job) {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.entry(group).or_default();
+        let start = self
+            .max_overtakes
+            .and_then(|max| queue.iter().rposition(|queued| queued.overtaken >= max))
+            .map_or(0, |last| last + 1);
+        let index = queue
+            .iter()
+            .skip(start)
+            .position(|queued| queued.priority < job.priority)
+            .map_or(queue.len(), |offset| start + offset);
+        for queued in queue.range_mut(index..) {
+            queued.overtaken += 1;
+        }
+        queue.insert(index, job);
+    }
+
+    fn pop(&self, group: u64) -> Option<Job> {
+        self.take(group, VecDeque::pop_front)
+    }
+
+    fn pop_newest(&self, group: u64) -> Option<Job> {
+        self.take(group, VecDeque::pop_back)
+    }
+
+    fn len(&self) -> usize {
+        self.queues.lock().unwrap().values().map(VecDeque::len).sum()
+    }
+
+    fn traverse(&self, visit: &PyVisit<'_>) -> Result<(), PyTraverseError> {
+        if let Ok(queues) = self.queues.try_lock() {
+            for job in queues.values().flatten() {
+                job.traverse(visit)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self, py: Python<'_>) -> Vec<PendingJob> {
+        let queues = self.queues.lock().unwrap();
+        queues
+            .values()
+            .flatten()
+            .map(|job| (job.id, job.func.clone_ref(py), job.metadata(py)))
+            .collect()
+    }
+
+    fn take_all(&self) -> Vec<Job> {
+        let mut queues = self.queues.lock().unwrap();
+        self.turns.lock().unwrap().clear();
+        queues.drain().flat_map(|(_, queue)| queue).collect()
+    }
+
+    fn take(&self, group: u64, pop: fn(&mut VecDeque<Job>) -> Option<Job>) -> Option<Job> {
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.get_mut(&group)?;
+        let job = pop(queue);
+        if queue.is_empty() {
+            queues.remove(&group);
+        }
+        job
+    }
+}
+
+// Named sub-queues for add_job_to, served in weighted round-robin order
+struct WeightedQueues {
+    state: Mutex<WeightedState>,
+}
+
+struct WeightedState {
+    // Name, weight and waiting jobs of each sub-queue, in construction order
+    queues: Vec<(String, usize, VecDeque<Job>)>,
+    // The sub-queue whose turn it is and how many jobs it has had this turn
+    turn: usize,
+    served: usize,
+}
+
+impl WeightedQueues {
+    fn new(queues: Vec<(String, usize)>) -> WeightedQueues {
+        WeightedQueues {
+            state: Mutex::new(WeightedState {
+                queues: queues
+                    .into_iter()
+                    .map(|(name, weight)| (name, weight, VecDeque::new()))
+                    .collect(),
+                turn: 0,
+                served: 0,
+            }),
+        }
+    }
+
+    fn index(&self, name: &str) -> Option<usize> {
+        let state = self.state.lock().unwrap();
+        state.queues.iter().position(|(queue, _, _)| queue == name)
+    }
+
+    fn push(&self, index: usize, job: Job) {
+        self.state.lock().unwrap().queues[index].2.push_back(job);
+    }
+
+    // The next job in turn: up to `weight` jobs from a sub-queue before
+    // moving on, skipping empty ones
+    fn next(&self) -> Option<Job> {
+        let mut state = self.state.lock().unwrap();
+        let WeightedState { queues, turn, served } = &mut *state;
+        if queues.is_empty() {
+            return None;
+        }
+        for _ in 0..=queues.len() {
+            let (_, weight, jobs) = &mut queues[*turn];
+            if *served < *weight {
+                if let Some(job) = jobs.pop_front() {
+                    *served += 1;
+                    return Some(job);
+                }
+            }
+            *turn = (*turn + 1) % queues.len();
+            *served = 0;
+        }
+        None
+    }
+
+    // Take a job back out for a discarded message. The message's own
+    // sub-queue may have been drained through other messages, in which case
+    // any job stands in for it.
+    fn remove(&self, index: usize, newest: bool) -> Option<Job> {
+        let job = {
+            let mut state = self.state.lock().unwrap();
+            let jobs = &mut state.queues[index].2;
+            if newest {
+                jobs.pop_back()
+            } else {
+                jobs.pop_front()
+            }
+        };
+        job.or_else(|| self.next())
+    }
+
+    fn len(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.queues.iter().map(|(_, _, jobs)| jobs.len()).sum()
+    }
+
+    fn depths(&self) -> Vec<(String, usize)> {
+        let state = self.state.lock().unwrap();
+        state
+            .queues
+            .iter()
+            .map(|(name, _, jobs)| (name.clone(), jobs.len()))
+            .collect()
+    }
+
+    fn traverse(&self, visit: &PyVisit<'_>) -> Result<(), PyTraverseError> {
+        if let Ok(state) = self.state.try_lock() {
+            for (_, _, jobs) in &state.queues {
+                for job in jobs {
+                    job.traverse(visit)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self, py: Python<'_>) -> Vec<PendingJob> {
+        let state = self.state.lock().unwrap();
+        state
+            .queues
+            .iter()
+            .flat_map(|(_, _, jobs)| jobs)
+            .map(|job| (job.id, job.func.clone_ref(py), job.metadata(py)))
+            .collect()
+    }
+
+    fn take_all(&self) -> Vec<Job> {
+        let mut state = self.state.lock().unwrap();
+        state.queues.iter_mut().flat_map(|(_, _, jobs)| jobs.drain(..)).collect()
+    }
+}
+
+// Outcomes so far of the jobs in one add_job_in_group group
+#[derive(Clone, Copy, Default)]
+struct GroupTally {
+    completed: usize,
+    failed: usize,
+    cancelled: usize,
+    dropped: usize,
+    pending: usize,
+}
+
+// Cumulative job outcome counts, zeroed by drain_stats. A reschedule
+// counts as a retry, and a job the overflow policy gave up on as expired.
+#[derive(Clone, Copy, Default)]
+struct JobCounters {
+    submitted: u64,
+    completed: u64,
+    failed: u64,
+    retried: u64,
+    cancelled: u64,
+    expired: u64,
+}
+
+impl JobCounters {
+    fn record(&mut self, event: &str) {
+        match event {
+            "submitted" => self.submitted += 1,
+            "completed" => self.completed += 1,
+            "failed" => self.failed += 1,
+            "rescheduled" => self.retried += 1,
+            "cancelled" => self.cancelled += 1,
+            _ => self.expired += 1,
+        }
+    }
+}
+
+// Limits a job category to `rate` starts per second, allowing bursts of up
+// to a second's worth
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64) -> TokenBucket {
+        TokenBucket {
+            rate,
+            tokens: rate.max(1.0),
+            refilled: Instant::now(),
+        }
+    }
+
+    // Take a token, returning how long until it is due. Tokens not yet
+    // refilled are reserved in turn, so waiting jobs keep their order and
+    // don't compete again when they come back.
+    fn take(&mut self) -> Duration {
+        let now = Instant::now();
+        let refill = now.duration_since(self.refilled).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.rate.max(1.0));
+        self.refilled = now;
+
+        self.tokens -= 1.0;
+        Duration::from_secs_f64((-self.tokens).max(0.0) / self.rate)
+    }
+}
+
+// The last `capacity` job lifecycle events, for post-mortem debugging. The
+// buffer is allocated up front and events are plain values, so recording
+// never allocates.
+struct FlightRecorder {
+    events: Mutex<VecDeque<(SystemTime, u64, &'static str)>>,
+    capacity: usize,
+}
+
+impl FlightRecorder {
+    fn new(capacity: usize) -> FlightRecorder {
+        FlightRecorder {
+            events: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    fn record(&self, job_id: u64, event: &'static str) {
+        let mut events = self.events.lock().unwrap();
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back((SystemTime::now(), job_id, event));
+    }
+
+    // Oldest first
+    fn dump(&self) -> Vec<(SystemTime, u64, &'static str)> {
+        self.events.lock().unwrap().iter().copied().collect()
+    }
+}
+
+// Successful completions per second over the last THROUGHPUT_WINDOW_SECS
+// seconds. Buckets roll over as completions are recorded and read, so
+// nothing needs to tick while the pool is quiet.
+struct Throughput {
+    started: Instant,
+    // (seconds since started, completions) for recent seconds with any
+    buckets: Mutex<VecDeque<(u64, u64)>>,
 }
 
-impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<Receiver<Message>>>, job_count: Arc<Mutex<usize>>) -> Worker {
-        // Create a new thread that will process jobs
-        let thread = thread::spawn(move || {
-            loop {
-                // Get a message from the channel
-                let message = match receiver.lock().unwrap().recv() {
-                    Ok(msg) => msg,
-                    Err(_) => {
-                        println!("Worker {}: Channel closed, exiting", id);
-                        break;
-                    }
-                };
+impl Throughput {
+    fn new() -> Throughput {
+        Throughput {
+            started: Instant::now(),
+            buckets: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    fn record(&self) {
+        let now = self.started.elapsed().as_secs();
+        let mut buckets = self.buckets.lock().unwrap();
+        match buckets.back_mut() {
+            Some((second, count)) if *second == now => *count += 1,
+            _ => buckets.push_back((now, 1)),
+        }
+        while buckets.front().is_some_and(|&(second, _)| now - second >= THROUGHPUT_WINDOW_SECS) {
+            buckets.pop_front();
+        }
+    }
+
+    // Completions per second over the last `window` seconds, counting the
+    // current second for as much of it as has passed
+    fn rate(&self, window: u64) -> f64 {
+        let elapsed = self.started.elapsed();
+        let now = elapsed.as_secs();
+        let count: u64 = self
+            .buckets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|&&(second, _)| now - second < window)
+            .map(|&(_, count)| count)
+            .sum();
+        let covered = ((window - 1) as f64 + f64::from(elapsed.subsec_nanos()) / 1e9).min(elapsed.as_secs_f64());
+        if covered > 0.0 {
+            count as f64 / covered
+        } else {
+            0.0
+        }
+    }
+}
+
+// Collapses repeated job errors into one summary line per window instead
+// of logging every failure
+struct ErrorLog {
+    window: Duration,
+    state: Mutex<ErrorLogState>,
+    wake: Condvar,
+}
+
+#[derive(Default)]
+struct ErrorLogState {
+    // Failure counts keyed by exception type
+    counts: HashMap<String, usize>,
+    stopped: bool,
+}
+
+impl ErrorLog {
+    fn new(window: Duration) -> ErrorLog {
+        ErrorLog {
+            window,
+            state: Mutex::new(ErrorLogState::default()),
+            wake: Condvar::new(),
+        }
+    }
+
+    fn record(&self, kind: String) {
+        *self.state.lock().unwrap().counts.entry(kind).or_insert(0) += 1;
+    }
+
+    // Flush a summary every window until stopped, then flush once more
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let stopped = state.stopped;
+            if !stopped {
+                state = self.wake.wait_timeout(state, self.window).unwrap().0;
+            }
+
+            let mut counts: Vec<_> = state.counts.drain().collect();
+            counts.sort();
+            for (kind, count) in counts {
+                let jobs = if count == 1 { "job" } else { "jobs" };
+                eprintln!(
+                    "{} {} failed: {} (last {:?})",
+                    count, jobs, kind, self.window
+                );
+            }
+
+            if stopped {
+                break;
+            }
+        }
+    }
+
+    fn stop(&self) {
+        self.state.lock().unwrap().stopped = true;
+        self.wake.notify_all();
+    }
+}
+
+// Grows and shrinks an autoscaling pool's core workers from sampled queue
+// depth. Either change only happens once its condition has held for a
+// while, then proceeds one worker per sample until the condition clears.
+struct Autoscaler {
+    // How long the pool must sit quiet before it starts shrinking
+    shrink_after: Duration,
+    stopped: Mutex<bool>,
+    wake: Condvar,
+}
+
+impl Autoscaler {
+    fn new(shrink_after: Duration) -> Autoscaler {
+        Autoscaler {
+            shrink_after,
+            stopped: Mutex::new(false),
+            wake: Condvar::new(),
+        }
+    }
+
+    fn run(&self, workers: &Workers, sender: Sender<Envelope>, size: usize) {
+        let shared = &workers.shared;
+        let mut backed_up = 0;
+        let mut quiet_since: Option<Instant> = None;
+
+        loop {
+            let stopped = self.stopped.lock().unwrap();
+            let (stopped, _) = self
+                .wake
+                .wait_timeout_while(stopped, AUTOSCALE_INTERVAL, |stopped| !*stopped)
+                .unwrap();
+            if *stopped {
+                break;
+            }
+            drop(stopped);
+
+            // Stale messages would keep it growing with nothing to run
+            let queue_len = shared.queue_len();
+            let idle = shared.idle_workers.load(Ordering::SeqCst);
+            let live = shared.live_workers.load(Ordering::SeqCst);
+            let busy = (live + shared.burst_workers.load(Ordering::SeqCst)).saturating_sub(idle);
+
+            if queue_len > idle {
+                backed_up += 1;
+                quiet_since = None;
+            } else {
+                backed_up = 0;
+                if queue_len == 0 && idle > 0 {
+                    quiet_since.get_or_insert_with(Instant::now);
+                } else {
+                    quiet_since = None;
+                }
+            }
+
+            // Nothing can run the queue without a worker, so don't wait
+            let grow = backed_up >= SCALE_UP_SAMPLES || (live == 0 && queue_len > 0);
+            let shrink = quiet_since.is_some_and(|since| since.elapsed() >= self.shrink_after);
+
+            if grow && shared.try_grow(size) {
+                match workers.spawn(WorkerKind::Core) {
+                    Ok(()) => shared.notify_scale(live, live + 1, queue_len, busy),
+                    Err(e) => eprintln!("Failed to spawn worker thread: {}", e),
+                }
+            } else if shrink && shared.try_retire() {
+                // A busy worker picks this up once it is done; with the queue
+                // empty that's soon
+                if sender.send((Message::Retire, Instant::now())).is_ok() {
+                    shared.notify_scale(live, live - 1, queue_len, busy);
+                }
+            }
+        }
+    }
+
+    fn stop(&self) {
+        *self.stopped.lock().unwrap() = true;
+        self.wake.notify_all();
+    }
+}
+
+// Construction options for a thread pool
+struct PoolConfig {
+    size: usize,
+    group_batch_limit: usize,
+    // Spawn workers on demand instead of all up front
+    lazy: bool,
+    // Lazy pools start with, and shrink back to, this many workers
+    min_workers: usize,
+    idle_timeout: Duration,
+    overflow: OverflowPolicy,
+    // Run add_job jobs newest-first
+    lifo: bool,
+    // Lifecycle callbacks, each called with the worker id
+    on_worker_start: Option<PyObject>,
+    on_worker_stop: Option<PyObject>,
+    // Called with (job_id, result, exec_time) after each successful job
+    on_complete_timed: Option<PyObject>,
+    // Called with (job_id, status) exactly once when a job reaches a terminal state
+    on_finally: Option<PyObject>,
+    // Add the job's metadata as a last argument to both callbacks
+    callback_metadata: bool,
+    // Called with each submitted callable, returning the one to queue
+    submit_middleware: Option<PyObject>,
+    // Summarise job errors over this window instead of logging each one
+    error_log_window: Option<Duration>,
+    // Stack size in bytes for worker threads, or the platform default
+    stack_size: Option<usize>,
+    // Warn when size exceeds twice the CPU count
+    warn_oversubscribe: bool,
+    // Cap on jobs running at once, below the worker count
+    max_in_flight: Option<usize>,
+    // Called with no arguments when a worker takes the last queued message
+    on_queue_empty: Option<PyObject>,
+    init_failure: InitFailure,
+    // Queue length above which on_watermark reports "high"
+    soft_capacity: Option<usize>,
+    // Queue capacity before the overflow policy applies, instead of twice size
+    hard_capacity: Option<usize>,
+    // Called with "high" when the queue rises above soft_capacity and "low"
+    // when it falls back
+    on_watermark: Option<PyObject>,
+    // Queue length and how long it must stay above it for on_backlog_alarm
+    // to report "raised", or at or below it to report "cleared"
+    backlog_alarm: Option<(usize, Duration)>,
+    on_backlog_alarm: Option<PyObject>,
+    // Size the pool between min_workers and size from sampled queue depth,
+    // shrinking once it has been quiet for idle_timeout
+    autoscale: bool,
+    // Called with (old_workers, new_workers, queue_len, busy_workers) after
+    // each autoscaling change
+    on_scale: Option<PyObject>,
+    // How often one job may reschedule itself
+    max_reschedules: usize,
+    // Object with put(job) and get() that orders add_job jobs instead of
+    // the channel
+    queue: Option<PyObject>,
+    // Call on_complete_timed and on_finally from one dedicated thread
+    // instead of the worker
+    callback_thread: bool,
+    // Call on_complete_timed and on_finally in submission order, on the
+    // callback thread
+    ordered_callbacks: bool,
+    // Called when the last free queue slot is taken, and once enough slots
+    // are free again
+    on_full: Option<PyObject>,
+    on_not_full: Option<PyObject>,
+    // Reject jobs that cannot be pickled, so checkpoint() can save them all
+    picklable: bool,
+    // How long a job may run before on_slow_job is called for it
+    slow_job_threshold: Option<Duration>,
+    on_slow_job: Option<PyObject>,
+    // Most add_job jobs a worker runs in one GIL acquisition
+    coalesce: Option<usize>,
+    // How many higher-priority add_job_keyed jobs may pass a waiting one
+    max_overtakes: Option<usize>,
+    // Set when the pool is created in a subinterpreter
+    interpreter: Option<Interpreter>,
+    // Longest a waiting worker goes without waking to refresh its heartbeat
+    housekeeping_interval: Duration,
+    // Keep this many recent job events for flight_recorder_dump
+    flight_recorder: Option<usize>,
+    // Frames kept when logging a failed job's traceback, or all of them
+    traceback_depth: Option<usize>,
+    // Called with (job_id, message) for each log() call, instead of
+    // buffering the line for job_logs
+    on_log: Option<PyObject>,
+    // Names and weights of the add_job_to sub-queues
+    sub_queues: Vec<(String, usize)>,
+    // Capture a context such as OpenTelemetry's at submission, and restore
+    // it on the worker around the job
+    context_getter: Option<PyObject>,
+    context_setter: Option<PyObject>,
+    // Replace a worker once its jobs have grown the process RSS by this
+    // many bytes
+    max_worker_rss: Option<usize>,
+}
+
+impl PoolConfig {
+    // The defaults Aqueue uses, without any Python callbacks, for NativePool
+    fn native(size: usize) -> PoolConfig {
+        PoolConfig {
+            size,
+            group_batch_limit: DEFAULT_GROUP_BATCH_LIMIT,
+            lazy: false,
+            min_workers: 0,
+            idle_timeout: Duration::from_secs_f64(DEFAULT_IDLE_TIMEOUT_SECS),
+            overflow: OverflowPolicy::Block,
+            lifo: false,
+            on_worker_start: None,
+            on_worker_stop: None,
+            on_complete_timed: None,
+            on_finally: None,
+            callback_metadata: false,
+            submit_middleware: None,
+            error_log_window: None,
+            stack_size: None,
+            warn_oversubscribe: true,
+            max_in_flight: None,
+            on_queue_empty: None,
+            init_failure: InitFailure::Log,
+            soft_capacity: None,
+            hard_capacity: None,
+            on_watermark: None,
+            backlog_alarm: None,
+            on_backlog_alarm: None,
+            autoscale: false,
+            on_scale: None,
+            max_reschedules: DEFAULT_MAX_RESCHEDULES,
+            queue: None,
+            callback_thread: false,
+            ordered_callbacks: false,
+            on_full: None,
+            on_not_full: None,
+            picklable: false,
+            slow_job_threshold: None,
+            on_slow_job: None,
+            coalesce: None,
+            max_overtakes: None,
+            interpreter: None,
+            housekeeping_interval: Duration::from_secs_f64(DEFAULT_HOUSEKEEPING_INTERVAL_SECS),
+            flight_recorder: None,
+            traceback_depth: None,
+            on_log: None,
+            sub_queues: Vec::new(),
+            context_getter: None,
+            context_setter: None,
+            max_worker_rss: None,
+        }
+    }
+}
+
+// Which part of the pool's capacity a worker provides
+#[derive(Clone, Copy)]
+enum WorkerKind {
+    // One of the pool's configured workers
+    Core,
+    // Temporary capacity from burst(), retired after idling for the ttl
+    Burst(Duration),
+}
+
+// State shared between the pool and its worker threads
+struct Shared {
+    // Workers receive directly; crossbeam channels are multi-consumer
+    receiver: Receiver<Envelope>,
+    // Bounds the queue: every queued message other than Terminate holds a
+    // slot until a worker receives it
+    slots: Arc<Semaphore>,
+    // Jobs waiting to run, keyed by the id carried in their message
+    queued: Mutex<HashMap<u64, Job>>,
+    // In LIFO mode, ids of add_job jobs in submission order; workers run
+    // the newest, and NewJob messages only wake them
+    lifo: Option<Mutex<Vec<u64>>>,
+    // Outstanding job count, paired with a condvar signalled whenever it drops
+    job_count: Mutex<usize>,
+    job_done: Condvar,
+    // Shared with every WorkerLocal handed out by the pool
+    worker_slots: Arc<WorkerSlots>,
+    // Sum of the weights of outstanding jobs
+    weighted_load: AtomicUsize,
+    // Jobs currently running, and the max_in_flight cap workers park on
+    in_flight: AtomicUsize,
+    in_flight_limit: Option<Semaphore>,
+    // Keys of add_job_idempotent jobs still queued or running, with their ids
+    idempotency_keys: Mutex<HashMap<String, u64>>,
+    // The latest add_job_replace job for each key
+    replace_keys: Mutex<HashMap<String, u64>>,
+    // Tallies for add_job_in_group, updated before each job is settled
+    tallies: Mutex<HashMap<u64, GroupTally>>,
+    // Under one lock so drain_stats reads and zeroes them together
+    counters: Mutex<JobCounters>,
+    // Running totals of jobs counted in and settled, only changed while
+    // holding job_count so waiters on job_done see them consistently
+    submitted: AtomicU64,
+    settled: AtomicU64,
+    // Jobs that completed successfully, raised just before they settle
+    completed: AtomicU64,
+    throughput: Throughput,
+    groups: GroupQueues,
+    // Jobs from add_job_keyed, queued per key hash
+    keyed: GroupQueues,
+    // Jobs from add_job_to
+    weighted: WeightedQueues,
+    // apply() items and native jobs sent but not yet received, which carry
+    // their work in the message instead of waiting in a queue
+    bare_messages: AtomicUsize,
+    // Lets workers put rescheduled jobs back on the queue
+    resend: Sender<Envelope>,
+    // Ids of rescheduled jobs waiting in the queued table for their delay
+    // to pass, with when it does
+    delayed: Mutex<Vec<(Instant, u64)>>,
+    max_reschedules: usize,
+    // Jobs from add_job_main, run by pump_main on the main thread. They are
+    // not counted as outstanding, since waiting on them from the main
+    // thread would never finish.
+    main_jobs: Mutex<VecDeque<Job>>,
+    // Jobs from add_shutdown_job, run one at a time during shutdown
+    shutdown_jobs: Mutex<VecDeque<Job>>,
+    // Keys whose jobs a worker is running, each with how many more of its
+    // messages arrived meanwhile; that worker runs those jobs as well
+    busy_keys: Mutex<HashMap<u64, usize>>,
+    // Core workers currently alive
+    live_workers: AtomicUsize,
+    burst_workers: AtomicUsize,
+    // Live workers currently waiting for a message
+    idle_workers: AtomicUsize,
+    min_workers: usize,
+    // Set for lazy pools, whose workers retire after idling this long
+    idle_timeout: Option<Duration>,
+    housekeeping_interval: Duration,
+    worker_status: Mutex<HashMap<usize, WorkerStatus>>,
+    // Wakes parked workers when one is unquiesced or the pool shuts down
+    unquiesced: Condvar,
+    max_worker_rss: Option<usize>,
+    // Set at shutdown so pause_all() no longer holds this pool's workers
+    stopping: AtomicBool,
+    slow_job_threshold: Option<Duration>,
+    on_slow_job: Callback,
+    coalesce: Option<usize>,
+    // Asks the recycler thread to spawn a replacement for a worker leaving
+    // over max_worker_rss; taken at shutdown
+    recycle_requests: Mutex<Option<Sender<()>>>,
+    flight_recorder: Option<Arc<FlightRecorder>>,
+    traceback_depth: Option<usize>,
+    on_log: Callback,
+    // Lines from log() by job id, until job_logs collects them
+    job_logs: Mutex<HashMap<u64, Vec<String>>>,
+    // Rates set with set_category_rate; categories without one run freely
+    category_rates: Mutex<HashMap<String, TokenBucket>>,
+    on_worker_start: Callback,
+    init_failure: InitFailure,
+    init_log: InitLog,
+    on_worker_stop: Callback,
+    on_complete_timed: Callback,
+    on_finally: Callback,
+    // Pass each job's metadata to on_complete_timed and on_finally
+    callback_metadata: bool,
+    submit_middleware: Callback,
+    context_getter: Callback,
+    context_setter: Callback,
+    on_scale: Callback,
+    on_queue_empty: Callback,
+    soft_capacity: Option<usize>,
+    on_watermark: Callback,
+    backlog_alarm: Option<(usize, Duration)>,
+    on_backlog_alarm: Callback,
+    // Whether the backlog alarm is raised, and since when the queue has
+    // been on the other side of the threshold
+    alarm_state: Mutex<(bool, Option<Instant>)>,
+    on_full: Callback,
+    on_not_full: Callback,
+    // Whether on_full was the last to fire
+    full: AtomicBool,
+    // Messages the queue holds before the overflow policy applies, the
+    // total of `slots`
+    capacity: AtomicUsize,
+    custom_queue: Callback,
+    // Ids of the jobs in custom_queue by the address of their callable, so
+    // the callable get() returns leads back to its job
+    custom_ids: Mutex<HashMap<usize, VecDeque<u64>>>,
+    // Whether the queue is above soft_capacity
+    above_soft: AtomicBool,
+    // Set when a job message is queued and cleared by the worker that takes
+    // the last one, so on_queue_empty fires once per backlog
+    backlog: AtomicBool,
+    error_log: Option<Arc<ErrorLog>>,
+    // Nanoseconds workers spent waiting to acquire the GIL for jobs
+    gil_wait_nanos: AtomicU64,
+    // What the most recent failed job raised, for last_error()
+    last_error: Mutex<Option<PyObject>>,
+    // Completion channel for results(), opened by its first call and closed
+    // at shutdown; the receiver is kept to hand out clones
+    results: Mutex<Option<(Sender<Outcome>, Receiver<Outcome>)>>,
+    // Feeds the callback thread, if there is one, until shutdown
+    notifications: Mutex<Option<Sender<Notification>>>,
+    // Set with ordered_callbacks, which also always uses the callback thread
+    reorder: Option<Mutex<ReorderBuffer>>,
+    interpreter: Option<Interpreter>,
+    // Exponential moving averages of job exec_time and CPU time in seconds,
+    // stored as f64 bits; zero until a job has run
+    avg_exec_time: AtomicU64,
+    avg_cpu_time: AtomicU64,
+    // Counts of job messages by how long they sat in the channel, bucketed
+    // by DISPATCH_BUCKETS_NANOS
+    dispatch_latency: [AtomicU64; DISPATCH_BUCKETS_NANOS.len() + 1],
+}
+
+impl Shared {
+    // Wait for room under max_in_flight, then count a job as running until
+    // the guard is dropped
+    fn start_in_flight(&self) -> InFlight<'_> {
+        let permit = self.in_flight_limit.as_ref().map(|limit| limit.acquire(1));
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight {
+            shared: self,
+            _permit: permit,
+        }
+    }
+
+    // Acquire the GIL to run a job, recording how long that took
+    fn with_job_gil<R>(&self, f: impl FnOnce(Python<'_>) -> R) -> R {
+        let requested = Instant::now();
+        Python::with_gil(|py| {
+            let waited = requested.elapsed().as_nanos() as u64;
+            self.gil_wait_nanos.fetch_add(waited, Ordering::Relaxed);
+            f(py)
+        })
+    }
+
+    // Send a finished job's outcome to results() iterators, if any were made
+    fn publish_result(&self, py: Python<'_>, job_id: u64, result: &PyResult<PyObject>) {
+        if let Some((sender, _)) = &*self.results.lock().unwrap() {
+            let value = match result {
+                Ok(value) => value.clone_ref(py),
+                Err(e) => e.value(py).into(),
+            };
+            let _ = sender.send((job_id, value));
+        }
+    }
+
+    // The exception as raised, with its traceback attached so it can be
+    // inspected after the job's frames are gone
+    fn record_last_error(&self, py: Python<'_>, error: &PyErr) {
+        let value = error.value(py);
+        if let Some(traceback) = error.traceback(py) {
+            if value.getattr("__traceback__").map_or(true, |current| current.is_none()) {
+                let _ = value.setattr("__traceback__", traceback);
+            }
+        }
+        let previous = self.last_error.lock().unwrap().replace(value.into());
+        // Dropped outside the lock, since a decref can run Python
+        drop(previous);
+    }
+
+    fn results(&self) -> Receiver<Outcome> {
+        let mut results = self.results.lock().unwrap();
+        let (_, receiver) = results.get_or_insert_with(unbounded);
+        receiver.clone()
+    }
+
+    fn record_exec_time(&self, exec_time: f64, cpu_time: Option<f64>) {
+        record_average(&self.avg_exec_time, exec_time);
+        if let Some(cpu_time) = cpu_time {
+            record_average(&self.avg_cpu_time, cpu_time);
+        }
+    }
+
+    fn record_dispatch(&self, latency: Duration) {
+        let nanos = latency.as_nanos() as u64;
+        let bucket = DISPATCH_BUCKETS_NANOS
+            .iter()
+            .position(|&bound| nanos <= bound)
+            .unwrap_or(DISPATCH_BUCKETS_NANOS.len());
+        self.dispatch_latency[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // How long a worker of this kind may idle before it retires, if ever
+    fn idle_limit(&self, kind: WorkerKind) -> Option<Duration> {
+        match kind {
+            WorkerKind::Core => self.idle_timeout,
+            WorkerKind::Burst(ttl) => Some(ttl),
+        }
+    }
+
+    // Wait for a message, waking by the housekeeping interval at the latest
+    fn recv(&self, kind: WorkerKind, idle_since: Instant) -> Result<Envelope, RecvTimeoutError> {
+        let mut timeout = self.housekeeping_interval;
+        if let Some(limit) = self.idle_limit(kind) {
+            timeout = timeout.min(limit.saturating_sub(idle_since.elapsed()));
+        }
+
+        // Wake up in time to put back the next delayed job
+        let next_due = self.delayed.lock().unwrap().iter().map(|(due, _)| *due).min();
+        if let Some(due) = next_due {
+            timeout = timeout.min(due.saturating_duration_since(Instant::now()));
+        }
+
+        self.receiver.recv_timeout(timeout)
+    }
+
+    // Run up to `limit` more add_job jobs that are already waiting, for a
+    // worker that holds the GIL after running one. Stops at the first other
+    // kind of message, which goes back on the queue with its slot, or once
+    // the worker is held.
+    fn run_coalesced(&self, worker_id: usize, limit: usize) {
+        for _ in 0..limit {
+            if self.is_held(worker_id) {
+                return;
+            }
+            let (job_id, sent) = match self.receiver.try_recv() {
+                Ok((Message::NewJob(job_id), sent)) => (job_id, sent),
+                Ok(envelope) => {
+                    let _ = self.resend.send(envelope);
+                    return;
+                }
+                Err(_) => return,
+            };
+            self.record_dispatch(sent.elapsed());
+            self.check_watermark();
+            self.check_queue_empty();
+
+            self.beat(worker_id);
+            if let Some(job) = self.take_new_job(job_id, true) {
+                run_job(worker_id, job, self);
+            }
+        }
+    }
+
+    // The id and reschedule count of the job running on this thread, if it
+    // is one of this pool's workers
+    fn current_job(&self) -> Option<(u64, usize)> {
+        let on_worker = self.worker_slots.lock().unwrap().contains_key(&thread::current().id());
+        CURRENT_JOB.with(Cell::get).filter(|_| on_worker)
+    }
+
+    fn record_event(&self, job_id: u64, event: &'static str) {
+        if let Some(recorder) = &self.flight_recorder {
+            recorder.record(job_id, event);
+        }
+    }
+
+    // The housekeeping that runs each time a worker wakes between jobs
+    fn beat(&self, worker_id: usize) {
+        let mut status = self.worker_status.lock().unwrap();
+        let status = status.entry(worker_id).or_insert_with(|| WorkerStatus {
+            heartbeat: Instant::now(),
+            current: None,
+            jobs_executed: 0,
+            rss_growth: 0,
+            quiesced: false,
+            slow_reported: false,
+            exited: false,
+        });
+        status.heartbeat = Instant::now();
+    }
+
+    // Hold a quiesced worker here until it is unquiesced, refreshing its
+    // heartbeat so it doesn't look stuck. Returns whether it parked.
+    fn park_if_quiesced(&self, worker_id: usize) -> bool {
+        let mut status = self.worker_status.lock().unwrap();
+        let mut parked = false;
+        while status.get(&worker_id).is_some_and(|status| status.quiesced) {
+            parked = true;
+            status = self
+                .unquiesced
+                .wait_timeout(status, self.housekeeping_interval)
+                .unwrap()
+                .0;
+            if let Some(status) = status.get_mut(&worker_id) {
+                status.heartbeat = Instant::now();
+            }
+        }
+        parked
+    }
+
+    // Add the RSS growth since `before` to the worker's total, returning
+    // whether that is now over max_worker_rss
+    fn add_rss_growth(&self, worker_id: usize, before: usize) -> bool {
+        let (Some(limit), Some(after)) = (self.max_worker_rss, process_rss()) else {
+            return false;
+        };
+        let mut status = self.worker_status.lock().unwrap();
+        let Some(status) = status.get_mut(&worker_id) else { return false };
+        status.rss_growth += after.saturating_sub(before);
+        status.rss_growth > limit
+    }
+
+    // Hand a leaving worker's live slot to a replacement, unless the pool
+    // is shutting down
+    fn request_replacement(&self) -> bool {
+        let requests = self.recycle_requests.lock().unwrap();
+        requests.as_ref().is_some_and(|requests| requests.send(()).is_ok())
+    }
+
+    // Whether the worker should leave messages alone, being quiesced or
+    // paused with the rest of the process
+    fn is_held(&self, worker_id: usize) -> bool {
+        if self.is_paused() {
+            return true;
+        }
+        let status = self.worker_status.lock().unwrap();
+        status.get(&worker_id).is_some_and(|status| status.quiesced)
+    }
+
+    fn is_paused(&self) -> bool {
+        PAUSED.load(Ordering::SeqCst) && !self.stopping.load(Ordering::SeqCst)
+    }
+
+    // Hold the worker here while pause_all() is in effect, refreshing its
+    // heartbeat. Returns whether it waited.
+    fn wait_if_paused(&self, worker_id: usize) -> bool {
+        let mut guard = PAUSE_LOCK.lock().unwrap();
+        let mut waited = false;
+        while self.is_paused() {
+            waited = true;
+            guard = RESUMED.wait_timeout(guard, self.housekeeping_interval).unwrap().0;
+            self.beat(worker_id);
+        }
+        waited
+    }
+
+    // Set or clear a live worker's quiesced flag, returning false if there
+    // is no such worker
+    fn set_quiesced(&self, worker_id: usize, quiesced: bool) -> bool {
+        let mut status = self.worker_status.lock().unwrap();
+        match status.get_mut(&worker_id) {
+            Some(status) if !status.exited => {
+                status.quiesced = quiesced;
+                self.unquiesced.notify_all();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Run context_getter on the submitting thread. Under allow_threads this
+    // takes the GIL back with the same thread state, so the caller's
+    // contextvars are the ones seen.
+    fn capture_context(&self) -> Option<PyObject> {
+        if !self.context_getter.is_set() {
+            return None;
+        }
+        Python::with_gil(|py| {
+            let getter = self.context_getter.get(py)?;
+            match getter.call0(py) {
+                Ok(context) => Some(context),
+                Err(e) => {
+                    eprintln!("context_getter error: {:?}", e);
+                    None
+                }
+            }
+        })
+    }
+
+    // Pass the job's captured context to context_setter before it runs,
+    // returning what the setter gave back to undo it afterwards
+    fn enter_context(&self, py: Python<'_>, worker_id: usize, job: &Job) -> Option<PyObject> {
+        let context = job.context.as_ref()?;
+        let setter = self.context_setter.get(py)?;
+        match setter.call1(py, (context,)) {
+            Ok(restore) if restore.is_none(py) => None,
+            Ok(restore) => Some(restore),
+            Err(e) => {
+                eprintln!("Worker {}: context_setter error for job {}: {:?}", worker_id, job.id, e);
+                None
+            }
+        }
+    }
+
+    // Show the worker running a job, or done with it when None
+    fn set_current(&self, worker_id: usize, job: Option<(Option<u64>, PyObject)>) {
+        // Dropped after the lock is released; the caller holds the GIL
+        let _finished = {
+            let mut status = self.worker_status.lock().unwrap();
+            let Some(status) = status.get_mut(&worker_id) else { return };
+            match job {
+                Some((id, func)) => {
+                    status.slow_reported = false;
+                    status.current.replace((id, func, SystemTime::now()))
+                }
+                None => {
+                    status.jobs_executed += 1;
+                    status.current.take()
+                }
+            }
+        };
+    }
+
+    // Queue the job again, straight away or once the delay has passed. It
+    // keeps its id and still counts as outstanding.
+    fn reschedule(&self, mut job: Job, delay: Duration) {
+        job.reschedules += 1;
+        self.record_event(job.id, "rescheduled");
+        self.counters.lock().unwrap().record("rescheduled");
+        self.requeue_after(job, delay);
+    }
+
+    // Queue the job by id, without a queue slot, once the delay has passed
+    fn requeue_after(&self, job: Job, delay: Duration) {
+        let id = job.id;
+        self.queue_job(job);
+        if delay.is_zero() {
+            let _ = self.resend.send((Message::Requeued(id), Instant::now()));
+        } else {
+            self.delayed.lock().unwrap().push((Instant::now() + delay, id));
+        }
+    }
+
+    // How long the job must wait for its category's rate, if at all. It
+    // takes its token either way, so a job that waits is not throttled again.
+    fn throttle(&self, job: &mut Job) -> Option<Duration> {
+        let category = job.category.take()?;
+        let mut rates = self.category_rates.lock().unwrap();
+        Some(rates.get_mut(&category)?.take()).filter(|wait| !wait.is_zero())
+    }
+
+    // Put back the delayed jobs that are due, returning whether there were any
+    fn release_due(&self) -> bool {
+        let now = Instant::now();
+        let mut delayed = self.delayed.lock().unwrap();
+        let before = delayed.len();
+        delayed.retain(|&(due, id)| {
+            if due > now {
+                return true;
+            }
+            let _ = self.resend.send((Message::Requeued(id), Instant::now()));
+            false
+        });
+        delayed.len() < before
+    }
+
+    fn queue_job(&self, job: Job) {
+        self.queued.lock().unwrap().insert(job.id, job);
+    }
+
+    // None if the job was cleared by the GC after it was queued
+    fn take_queued(&self, id: u64) -> Option<Job> {
+        let mut job = self.queued.lock().unwrap().remove(&id)?;
+        self.release_slot(&mut job);
+        Some(job)
+    }
+
+    // take_queued for a job that holds a slot, which it keeps so that a
+    // job replacing it can take the slot over
+    fn take_slot_holder(&self, id: u64) -> Option<Job> {
+        let mut queued = self.queued.lock().unwrap();
+        queued.get(&id).filter(|job| job.slot)?;
+        queued.remove(&id)
+    }
+
+    // Give back the queue slot a job held while it waited, if it holds one
+    fn release_slot(&self, job: &mut Job) {
+        if std::mem::take(&mut job.slot) {
+            self.slots.release_many(1);
+            self.check_full();
+        }
+    }
+
+    fn queue_new_job(&self, job: Job) {
+        if let Some(stack) = &self.lifo {
+            stack.lock().unwrap().push(job.id);
+        }
+        self.queue_job(job);
+    }
+
+    // Put the job's callable on the custom queue, keeping the job itself
+    // in the queued table
+    fn put_custom(&self, job: Job) -> PyResult<()> {
+        let id = job.id;
+        let key = job.func.as_ptr() as usize;
+        Python::with_gil(|py| {
+            let func = job.func.clone_ref(py);
+            // Registered before put(), since a worker may get() it straight away
+            self.queue_job(job);
+            self.custom_ids.lock().unwrap().entry(key).or_default().push_back(id);
+
+            let Some(queue) = self.custom_queue.get(py) else { return Ok(()) };
+            let result = queue.call_method1(py, "put", (func,)).map(drop);
+            if result.is_err() {
+                if let Some(ids) = self.custom_ids.lock().unwrap().get_mut(&key) {
+                    ids.retain(|&queued| queued != id);
+                }
+            }
+            result
+        })
+    }
+
+    // The job behind the callable the custom queue hands out next. None if
+    // the job was cancelled meanwhile, or the queue broke its contract.
+    fn get_custom(&self) -> Option<Job> {
+        let id = Python::with_gil(|py| {
+            let queue = self.custom_queue.get(py)?;
+            let func = match queue.call_method0(py, "get") {
+                Ok(func) => func,
+                Err(e) => {
+                    eprintln!("Custom queue get() failed: {:?}", e);
+                    return None;
+                }
+            };
+
+            let key = func.as_ptr() as usize;
+            let mut ids = self.custom_ids.lock().unwrap();
+            let id = ids.get_mut(&key).and_then(VecDeque::pop_front);
+            if ids.get(&key).is_some_and(VecDeque::is_empty) {
+                ids.remove(&key);
+            }
+            if id.is_none() {
+                eprintln!("Custom queue get() returned an object that was never put()");
+            }
+            id
+        })?;
+        self.take_queued(id)
+    }
+
+    // The job a NewJob message stands for: the one it names, the one the
+    // custom queue hands out, or in LIFO mode the newest (or, for
+    // evictions, the oldest) waiting job
+    fn take_new_job(&self, id: u64, newest: bool) -> Option<Job> {
+        if self.custom_queue.is_set() {
+            return self.get_custom();
+        }
+        let id = match &self.lifo {
+            None => id,
+            Some(stack) => {
+                let mut stack = stack.lock().unwrap();
+                let id = if newest {
+                    stack.pop()
+                } else if stack.is_empty() {
+                    None
+                } else {
+                    Some(stack.remove(0))
+                };
+                id?
+            }
+        };
+        self.take_queued(id)
+    }
+
+    // Account for a job reaching a terminal status: take its weight off
+    // the load and record the status against its group, if it has one
+    fn settle(&self, job: &Job, status: &'static str) {
+        self.weighted_load.fetch_sub(job.weight, Ordering::SeqCst);
+        self.record_event(job.id, status);
+        match status {
+            "completed" => self.count_completed(),
+            _ => self.counters.lock().unwrap().record(status),
+        }
+        if let Some(key) = &job.idempotency_key {
+            self.release_key(key, job.id);
+        }
+        if let Some(key) = &job.replace_key {
+            let mut keys = self.replace_keys.lock().unwrap();
+            if keys.get(key) == Some(&job.id) {
+                keys.remove(key);
+            }
+        }
+
+        let Some(tag) = job.tag else { return };
+        let mut tallies = self.tallies.lock().unwrap();
+        let tally = tallies.entry(tag).or_default();
+        match status {
+            "completed" => tally.completed += 1,
+            "failed" => tally.failed += 1,
+            "cancelled" => tally.cancelled += 1,
+            _ => tally.dropped += 1,
+        }
+        tally.pending = tally.pending.saturating_sub(1);
+    }
+
+    // Settle a job taken out of the queued table before it ran, because a
+    // newer add_job_replace job for its key took its place or its group was
+    // cancelled
+    fn cancel_taken(&self, job: Job) {
+        Python::with_gil(|py| {
+            if let Some(future) = &job.future {
+                let _ = future.call_method0(py, "cancel");
+            }
+            job.signal(py);
+            self.notify_finally(py, &job, "cancelled");
+        });
+        self.settle(&job, "cancelled");
+        finish_job(self);
+    }
+
+    // Cancel the group's jobs still waiting in the queued table, returning
+    // how many were cancelled and how many of its jobs remain pending
+    fn cancel_group(&self, tag: u64) -> (usize, usize) {
+        let jobs: Vec<Job> = {
+            let mut queued = self.queued.lock().unwrap();
+            let ids: Vec<u64> = queued
+                .values()
+                .filter(|job| job.tag == Some(tag))
+                .map(|job| job.id)
+                .collect();
+            ids.iter().filter_map(|id| queued.remove(id)).collect()
+        };
+        let cancelled = jobs.len();
+        for mut job in jobs {
+            self.release_slot(&mut job);
+            self.cancel_taken(job);
+        }
+        let pending = self.tallies.lock().unwrap().get(&tag).map_or(0, |tally| tally.pending);
+        (cancelled, pending)
+    }
+
+    // Report a failed job to the error log, which groups failures by kind,
+    // or print the details straight away without one
+    fn log_failure(&self, kind: String, details: impl FnOnce() -> String) {
+        match &self.error_log {
+            Some(log) => log.record(kind),
+            None => eprintln!("{}", details()),
+        }
+    }
+
+    fn count_completed(&self) {
+        self.counters.lock().unwrap().record("completed");
+        self.completed.fetch_add(1, Ordering::SeqCst);
+        self.throughput.record();
+    }
+
+    // Let the key be submitted again, unless it already went to a newer job
+    fn release_key(&self, key: &str, job_id: u64) {
+        let mut keys = self.idempotency_keys.lock().unwrap();
+        if keys.get(key) == Some(&job_id) {
+            keys.remove(key);
+        }
+    }
+
+    // Returns what the callback returned, if it was set and succeeded
+    fn notify_worker_event(&self, callback: &Callback, worker_id: usize, event: &str) -> Option<PyObject> {
+        if !callback.is_set() {
+            return None;
+        }
+
+        Python::with_gil(|py| {
+            let callback = callback.get(py)?;
+            match callback.call1(py, (worker_id,)) {
+                Ok(value) => Some(value),
+                Err(e) => {
+                    eprintln!("Worker {}: {} callback error: {:?}", worker_id, event, e);
+                    None
+                }
+            }
+        })
+    }
+
+    // Run on_worker_start under the init_failure policy. Ok holds the
+    // worker's initial worker_local value; Err means the worker gave up.
+    fn init_worker(&self, worker_id: usize) -> Result<Option<PyObject>, String> {
+        let attempts = match self.init_failure {
+            InitFailure::Retry(retries) => retries + 1,
+            InitFailure::Log | InitFailure::Fail => 1,
+        };
+
+        let outcome = if !self.on_worker_start.is_set() {
+            Ok(None)
+        } else {
+            Python::with_gil(|py| {
+                let mut error = String::new();
+                let mut delay = INIT_RETRY_DELAY;
+                for attempt in 0..attempts {
+                    if attempt > 0 {
+                        py.allow_threads(|| thread::sleep(delay));
+                        delay *= 2;
+                    }
+                    // Cleared by the GC in the meantime
+                    let Some(callback) = self.on_worker_start.get(py) else { return Ok(None) };
+                    match callback.call1(py, (worker_id,)) {
+                        Ok(value) => return Ok(Some(value)),
+                        Err(e) => {
+                            eprintln!("Worker {}: on_worker_start callback error: {:?}", worker_id, e);
+                            error = format!("worker {}: {}", worker_id, e);
+                        }
+                    }
+                }
+                match self.init_failure {
+                    InitFailure::Log => Ok(None),
+                    InitFailure::Retry(_) | InitFailure::Fail => Err(error),
+                }
+            })
+        };
+
+        self.init_log.record(outcome.as_ref().err().cloned());
+        outcome
+    }
+
+    // Report an autoscaling change through on_scale, or the log without one
+    fn notify_scale(&self, old: usize, new: usize, queue_len: usize, busy: usize) {
+        if !self.on_scale.is_set() {
+            eprintln!(
+                "Autoscale: {} -> {} workers (queue length {}, busy workers {})",
+                old, new, queue_len, busy
+            );
+            return;
+        }
+
+        Python::with_gil(|py| {
+            if let Some(callback) = self.on_scale.get(py) {
+                if let Err(e) = callback.call1(py, (old, new, queue_len, busy)) {
+                    eprintln!("on_scale callback error: {:?}", e);
+                }
+            }
+        });
+    }
+
+    // Jobs waiting for a worker, in whichever queue they wait. Unlike the
+    // channel's length this leaves out the stale messages of cancelled or
+    // replaced jobs, control messages and jobs waiting out a delay.
+    fn queue_len(&self) -> usize {
+        let queued = {
+            let queued = self.queued.lock().unwrap();
+            let delayed = self.delayed.lock().unwrap();
+            let waiting = delayed.iter().filter(|(_, id)| queued.contains_key(id)).count();
+            queued.len() - waiting
+        };
+        queued
+            + self.groups.len()
+            + self.keyed.len()
+            + self.weighted.len()
+            + self.bare_messages.load(Ordering::SeqCst)
+    }
+
+    // Report the queue crossing soft_capacity, once per crossing
+    fn check_watermark(&self) {
+        let Some(soft) = self.soft_capacity else { return };
+        let above = self.queue_len() > soft;
+        if self.above_soft.swap(above, Ordering::SeqCst) == above {
+            return;
+        }
+
+        let level = if above { "high" } else { "low" };
+        if !self.on_watermark.is_set() {
+            if above {
+                eprintln!("Warning: queue length exceeds soft capacity ({})", soft);
+            }
+            return;
+        }
+
+        Python::with_gil(|py| {
+            if let Some(callback) = self.on_watermark.get(py) {
+                if let Err(e) = callback.call1(py, (level,)) {
+                    eprintln!("on_watermark callback error: {:?}", e);
+                }
+            }
+        });
+    }
+
+    // Raise or clear the backlog alarm once the queue has stayed on the
+    // other side of the threshold for the whole duration
+    fn check_backlog(&self) {
+        let Some((threshold, duration)) = self.backlog_alarm else { return };
+        let above = self.queue_len() > threshold;
+        {
+            let mut state = self.alarm_state.lock().unwrap();
+            let (raised, since) = &mut *state;
+            if above == *raised {
+                *since = None;
+                return;
+            }
+            if since.get_or_insert_with(Instant::now).elapsed() < duration {
+                return;
+            }
+            *raised = above;
+            *since = None;
+        }
+
+        let state = if above { "raised" } else { "cleared" };
+        if !self.on_backlog_alarm.is_set() {
+            if above {
+                eprintln!(
+                    "Warning: queue length has exceeded {} for {:?}",
+                    threshold, duration
+                );
+            }
+            return;
+        }
+
+        Python::with_gil(|py| {
+            if let Some(callback) = self.on_backlog_alarm.get(py) {
+                if let Err(e) = callback.call1(py, (state,)) {
+                    eprintln!("on_backlog_alarm callback error: {:?}", e);
+                }
+            }
+        });
+    }
+
+    // Report the queue filling up as the last free slot is taken, then
+    // recovering once a tenth of it is free again, so a queue hovering at
+    // capacity does not report every slot that comes and goes
+    fn check_full(&self) {
+        if !self.on_full.is_set() && !self.on_not_full.is_set() {
+            return;
+        }
+        let available = self.slots.available();
+        let full = if self.full.load(Ordering::SeqCst) {
+            available < (self.capacity.load(Ordering::SeqCst) / 10).max(1)
+        } else {
+            available == 0
+        };
+        if self.full.swap(full, Ordering::SeqCst) == full {
+            return;
+        }
+
+        let (name, callback) = if full {
+            ("on_full", &self.on_full)
+        } else {
+            ("on_not_full", &self.on_not_full)
+        };
+        Python::with_gil(|py| {
+            if let Some(callback) = callback.get(py) {
+                if let Err(e) = callback.call0(py) {
+                    eprintln!("{} callback error: {:?}", name, e);
+                }
+            }
+        });
+    }
+
+    // Report each running job that has passed slow_job_threshold, once per
+    // job. The scan itself doesn't need the GIL, which is only taken when
+    // there is something to report.
+    fn check_slow_jobs(&self) {
+        let Some(threshold) = self.slow_job_threshold else { return };
+        let is_slow = |status: &WorkerStatus| {
+            !status.slow_reported
+                && status.current.as_ref().is_some_and(|(_, _, since)| {
+                    since.elapsed().unwrap_or_default() >= threshold
+                })
+        };
+        if !self.worker_status.lock().unwrap().values().any(is_slow) {
+            return;
+        }
+
+        Python::with_gil(|py| {
+            let slow: Vec<(Option<u64>, PyObject, f64)> = {
+                let mut statuses = self.worker_status.lock().unwrap();
+                statuses
+                    .values_mut()
+                    .filter(|status| is_slow(status))
+                    .filter_map(|status| {
+                        status.slow_reported = true;
+                        let (id, func, since) = status.current.as_ref()?;
+                        Some((*id, func.clone_ref(py), since.elapsed().unwrap_or_default().as_secs_f64()))
+                    })
+                    .collect()
+            };
+
+            // Names are looked up after the lock is released, since that
+            // can run arbitrary Python
+            for (id, func, elapsed) in slow {
+                let name = job_name(py, &func);
+                match self.on_slow_job.get(py) {
+                    Some(callback) => {
+                        if let Err(e) = callback.call1(py, (id, name, elapsed)) {
+                            eprintln!("on_slow_job callback error: {:?}", e);
+                        }
+                    }
+                    None => eprintln!(
+                        "Warning: job {} ({}) has been running for {:.1}s",
+                        id.map_or_else(|| "<map item>".to_string(), |id| id.to_string()),
+                        name,
+                        elapsed
+                    ),
+                }
+            }
+        });
+    }
+
+    // Fire on_queue_empty if this receive drained a backlog
+    fn check_queue_empty(&self) {
+        if !self.on_queue_empty.is_set()
+            || !self.receiver.is_empty()
+            || !self.backlog.swap(false, Ordering::SeqCst)
+        {
+            return;
+        }
+
+        Python::with_gil(|py| {
+            if let Some(callback) = self.on_queue_empty.get(py) {
+                if let Err(e) = callback.call0(py) {
+                    eprintln!("on_queue_empty callback error: {:?}", e);
+                }
+            }
+        });
+    }
+
+    fn notify_finally(&self, py: Python<'_>, job: &Job, status: &'static str) {
+        let job_id = job.id;
+        // Every settled job comes through here, which is what ends its turn
+        if let Some(reorder) = &self.reorder {
+            let notification =
+                self.on_finally.is_set().then(|| Notification::Finally(job_id, status, job.metadata(py)));
+            self.hold(reorder, job_id, notification, true);
+            return;
+        }
+        if !self.on_finally.is_set() {
+            return;
+        }
+        if let Some(notifications) = &*self.notifications.lock().unwrap() {
+            let _ = notifications.send(Notification::Finally(job_id, status, job.metadata(py)));
+            return;
+        }
+        self.call_finally(py, job_id, status, job.metadata(py));
+    }
+
+    fn call_finally(&self, py: Python<'_>, job_id: u64, status: &str, metadata: PyObject) {
+        if let Some(callback) = self.on_finally.get(py) {
+            let result = if self.callback_metadata {
+                callback.call1(py, (job_id, status, metadata))
+            } else {
+                callback.call1(py, (job_id, status))
+            };
+            if let Err(e) = result {
+                eprintln!("on_finally callback error for job {}: {:?}", job_id, e);
+            }
+        }
+    }
+
+    fn notify_completed(&self, py: Python<'_>, worker_id: usize, job: &Job, value: PyObject, exec_time: f64) {
+        let job_id = job.id;
+        if !self.on_complete_timed.is_set() {
+            return;
+        }
+        let metadata = job.metadata(py);
+        if let Some(reorder) = &self.reorder {
+            let notification = Notification::Completed(job_id, value, exec_time, metadata);
+            self.hold(reorder, job_id, Some(notification), false);
+            return;
+        }
+        if let Some(notifications) = &*self.notifications.lock().unwrap() {
+            let _ = notifications.send(Notification::Completed(job_id, value, exec_time, metadata));
+            return;
+        }
+        if let Err(e) = self.call_completed(py, job_id, value, exec_time, metadata) {
+            eprintln!(
+                "Worker {}: on_complete_timed callback error for job {}: {:?}",
+                worker_id, job_id, e
+            );
+        }
+    }
+
+    fn call_completed(
+        &self,
+        py: Python<'_>,
+        job_id: u64,
+        value: PyObject,
+        exec_time: f64,
+        metadata: PyObject,
+    ) -> PyResult<()> {
+        match self.on_complete_timed.get(py) {
+            Some(callback) if self.callback_metadata => {
+                callback.call1(py, (job_id, value, exec_time, metadata)).map(drop)
+            }
+            Some(callback) => callback.call1(py, (job_id, value, exec_time)).map(drop),
+            None => Ok(()),
+        }
+    }
+
+    // Take a job into the submission order for ordered_callbacks
+    fn register_ordered(&self, job_id: u64) {
+        if let Some(reorder) = &self.reorder {
+            reorder.lock().unwrap().outstanding.insert(job_id);
+        }
+    }
+
+    // End the turn of a job that will never send notifications
+    fn release_ordered(&self, job_id: u64) {
+        if let Some(reorder) = &self.reorder {
+            self.hold(reorder, job_id, None, true);
+        }
+    }
+
+    // Hold the job's notification back. Once the job is finished, send on
+    // every finished job's notifications that no unfinished job comes
+    // before. Sent under the lock, so the callback thread sees them in order.
+    fn hold(&self, reorder: &Mutex<ReorderBuffer>, job_id: u64, notification: Option<Notification>, finished: bool) {
+        let mut buffer = reorder.lock().unwrap();
+        if let Some(notification) = notification {
+            buffer.held.entry(job_id).or_default().push(notification);
+        }
+        if !finished {
+            return;
+        }
+        buffer.outstanding.remove(&job_id);
+        buffer.finished.insert(job_id);
+
+        let notifications = self.notifications.lock().unwrap();
+        while let Some(&first) = buffer.finished.first() {
+            if buffer.outstanding.first().is_some_and(|&earliest| earliest < first) {
+                break;
+            }
+            buffer.finished.remove(&first);
+            for notification in buffer.held.remove(&first).unwrap_or_default() {
+                if let Some(notifications) = &*notifications {
+                    let _ = notifications.send(notification);
+                }
+            }
+        }
+    }
+
+    // The callback thread: call the callbacks in the order jobs finished,
+    // until shutdown closes the channel
+    fn run_notifications(&self, receiver: Receiver<Notification>) {
+        while let Ok(notification) = receiver.recv() {
+            Python::with_gil(|py| match notification {
+                Notification::Completed(job_id, value, exec_time, metadata) => {
+                    if let Err(e) = self.call_completed(py, job_id, value, exec_time, metadata) {
+                        eprintln!("on_complete_timed callback error for job {}: {:?}", job_id, e);
+                    }
+                }
+                Notification::Finally(job_id, status, metadata) => {
+                    self.call_finally(py, job_id, status, metadata)
+                }
+            });
+        }
+    }
+
+    // Visit every Python object held on behalf of the pool's owner
+    fn traverse(&self, visit: &PyVisit<'_>) -> Result<(), PyTraverseError> {
+        for callback in [
+            &self.on_worker_start,
+            &self.on_worker_stop,
+            &self.on_complete_timed,
+            &self.on_finally,
+            &self.submit_middleware,
+            &self.on_scale,
+            &self.on_queue_empty,
+            &self.on_watermark,
+            &self.on_backlog_alarm,
+            &self.on_full,
+            &self.on_not_full,
+            &self.on_slow_job,
+            &self.custom_queue,
+            &self.on_log,
+            &self.context_getter,
+            &self.context_setter,
+        ] {
+            callback.traverse(visit)?;
+        }
+
+        if let Ok(queued) = self.queued.try_lock() {
+            for job in queued.values() {
+                job.traverse(visit)?;
+            }
+        }
+
+        if let Ok(slots) = self.worker_slots.try_lock() {
+            for value in slots.values().flatten() {
+                visit.call(value)?;
+            }
+        }
+
+        for jobs in [&self.main_jobs, &self.shutdown_jobs] {
+            if let Ok(jobs) = jobs.try_lock() {
+                for job in jobs.iter() {
+                    job.traverse(visit)?;
+                }
+            }
+        }
+
+        // Its traceback holds the job's frames, which may hold the pool
+        if let Ok(last_error) = self.last_error.try_lock() {
+            if let Some(error) = &*last_error {
+                visit.call(error)?;
+            }
+        }
+
+        self.groups.traverse(visit)?;
+        self.keyed.traverse(visit)?;
+        self.weighted.traverse(visit)
+    }
+
+    // Remove every job still waiting to run, cancelling its future and
+    // settling it here; the jobs' messages become no-ops. The jobs are
+    // returned in case the caller wants their callables.
+    fn cancel_queued(&self) -> Vec<Job> {
+        let mut jobs: Vec<Job> = self.queued.lock().unwrap().drain().map(|(_, job)| job).collect();
+        for job in &mut jobs {
+            self.release_slot(job);
+        }
+        self.delayed.lock().unwrap().clear();
+        if let Some(stack) = &self.lifo {
+            stack.lock().unwrap().clear();
+        }
+        jobs.extend(self.groups.take_all());
+        jobs.extend(self.keyed.take_all());
+        jobs.extend(self.weighted.take_all());
+
+        Python::with_gil(|py| {
+            for job in &jobs {
+                if let Some(future) = &job.future {
+                    let _ = future.call_method0(py, "cancel");
+                }
+                job.signal(py);
+                self.notify_finally(py, job, "cancelled");
+            }
+        });
+
+        for job in &jobs {
+            self.settle(job, "cancelled");
+            finish_job(self);
+        }
+        jobs
+    }
+
+    // Cancel the add_shutdown_job jobs that have not started, returning how
+    // many there were. They were never counted in, so there is nothing to
+    // settle.
+    fn cancel_shutdown_jobs(&self, py: Python<'_>) -> usize {
+        let jobs = std::mem::take(&mut *self.shutdown_jobs.lock().unwrap());
+        for job in &jobs {
+            if let Some(future) = &job.future {
+                let _ = future.call_method0(py, "cancel");
+            }
+            job.signal(py);
+            self.notify_finally(py, job, "cancelled");
+        }
+        jobs.len()
+    }
+
+    // Drop callbacks and queued jobs so the GC can break cycles through them
+    fn clear(&self) {
+        for callback in [
+            &self.on_worker_start,
+            &self.on_worker_stop,
+            &self.on_complete_timed,
+            &self.on_finally,
+            &self.submit_middleware,
+            &self.on_scale,
+            &self.on_queue_empty,
+            &self.on_watermark,
+            &self.on_backlog_alarm,
+            &self.on_full,
+            &self.on_not_full,
+            &self.on_slow_job,
+            &self.custom_queue,
+            &self.on_log,
+            &self.context_getter,
+            &self.context_setter,
+        ] {
+            callback.clear();
+        }
+
+        // Keep the workers' entries so WorkerLocal still recognises them
+        let values: Vec<PyObject> = self
+            .worker_slots
+            .lock()
+            .unwrap()
+            .values_mut()
+            .filter_map(Option::take)
+            .collect();
+        drop(values);
+
+        let main_jobs = std::mem::take(&mut *self.main_jobs.lock().unwrap());
+        drop(main_jobs);
+        let shutdown_jobs = std::mem::take(&mut *self.shutdown_jobs.lock().unwrap());
+        drop(shutdown_jobs);
+        let last_error = self.last_error.lock().unwrap().take();
+        drop(last_error);
+
+        self.cancel_queued();
+    }
+
+    // Take the key for this worker, or leave the job to the worker holding it
+    fn claim_key(&self, key: u64) -> bool {
+        let mut busy = self.busy_keys.lock().unwrap();
+        match busy.get_mut(&key) {
+            Some(owed) => {
+                *owed += 1;
+                false
+            }
+            None => {
+                busy.insert(key, 0);
+                true
+            }
+        }
+    }
+
+    // Whether the key's holder has another job to run; frees the key if not
+    fn next_for_key(&self, key: u64) -> bool {
+        let mut busy = self.busy_keys.lock().unwrap();
+        match busy.get_mut(&key) {
+            Some(owed) if *owed > 0 => {
+                *owed -= 1;
+                true
+            }
+            _ => {
+                busy.remove(&key);
+                false
+            }
+        }
+    }
+
+    // Claim a live worker slot for a new worker unless the pool is full
+    fn try_grow(&self, size: usize) -> bool {
+        self.live_workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |live| {
+                (live < size).then_some(live + 1)
+            })
+            .is_ok()
+    }
+
+    // Give up a live worker slot unless that would drop below the floor
+    fn try_retire(&self) -> bool {
+        self.live_workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |live| {
+                (live > self.min_workers).then_some(live - 1)
+            })
+            .is_ok()
+    }
+}
+
+// Worker struct to handle individual threads
+struct Worker {
+    id: usize,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    fn new(id: usize, kind: WorkerKind, shared: Arc<Shared>, stack_size: Option<usize>) -> io::Result<Worker> {
+        let mut builder = thread::Builder::new();
+        if let Some(stack_size) = stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+
+        // Create a new thread that will process jobs
+        let thread = builder.spawn(move || {
+            // Declared first so it goes last, after every use of Python
+            let _interpreter = shared.interpreter.map(Interpreter::enter);
+            // Whatever the start callback returns seeds this worker's WorkerLocal
+            let value = match shared.init_worker(id) {
+                Ok(value) => value,
+                Err(_) => {
+                    println!("Worker {}: Failed to start, exiting", id);
+                    let counter = match kind {
+                        WorkerKind::Core => &shared.live_workers,
+                        WorkerKind::Burst(_) => &shared.burst_workers,
+                    };
+                    counter.fetch_sub(1, Ordering::SeqCst);
+                    return;
+                }
+            };
+            shared.worker_slots.lock().unwrap().insert(thread::current().id(), value);
+
+            let mut retired = false;
+            let mut idle_since = Instant::now();
+            loop {
+                shared.release_due();
+                shared.beat(id);
+                shared.check_backlog();
+                let parked = shared.park_if_quiesced(id);
+                let paused = shared.wait_if_paused(id);
+                if parked || paused {
+                    idle_since = Instant::now();
+                }
+
+                // Get a message from the channel
+                shared.idle_workers.fetch_add(1, Ordering::SeqCst);
+                let received = shared.recv(kind, idle_since);
+                shared.idle_workers.fetch_sub(1, Ordering::SeqCst);
+
+                let message = match received {
+                    // Quiesced or paused while waiting; the message keeps
+                    // its slot and goes back for another worker
+                    Ok(envelope) if shared.is_held(id) => {
+                        let _ = shared.resend.send(envelope);
+                        continue;
+                    }
+                    Ok((msg, sent)) => {
+                        if matches!(msg, Message::Apply(_) | Message::Native(_)) {
+                            shared.bare_messages.fetch_sub(1, Ordering::SeqCst);
+                        }
+                        // A NewJob's slot goes back when its job is taken
+                        if msg.holds_slot() {
+                            shared.slots.release_many(1);
+                            shared.check_full();
+                        }
+                        if msg.holds_slot() || matches!(msg, Message::NewJob(_)) {
+                            shared.record_dispatch(sent.elapsed());
+                            shared.check_watermark();
+                            shared.check_queue_empty();
+                        }
+                        msg
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        // Woken for a delayed job or housekeeping rather
+                        // than by idling for the whole limit
+                        if shared.release_due() {
+                            continue;
+                        }
+                        let idled_out = shared
+                            .idle_limit(kind)
+                            .is_some_and(|limit| idle_since.elapsed() >= limit);
+                        if !idled_out {
+                            continue;
+                        }
+                        let retire = match kind {
+                            WorkerKind::Core => shared.try_retire(),
+                            WorkerKind::Burst(_) => true,
+                        };
+                        if retire {
+                            println!("Worker {}: Idle timeout reached, retiring", id);
+                            retired = true;
+                            break;
+                        }
+                        // Kept to stay at min_workers; idle for another full limit
+                        idle_since = Instant::now();
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        println!("Worker {}: Channel closed, exiting", id);
+                        break;
+                    }
+                };
+
+                let rss_before = shared.max_worker_rss.and_then(|_| process_rss());
+                match message {
+                    Message::NewJob(job_id) => {
+                        if let Some(job) = shared.take_new_job(job_id, true) {
+                            match shared.coalesce {
+                                // The jobs' own GIL acquisitions nest in this one
+                                Some(limit) => shared.with_job_gil(|_| {
+                                    run_job(id, job, &shared);
+                                    shared.run_coalesced(id, limit - 1);
+                                }),
+                                None => run_job(id, job, &shared),
+                            }
+                        }
+                    }
+                    Message::LimitedJob(job_id, semaphore, weight) => {
+                        run_limited(id, job_id, &semaphore, weight, &shared);
+                    }
+                    Message::Requeued(job_id) => {
+                        // Gone if it was cancelled while waiting
+                        if let Some(job) = shared.take_queued(job_id) {
+                            run_routed(id, job, &shared);
+                        }
+                    }
+                    Message::Apply(apply) => {
+                        let running = shared.start_in_flight();
+                        let result = shared.with_job_gil(|py| {
+                            shared.set_current(id, Some((None, apply.func.clone_ref(py))));
+                            let result = apply.func.call1(py, (apply.item,));
+                            shared.set_current(id, None);
+                            result
+                        });
+                        drop(running);
+                        match &result {
+                            Ok(_) => shared.count_completed(),
+                            Err(_) => shared.counters.lock().unwrap().record("failed"),
+                        }
+                        // The caller may have stopped listening after an error
+                        let _ = apply.results.send((apply.index, result));
+                        shared.weighted_load.fetch_sub(1, Ordering::SeqCst);
+                        finish_job(&shared);
+                    }
+                    Message::Native(job) => {
+                        let running = shared.start_in_flight();
+                        let outcome = panic::catch_unwind(AssertUnwindSafe(job));
+                        drop(running);
+                        match outcome {
+                            Ok(()) => shared.count_completed(),
+                            Err(payload) => {
+                                shared.counters.lock().unwrap().record("failed");
+                                let message = payload
+                                    .downcast_ref::<&str>()
+                                    .map(|message| message.to_string())
+                                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                                    .unwrap_or_else(|| "<non-string panic>".to_string());
+                                shared.log_failure("panic".to_string(), || {
+                                    format!("Worker {}: Native job panicked: {}", id, message)
+                                });
+                            }
+                        }
+                        shared.weighted_load.fetch_sub(1, Ordering::SeqCst);
+                        finish_job(&shared);
+                    }
+                    Message::GroupedJob(_) => run_group_turn(id, &shared),
+                    Message::WeightedJob(_) => run_weighted(id, &shared),
+                    Message::KeyedJob(key) => run_keyed(id, key, &shared),
+                    Message::Retire => {
+                        // A burst worker takes the retirement in place of a
+                        // core one, which keeps its slot
+                        if let WorkerKind::Burst(_) = kind {
+                            shared.live_workers.fetch_add(1, Ordering::SeqCst);
+                        }
+                        println!("Worker {}: Scaled down, retiring", id);
+                        retired = true;
+                        break;
+                    }
+                    Message::Terminate => {
+                        println!("Worker {} terminating", id);
+                        break;
+                    }
+                }
+                idle_since = Instant::now();
+
+                if rss_before.is_some_and(|before| shared.add_rss_growth(id, before)) {
+                    // A core worker's replacement takes over its live slot
+                    let replaced = match kind {
+                        WorkerKind::Core => shared.request_replacement(),
+                        WorkerKind::Burst(_) => true,
+                    };
+                    if replaced {
+                        println!("Worker {}: Over max_worker_rss, recycling", id);
+                        retired = matches!(kind, WorkerKind::Core);
+                        break;
+                    }
+                }
+            }
+            if let Some(status) = shared.worker_status.lock().unwrap().get_mut(&id) {
+                status.exited = true;
+            }
+
+            match kind {
+                // Retiring already gave up the live slot
+                WorkerKind::Core if retired => {}
+                WorkerKind::Core => {
+                    shared.live_workers.fetch_sub(1, Ordering::SeqCst);
+                }
+                WorkerKind::Burst(_) => {
+                    shared.burst_workers.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+
+            shared.notify_worker_event(&shared.on_worker_stop, id, "on_worker_stop");
+
+            // Dropping without the GIL defers the decref, which keeps this
+            // safe while the interpreter is finalizing
+            let value = shared.worker_slots.lock().unwrap().remove(&thread::current().id());
+            drop(value);
+        })?;
+
+        Ok(Worker {
+            id,
+            thread: Some(thread),
+        })
+    }
+}
+
+// The pool's worker threads, shared with the autoscaler so it can spawn them
+struct Workers {
+    threads: Mutex<Vec<Worker>>,
+    next_id: AtomicUsize,
+    shared: Arc<Shared>,
+    stack_size: Option<usize>,
+}
+
+impl Workers {
+    // Spawn a worker thread; the caller must already have counted it, and
+    // the count is given back if the thread cannot be created
+    fn spawn(&self, kind: WorkerKind) -> io::Result<()> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let mut threads = self.threads.lock().unwrap();
+
+        // Reap workers that retired after idling
+        threads.retain_mut(|worker| match &worker.thread {
+            Some(thread) if thread.is_finished() => {
+                let _ = worker.thread.take().map(thread::JoinHandle::join);
+                self.shared.worker_status.lock().unwrap().remove(&worker.id);
+                false
+            }
+            _ => true,
+        });
+
+        match Worker::new(id, kind, Arc::clone(&self.shared), self.stack_size) {
+            Ok(worker) => {
+                threads.push(worker);
+                Ok(())
+            }
+            Err(e) => {
+                let counter = match kind {
+                    WorkerKind::Core => &self.shared.live_workers,
+                    WorkerKind::Burst(_) => &self.shared.burst_workers,
+                };
+                counter.fetch_sub(1, Ordering::SeqCst);
+                Err(e)
+            }
+        }
+    }
+}
+
+fn run_job(worker_id: usize, job: Job, shared: &Shared) {
+    if call_job(worker_id, job, shared) {
+        finish_job(shared);
+    }
+}
+
+fn run_limited(worker_id: usize, job_id: u64, semaphore: &Semaphore, weight: usize, shared: &Shared) {
+    // Hand the permits back before the job counts as done
+    let permit = semaphore.acquire(weight);
+    if let Some(job) = shared.take_queued(job_id) {
+        let settled = call_job(worker_id, job, shared);
+        drop(permit);
+        if settled {
+            finish_job(shared);
+        }
+    }
+}
+
+fn run_group_turn(worker_id: usize, shared: &Shared) {
+    // The message only wakes a worker, which batches whichever group's turn
+    // it is. Other workers may have already batched every job.
+    if let Some(group) = shared.groups.next_turn() {
+        let mut batched = 0;
+        while batched < shared.groups.batch_limit {
+            match shared.groups.pop(group) {
+                Some(job) => run_job(worker_id, job, shared),
+                None => break,
+            }
+            batched += 1;
+        }
+    }
+}
+
+fn run_weighted(worker_id: usize, shared: &Shared) {
+    // Cancelled or cleared jobs leave nothing to run
+    if let Some(job) = shared.weighted.next() {
+        run_job(worker_id, job, shared);
+    }
+}
+
+fn run_keyed(worker_id: usize, key: u64, shared: &Shared) {
+    if shared.claim_key(key) {
+        loop {
+            // Cancelled or cleared jobs leave nothing to run
+            if let Some(job) = shared.keyed.pop(key) {
+                run_job(worker_id, job, shared);
+            }
+            if !shared.next_for_key(key) {
+                break;
+            }
+        }
+    }
+}
+
+// Run a job put back by reschedule() or its category's rate the way its
+// original message would have, so it keeps its limiter, key and group
+fn run_routed(worker_id: usize, job: Job, shared: &Shared) {
+    match &job.route {
+        Route::Plain => run_job(worker_id, job, shared),
+        Route::Limited(semaphore) => {
+            let (job_id, semaphore, weight) = (job.id, Arc::clone(semaphore), job.weight);
+            shared.queue_job(job);
+            run_limited(worker_id, job_id, &semaphore, weight, shared);
+        }
+        &Route::Grouped(group) => {
+            shared.groups.push(group, job);
+            run_group_turn(worker_id, shared);
+        }
+        &Route::Keyed(key) => {
+            shared.keyed.push_by_priority(key, job);
+            run_keyed(worker_id, key, shared);
+        }
+        &Route::Weighted(index) => {
+            shared.weighted.push(index, job);
+            run_weighted(worker_id, shared);
+        }
+    }
+}
+
+fn check_result_type(py: Python<'_>, job_id: u64, value: PyObject, result_type: &PyObject) -> PyResult<PyObject> {
+    if value.as_ref(py).is_instance(result_type.as_ref(py))? {
+        return Ok(value);
+    }
+    let expected = match result_type.getattr(py, "__name__") {
+        Ok(name) => name.to_string(),
+        Err(_) => result_type.as_ref(py).repr()?.to_string(),
+    };
+    Err(PyTypeError::new_err(format!(
+        "Job {} returned {}, expected {}",
+        job_id,
+        value.as_ref(py).get_type().name()?,
+        expected
+    )))
+}
+
+// Returns whether the job settled, which it didn't if it rescheduled itself
+fn call_job(worker_id: usize, mut job: Job, shared: &Shared) -> bool {
+    // Over its category's rate, so it waits out of the way like a delayed
+    // reschedule instead of holding the worker
+    if let Some(wait) = shared.throttle(&mut job) {
+        shared.requeue_after(job, wait);
+        return false;
+    }
+    // Out of its queue now, which a crossing back to "low" may wait on
+    shared.check_watermark();
+
+    // Idle workers park here with the job in hand until the cap has room
+    let _running = shared.start_in_flight();
+
+    // Execute the Python callback with GIL
+    shared.with_job_gil(|py| {
+        // A future cancelled while the job was queued means it is skipped.
+        // A rescheduled job's future is already running.
+        if let Some(future) = job.future.as_ref().filter(|_| job.reschedules == 0) {
+            match future
+                .call_method0(py, "set_running_or_notify_cancel")
+                .and_then(|running| running.is_true(py))
+            {
+                Ok(true) => {}
+                Ok(false) => {
+                    shared.settle(&job, "cancelled");
+                    job.signal(py);
+                    shared.notify_finally(py, &job, "cancelled");
+                    return true;
+                }
+                Err(e) => eprintln!("Worker {}: Job {} future error: {:?}", worker_id, job.id, e),
+            }
+        }
+
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!(
+            "job",
+            job_id = job.id,
+            name = %job_name(py, &job.func),
+            worker_id,
+        )
+        .entered();
+        #[cfg(feature = "tracing")]
+        tracing::debug!("job started");
+        shared.record_event(job.id, "started");
+
+        let started = Instant::now();
+        let cpu_started = thread_cpu_time();
+        CURRENT_JOB.with(|current| current.set(Some((job.id, job.reschedules))));
+        shared.set_current(worker_id, Some((Some(job.id), job.func.clone_ref(py))));
+        let restore = shared.enter_context(py, worker_id, &job);
+        let result = job.func.call0(py);
+        if let Some(restore) = restore {
+            if let Err(e) = restore.call0(py) {
+                eprintln!("Worker {}: context restore error for job {}: {:?}", worker_id, job.id, e);
+            }
+        }
+        shared.set_current(worker_id, None);
+        CURRENT_JOB.with(|current| current.set(None));
+        let reschedule = RESCHEDULE.with(Cell::take);
+        let exec_time = started.elapsed().as_secs_f64();
+        let cpu_time = cpu_started
+            .zip(thread_cpu_time())
+            .map(|(before, after)| after.saturating_sub(before).as_secs_f64());
+        shared.record_exec_time(exec_time, cpu_time);
+
+        // A job that raises after asking to be rescheduled just fails
+        if let (Some(delay), Ok(_)) = (reschedule, &result) {
+            shared.reschedule(job, delay);
+            return false;
+        }
+
+        // A transform that raises fails the job like the job raising would
+        let result = match &job.map_result {
+            Some(map_result) => result.and_then(|value| map_result.call1(py, (value,))),
+            None => result,
+        };
+
+        // Checked after map_result, since that is the value delivered
+        let result = match &job.result_type {
+            Some(result_type) => result.and_then(|value| check_result_type(py, job.id, value, result_type)),
+            None => result,
+        };
+
+        // A slot that no longer exists fails the job rather than the worker
+        let result = result.and_then(|value| match &job.into {
+            Some((results, index)) => {
+                results.as_ref(py).set_item(*index, value.clone_ref(py))?;
+                Ok(value)
+            }
+            None => Ok(value),
+        });
+
+        if let Some(future) = &job.future {
+            let resolved = match &result {
+                Ok(value) => future.call_method1(py, "set_result", (value,)),
+                Err(e) => future.call_method1(py, "set_exception", (e.value(py),)),
+            };
+            if let Err(e) = resolved {
+                eprintln!("Worker {}: Job {} future error: {:?}", worker_id, job.id, e);
+            }
+        }
+
+        shared.publish_result(py, job.id, &result);
+
+        let status = match result {
+            Ok(value) => {
+                shared.notify_completed(py, worker_id, &job, value, exec_time);
+                "completed"
+            }
+            Err(e) => {
+                shared.record_last_error(py, &e);
+                let kind = e.get_type(py).name().unwrap_or("<unknown>").to_string();
+                shared.log_failure(kind, || {
+                    format!(
+                        "Worker {}: Job {} ({}) failed:\n{}",
+                        worker_id,
+                        job.id,
+                        job_name(py, &job.func),
+                        format_traceback(py, &e, shared.traceback_depth)
+                    )
+                });
+                "failed"
+            }
+        };
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(status, exec_time, "job finished");
+
+        shared.settle(&job, status);
+        job.signal(py);
+        shared.notify_finally(py, &job, status);
+        true
+    })
+}
+
+// Fold a sample into an exponential moving average kept as f64 bits
+fn record_average(average: &AtomicU64, sample: f64) {
+    let _ = average.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+        let avg = f64::from_bits(bits);
+        let avg = if bits == 0 { sample } else { avg + EXEC_TIME_SMOOTHING * (sample - avg) };
+        Some(avg.to_bits())
+    });
+}
+
+// CPU time consumed by the calling thread, where the platform reports it
+#[cfg(unix)]
+fn thread_cpu_time() -> Option<Duration> {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    // SAFETY: ts is a valid timespec for clock_gettime to fill in
+    let rc = unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut ts) };
+    (rc == 0).then(|| Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32))
+}
+
+#[cfg(not(unix))]
+fn thread_cpu_time() -> Option<Duration> {
+    None
+}
+
+// The process's resident set size in bytes, from /proc
+#[cfg(target_os = "linux")]
+fn process_rss() -> Option<usize> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: usize = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf has no preconditions
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    usize::try_from(page_size).ok().map(|page_size| pages * page_size)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_rss() -> Option<usize> {
+    None
+}
+
+// The callable's qualified name, for labelling spans and listings
+fn job_name(py: Python<'_>, func: &PyObject) -> String {
+    func.getattr(py, "__qualname__")
+        .and_then(|name| name.extract(py))
+        .unwrap_or_else(|_| "<unnamed>".to_string())
+}
+
+// The exception as Python prints it, keeping the innermost `depth` frames
+// if given. Falls back to the debug form if the traceback module fails.
+fn format_traceback(py: Python<'_>, error: &PyErr, depth: Option<usize>) -> String {
+    let formatted = py.import("traceback").and_then(|traceback| {
+        let limit = depth.map(|depth| -(depth as isize));
+        let lines = traceback.call_method1(
+            "format_exception",
+            (error.get_type(py), error.value(py), error.traceback(py), limit),
+        )?;
+        lines.extract::<Vec<String>>()
+    });
+    match formatted {
+        Ok(lines) => lines.concat().trim_end().to_string(),
+        Err(_) => format!("{:?}", error),
+    }
+}
+
+fn finish_job(shared: &Shared) {
+    // Decrement job count after executing and wake any waiters
+    let mut count = shared.job_count.lock().unwrap();
+    *count = count.saturating_sub(1);
+    shared.settled.fetch_add(1, Ordering::SeqCst);
+    shared.job_done.notify_all();
+}
+
+// Thread pool implementation
+struct ThreadPool {
+    workers: Arc<Workers>,
+    sender: Option<Sender<Envelope>>,
+    is_running: Arc<AtomicBool>,
+    shared: Arc<Shared>,
+    size: usize,
+    lazy: bool,
+    next_job_id: AtomicU64,
+    overflow: OverflowPolicy,
+    // Jobs discarded by the overflow policy
+    dropped: AtomicUsize,
+    // Flushes the throttled error log
+    error_flusher: Option<thread::JoinHandle<()>>,
+    // Controller thread of an autoscaling pool
+    autoscaler: Option<(Arc<Autoscaler>, thread::JoinHandle<()>)>,
+    // Runs completion callbacks, with callback_thread
+    callback_thread: Option<thread::JoinHandle<()>>,
+    // Spawns replacements for workers recycled by max_worker_rss
+    recycler: Option<thread::JoinHandle<()>>,
+    // Scans for jobs over slow_job_threshold until its sender is dropped
+    slow_job_monitor: Option<(Sender<()>, thread::JoinHandle<()>)>,
+    picklable: bool,
+}
+
+impl ThreadPool {
+    fn new(config: PoolConfig) -> Result<ThreadPool, String> {
+        let size = config.size;
+        if size == 0 {
+            return Err("Thread pool size must be greater than 0".to_string());
+        }
+
+        if config.group_batch_limit == 0 {
+            return Err("Group batch limit must be greater than 0".to_string());
+        }
+
+        if config.min_workers > size {
+            return Err(format!(
+                "min_workers ({}) cannot exceed the pool size ({})",
+                config.min_workers, size
+            ));
+        }
+
+        // Get CPU count and calculate recommended maximum
+        let cpu_count = cpu_count();
+        let max_recommended = cpu_count * 2;
+
+        // Warn if thread count exceeds recommended maximum
+        if config.warn_oversubscribe && size > max_recommended {
+            eprintln!(
+                "Warning: Requested thread count ({}) exceeds recommended maximum ({}) for {} CPUs",
+                size, max_recommended, cpu_count
+            );
+        }
+
+        println!("Creating thread pool with {} workers (CPU count: {})", size, cpu_count);
+
+        // Create channel with appropriate buffer size
+        let capacity = config.hard_capacity.unwrap_or(size * 2); // Increase buffer size based on thread count
+        let (sender, receiver) = unbounded::<Envelope>();
+        let is_running = Arc::new(AtomicBool::new(true));
+        let error_log = config.error_log_window.map(|window| Arc::new(ErrorLog::new(window)));
+        let error_flusher = error_log.clone().map(|log| thread::spawn(move || log.run()));
+        let shared = Arc::new(Shared {
+            receiver,
+            slots: Arc::new(Semaphore::new(capacity)),
+            queued: Mutex::new(HashMap::new()),
+            lifo: config.lifo.then(|| Mutex::new(Vec::new())),
+            job_count: Mutex::new(0),
+            job_done: Condvar::new(),
+            worker_slots: Arc::new(Mutex::new(HashMap::new())),
+            weighted_load: AtomicUsize::new(0),
+            in_flight: AtomicUsize::new(0),
+            in_flight_limit: config.max_in_flight.map(Semaphore::new),
+            idempotency_keys: Mutex::new(HashMap::new()),
+            replace_keys: Mutex::new(HashMap::new()),
+            tallies: Mutex::new(HashMap::new()),
+            counters: Mutex::new(JobCounters::default()),
+            submitted: AtomicU64::new(0),
+            settled: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+            throughput: Throughput::new(),
+            groups: GroupQueues::new(config.group_batch_limit, None),
+            keyed: GroupQueues::new(config.group_batch_limit, config.max_overtakes),
+            weighted: WeightedQueues::new(config.sub_queues),
+            bare_messages: AtomicUsize::new(0),
+            busy_keys: Mutex::new(HashMap::new()),
+            main_jobs: Mutex::new(VecDeque::new()),
+            shutdown_jobs: Mutex::new(VecDeque::new()),
+            resend: sender.clone(),
+            delayed: Mutex::new(Vec::new()),
+            max_reschedules: config.max_reschedules,
+            live_workers: AtomicUsize::new(0),
+            burst_workers: AtomicUsize::new(0),
+            idle_workers: AtomicUsize::new(0),
+            min_workers: config.min_workers,
+            idle_timeout: config.lazy.then_some(config.idle_timeout),
+            housekeeping_interval: config.housekeeping_interval,
+            worker_status: Mutex::new(HashMap::new()),
+            unquiesced: Condvar::new(),
+            max_worker_rss: config.max_worker_rss,
+            stopping: AtomicBool::new(false),
+            recycle_requests: Mutex::new(None),
+            flight_recorder: config.flight_recorder.map(|capacity| Arc::new(FlightRecorder::new(capacity))),
+            category_rates: Mutex::new(HashMap::new()),
+            traceback_depth: config.traceback_depth,
+            on_log: Callback::new(config.on_log),
+            job_logs: Mutex::new(HashMap::new()),
+            on_worker_start: Callback::new(config.on_worker_start),
+            init_failure: config.init_failure,
+            init_log: InitLog {
+                state: Mutex::new((0, Vec::new())),
+                done: Condvar::new(),
+            },
+            on_worker_stop: Callback::new(config.on_worker_stop),
+            on_complete_timed: Callback::new(config.on_complete_timed),
+            on_finally: Callback::new(config.on_finally),
+            callback_metadata: config.callback_metadata,
+            submit_middleware: Callback::new(config.submit_middleware),
+            context_getter: Callback::new(config.context_getter),
+            context_setter: Callback::new(config.context_setter),
+            on_scale: Callback::new(config.on_scale),
+            on_queue_empty: Callback::new(config.on_queue_empty),
+            soft_capacity: config.soft_capacity,
+            on_watermark: Callback::new(config.on_watermark),
+            backlog_alarm: config.backlog_alarm,
+            on_backlog_alarm: Callback::new(config.on_backlog_alarm),
+            slow_job_threshold: config.slow_job_threshold,
+            on_slow_job: Callback::new(config.on_slow_job),
+            coalesce: config.coalesce,
+            alarm_state: Mutex::new((false, None)),
+            on_full: Callback::new(config.on_full),
+            on_not_full: Callback::new(config.on_not_full),
+            full: AtomicBool::new(false),
+            capacity: AtomicUsize::new(capacity),
+            custom_queue: Callback::new(config.queue),
+            custom_ids: Mutex::new(HashMap::new()),
+            above_soft: AtomicBool::new(false),
+            backlog: AtomicBool::new(false),
+            error_log,
+            gil_wait_nanos: AtomicU64::new(0),
+            avg_exec_time: AtomicU64::new(0),
+            avg_cpu_time: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+            results: Mutex::new(None),
+            notifications: Mutex::new(None),
+            reorder: config.ordered_callbacks.then(Default::default),
+            interpreter: config.interpreter,
+            dispatch_latency: Default::default(),
+        });
+
+        let workers = Arc::new(Workers {
+            threads: Mutex::new(Vec::with_capacity(size)),
+            next_id: AtomicUsize::new(0),
+            shared: Arc::clone(&shared),
+            stack_size: config.stack_size,
+        });
+
+        let callback_thread = (config.callback_thread || config.ordered_callbacks).then(|| {
+            let (sender, receiver) = unbounded::<Notification>();
+            *shared.notifications.lock().unwrap() = Some(sender);
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                let _interpreter = shared.interpreter.map(Interpreter::enter);
+                shared.run_notifications(receiver);
+            })
+        });
+
+        let recycler = config.max_worker_rss.map(|_| {
+            let (sender, receiver) = unbounded::<()>();
+            *shared.recycle_requests.lock().unwrap() = Some(sender);
+            let workers = Arc::clone(&workers);
+            thread::spawn(move || {
+                for () in receiver {
+                    // Spawn gives the slot back if the thread can't be created
+                    if let Err(e) = workers.spawn(WorkerKind::Core) {
+                        eprintln!("Failed to spawn replacement worker thread: {}", e);
+                    }
+                }
+            })
+        });
+
+        let slow_job_monitor = config.slow_job_threshold.map(|threshold| {
+            let (sender, receiver) = unbounded::<()>();
+            let shared = Arc::clone(&shared);
+            // A few scans per threshold, so a job is reported soon after it
+            // passes it
+            let interval = (threshold / SLOW_JOB_SCANS).min(config.housekeeping_interval);
+            let thread = thread::spawn(move || {
+                // on_slow_job runs here
+                let _interpreter = shared.interpreter.map(Interpreter::enter);
+                while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                    shared.check_slow_jobs();
+                }
+            });
+            (sender, thread)
+        });
+
+        let autoscaler = config.autoscale.then(|| {
+            let autoscaler = Arc::new(Autoscaler::new(config.idle_timeout));
+            let controller = Arc::clone(&autoscaler);
+            let workers = Arc::clone(&workers);
+            let sender = sender.clone();
+            let interpreter = shared.interpreter;
+            let thread = thread::spawn(move || {
+                // on_scale runs here
+                let _interpreter = interpreter.map(Interpreter::enter);
+                controller.run(&workers, sender, size);
+            });
+            (autoscaler, thread)
+        });
+
+        let pool = ThreadPool {
+            workers,
+            sender: Some(sender),
+            is_running,
+            shared,
+            size,
+            lazy: config.lazy,
+            next_job_id: AtomicU64::new(1),
+            overflow: config.overflow,
+            dropped: AtomicUsize::new(0),
+            error_flusher,
+            autoscaler,
+            callback_thread,
+            recycler,
+            slow_job_monitor,
+            picklable: config.picklable,
+        };
+
+        // Lazy and autoscaling pools only pre-spawn their floor; the rest
+        // arrive with jobs
+        let initial = if config.lazy || config.autoscale { config.min_workers } else { size };
+        for _ in 0..initial {
+            pool.shared.live_workers.fetch_add(1, Ordering::SeqCst);
+            pool.workers.spawn(WorkerKind::Core)
+                .map_err(|e| format!("Failed to spawn worker thread: {}", e))?;
+        }
+
+        Ok(pool)
+    }
+
+    // In lazy mode, add a worker if there aren't enough idle ones to take
+    // the queued messages plus the one about to be sent
+    fn ensure_worker(&self) {
+        if !self.lazy {
+            return;
+        }
+
+        if self.shared.idle_workers.load(Ordering::SeqCst) > self.channel_len() {
+            return;
+        }
+
+        if self.shared.try_grow(self.size) {
+            // Existing workers still drain the queue, so only report it
+            if let Err(e) = self.workers.spawn(WorkerKind::Core) {
+                eprintln!("Failed to spawn worker thread: {}", e);
+            }
+        }
+    }
+
+    // Add temporary workers that retire once they sit idle for the ttl
+    fn burst(&self, extra: usize, ttl: Duration) -> Result<(), PoolError> {
+        if !self.is_running.load(Ordering::SeqCst) {
+            return Err(PoolError::Shutdown);
+        }
+
+        for _ in 0..extra {
+            self.shared.burst_workers.fetch_add(1, Ordering::SeqCst);
+            if let Err(e) = self.workers.spawn(WorkerKind::Burst(ttl)) {
+                eprintln!("Failed to spawn burst worker thread: {}", e);
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn new_job(&self, func: PyObject) -> Job {
+        let context = self.shared.capture_context();
+        self.new_job_in(func, context)
+    }
+
+    // new_job with the context already captured, for callers holding a lock
+    // the GIL must not be taken under
+    fn new_job_in(&self, func: PyObject, context: Option<PyObject>) -> Job {
+        let id = self.next_job_id.fetch_add(1, Ordering::SeqCst);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(job_id = id, "job submitted");
+        self.shared.record_event(id, "submitted");
+        self.shared.register_ordered(id);
+        Job {
+            id,
+            func,
+            into: None,
+            tag: None,
+            future: None,
+            weight: 1,
+            event: None,
+            idempotency_key: None,
+            reschedules: 0,
+            category: None,
+            context,
+            map_result: None,
+            result_type: None,
+            replace_key: None,
+            priority: 0,
+            overtaken: 0,
+            metadata: None,
+            slot: false,
+            route: Route::Plain,
+        }
+    }
+
+    // Pass a submitted callable through the middleware, which may wrap it
+    // or raise to reject the submission. A picklable pool then rejects what
+    // checkpoint() could not save, as the middleware left it.
+    fn apply_middleware(&self, py: Python<'_>, func: PyObject) -> PyResult<PyObject> {
+        let func = match self.shared.submit_middleware.get(py) {
+            Some(middleware) => middleware.call1(py, (func,))?,
+            None => func,
+        };
+        if self.picklable {
+            if let Err(e) = py.import("pickle")?.call_method1("dumps", (&func,)) {
+                return Err(PyTypeError::new_err(format!(
+                    "Job {} cannot be pickled: {}",
+                    job_name(py, &func),
+                    e.value(py)
+                )));
+            }
+        }
+        Ok(func)
+    }
+
+    // Queue the callables a checkpoint saved, as plain jobs in their old order
+    fn restore(&self, py: Python<'_>, path: PathBuf) -> PyResult<usize> {
+        let data = py.allow_threads(|| fs::read(&path))?;
+        let jobs: Vec<PyObject> = py
+            .import("pickle")?
+            .call_method1("loads", (PyBytes::new(py, &data),))?
+            .extract()?;
+        let count = jobs.len();
+        for job in jobs {
+            py.allow_threads(|| self.execute(job, 1))?;
+        }
+        Ok(count)
+    }
+
+    fn execute(&self, func: PyObject, weight: usize) -> Result<u64, PoolError> {
+        let mut job = self.new_job(func);
+        job.weight = weight;
+        self.execute_job(job, false)
+    }
+
+    // add_job's job, with its optional category and metadata
+    fn execute_tagged(
+        &self,
+        func: PyObject,
+        weight: usize,
+        category: Option<String>,
+        metadata: Option<PyObject>,
+    ) -> Result<u64, PoolError> {
+        let mut job = self.new_job(func);
+        job.weight = weight;
+        job.category = category;
+        job.metadata = metadata;
+        self.execute_job(job, false)
+    }
+
+    // Submit into a slot already taken by reserve
+    fn execute_reserved(&self, func: PyObject) -> Result<u64, PoolError> {
+        let job = self.new_job(func);
+        self.execute_job(job, true)
+    }
+
+    // Queue the job only if a slot is free right now, without applying the
+    // overflow policy. None means the queue was full and nothing was queued.
+    fn try_execute(&self, func: PyObject) -> Result<Option<u64>, PoolError> {
+        if !self.is_running.load(Ordering::SeqCst) {
+            return Err(PoolError::Shutdown);
+        }
+
+        if !self.shared.slots.try_acquire_many(1) {
+            return Ok(None);
+        }
+        self.execute_reserved(func).map(Some)
+    }
+
+    fn execute_into(&self, func: PyObject, results: Py<PyList>, index: usize) -> Result<u64, PoolError> {
+        let mut job = self.new_job(func);
+        job.into = Some((results, index));
+        self.execute_job(job, false)
+    }
+
+    fn execute_event(&self, func: PyObject, event: PyObject) -> Result<u64, PoolError> {
+        let mut job = self.new_job(func);
+        job.event = Some(event);
+        self.execute_job(job, false)
+    }
+
+    fn execute_future(
+        &self,
+        func: PyObject,
+        future: PyObject,
+        map_result: Option<PyObject>,
+        result_type: Option<PyObject>,
+        metadata: Option<PyObject>,
+    ) -> Result<u64, PoolError> {
+        let mut job = self.new_job(func);
+        job.future = Some(future);
+        job.map_result = map_result;
+        job.result_type = result_type;
+        job.metadata = metadata;
+        self.execute_job(job, false)
+    }
+
+    fn execute_in_group(&self, func: PyObject, tag: u64) -> Result<u64, PoolError> {
+        let mut job = self.new_job(func);
+        job.tag = Some(tag);
+        self.execute_job(job, false)
+    }
+
+    fn execute_job(&self, mut job: Job, reserved: bool) -> Result<u64, PoolError> {
+        if !self.is_running.load(Ordering::SeqCst) {
+            self.shared.release_ordered(job.id);
+            return Err(PoolError::Shutdown);
+        }
+
+        let id = job.id;
+        if let Some(tag) = job.tag {
+            self.shared.tallies.lock().unwrap().entry(tag).or_default().pending += 1;
+        }
+        self.increment_job_count(job.weight);
+        if self.shared.custom_queue.is_set() {
+            return self.execute_custom(job, reserved);
+        }
+        // Taken before the job is queued, since in LIFO mode any worker may
+        // take it from then on
+        if !reserved {
+            if let Err(e) = self.take_slot() {
+                self.drop_job(Some(job));
+                return Err(e);
+            }
+        }
+        job.slot = true;
+        self.shared.queue_new_job(job);
+        self.send_reserved(Message::NewJob(id))?;
+        Ok(id)
+    }
+
+    // Nothing can be taken back out of a custom queue, so the slot comes
+    // first and a full queue rejects the job before put() sees it
+    fn execute_custom(&self, mut job: Job, reserved: bool) -> Result<u64, PoolError> {
+        let id = job.id;
+        if !reserved {
+            if let Err(e) = self.reserve(1) {
+                self.drop_job(Some(job));
+                return Err(e);
+            }
+        }
+
+        job.slot = true;
+        if let Err(e) = self.shared.put_custom(job) {
+            self.drop_job(self.shared.take_queued(id));
+            return Err(PoolError::Queue(e));
+        }
+        self.send_reserved(Message::NewJob(id))?;
+        Ok(id)
+    }
+
+    fn execute_limited(&self, func: PyObject, semaphore: Arc<Semaphore>, weight: usize) -> Result<u64, PoolError> {
+        if !self.is_running.load(Ordering::SeqCst) {
+            return Err(PoolError::Shutdown);
+        }
+
+        let mut job = self.new_job(func);
+        job.weight = weight;
+        job.route = Route::Limited(Arc::clone(&semaphore));
+        let id = job.id;
+        self.increment_job_count(weight);
+        self.shared.queue_job(job);
+        self.send(Message::LimitedJob(id, semaphore, weight))?;
+        Ok(id)
+    }
+
+    fn execute_native(&self, job: NativeJob) -> Result<(), PoolError> {
+        if !self.is_running.load(Ordering::SeqCst) {
+            return Err(PoolError::Shutdown);
+        }
+
+        self.increment_job_count(1);
+        self.shared.bare_messages.fetch_add(1, Ordering::SeqCst);
+        self.send(Message::Native(job))
+    }
+
+    fn execute_apply(&self, apply: Apply) -> Result<(), PoolError> {
+        if !self.is_running.load(Ordering::SeqCst) {
+            return Err(PoolError::Shutdown);
+        }
+
+        self.increment_job_count(1);
+        self.shared.bare_messages.fetch_add(1, Ordering::SeqCst);
+        self.send(Message::Apply(apply))
+    }
+
+    fn execute_grouped(&self, func: PyObject, group: u64) -> Result<u64, PoolError> {
+        if !self.is_running.load(Ordering::SeqCst) {
+            return Err(PoolError::Shutdown);
+        }
+
+        // Count and queue the job before waking a worker, since a worker
+        // batching this group may pick it up before its token arrives
+        let mut job = self.new_job(func);
+        job.route = Route::Grouped(group);
+        let id = job.id;
+        self.increment_job_count(job.weight);
+        self.shared.groups.push(group, job);
+        self.send(Message::GroupedJob(group))?;
+        Ok(id)
+    }
+
+    fn execute_weighted(&self, func: PyObject, index: usize) -> Result<u64, PoolError> {
+        if !self.is_running.load(Ordering::SeqCst) {
+            return Err(PoolError::Shutdown);
+        }
+
+        let mut job = self.new_job(func);
+        job.route = Route::Weighted(index);
+        let id = job.id;
+        self.increment_job_count(job.weight);
+        self.shared.weighted.push(index, job);
+        self.send(Message::WeightedJob(index))?;
+        Ok(id)
+    }
+
+    // Queue the job unless one with the same key is still queued or running,
+    // returning the id of whichever job holds the key
+    fn execute_idempotent(&self, func: PyObject, key: String) -> Result<u64, PoolError> {
+        let context = self.shared.capture_context();
+        let mut keys = self.shared.idempotency_keys.lock().unwrap();
+        if let Some(&id) = keys.get(&key) {
+            return Ok(id);
+        }
+
+        let mut job = self.new_job_in(func, context);
+        let id = job.id;
+        keys.insert(key.clone(), id);
+        drop(keys);
+
+        job.idempotency_key = Some(key.clone());
+        let result = self.execute_job(job, false);
+        if result.is_err() {
+            self.shared.release_key(&key, id);
+        }
+        result
+    }
+
+    // Queue the job in place of the key's previous one, which is skipped if
+    // it has not started. Its message stays queued and finds nothing to run.
+    fn execute_replacing(&self, func: PyObject, key: String) -> Result<u64, PoolError> {
+        let mut job = self.new_job(func);
+        let id = job.id;
+        job.replace_key = Some(key.clone());
+        let previous = self.shared.replace_keys.lock().unwrap().insert(key.clone(), id);
+
+        // A previous job still waiting hands its slot over, so replacing it
+        // never waits for room or meets the overflow policy
+        let handed_over = previous.and_then(|previous| self.shared.take_slot_holder(previous));
+        let reserved = handed_over.is_some();
+        if let Some(mut stale) = handed_over {
+            stale.slot = false;
+            self.shared.cancel_taken(stale);
+        }
+
+        if let Err(e) = self.execute_job(job, reserved) {
+            // The previous job still stands for the key, unless it is gone
+            let mut keys = self.shared.replace_keys.lock().unwrap();
+            if keys.get(&key) == Some(&id) {
+                match previous.filter(|_| !reserved) {
+                    Some(previous) => keys.insert(key, previous),
+                    None => keys.remove(&key),
+                };
+            }
+            return Err(e);
+        }
+
+        // One put back by reschedule() holds no slot, and is running or
+        // finished already if it is no longer queued
+        if !reserved {
+            if let Some(stale) = previous.and_then(|previous| self.shared.take_queued(previous)) {
+                self.shared.cancel_taken(stale);
+            }
+        }
+        Ok(id)
+    }
+
+    fn execute_main(&self, func: PyObject) -> Result<u64, PoolError> {
+        if !self.is_running.load(Ordering::SeqCst) {
+            return Err(PoolError::Shutdown);
+        }
+
+        let job = self.new_job(func);
+        let id = job.id;
+        // Main thread jobs have no callbacks to order
+        self.shared.release_ordered(id);
+        self.shared.main_jobs.lock().unwrap().push_back(job);
+        Ok(id)
+    }
+
+    fn execute_shutdown(&self, func: PyObject) -> Result<u64, PoolError> {
+        if !self.is_running.load(Ordering::SeqCst) {
+            return Err(PoolError::Shutdown);
+        }
+
+        let job = self.new_job(func);
+        let id = job.id;
+        self.shared.shutdown_jobs.lock().unwrap().push_back(job);
+        Ok(id)
+    }
+
+    // Run the shutdown jobs in order, each once everything before it has
+    // settled. Submissions have stopped by now, so nothing else can queue.
+    fn run_shutdown_jobs(&self) {
+        let Some(sender) = &self.sender else { return };
+        loop {
+            if self.shared.shutdown_jobs.lock().unwrap().is_empty() {
+                break;
+            }
+            self.wait_drained();
+            let Some(job) = self.shared.shutdown_jobs.lock().unwrap().pop_front() else { break };
+            let id = job.id;
+            self.increment_job_count(job.weight);
+            self.shared.queue_job(job);
+            self.ensure_worker();
+            let _ = sender.send((Message::Requeued(id), Instant::now()));
+        }
+    }
+
+    fn wait_drained(&self) {
+        let count = self.shared.job_count.lock().unwrap();
+        drop(self.shared.job_done.wait_while(count, |count| *count > 0).unwrap());
+    }
+
+    fn execute_keyed(&self, func: PyObject, key: u64, priority: i64) -> Result<u64, PoolError> {
+        if !self.is_running.load(Ordering::SeqCst) {
+            return Err(PoolError::Shutdown);
+        }
+
+        let mut job = self.new_job(func);
+        job.priority = priority;
+        job.route = Route::Keyed(key);
+        let id = job.id;
+        self.increment_job_count(job.weight);
+        self.shared.keyed.push_by_priority(key, job);
+        self.send(Message::KeyedJob(key))?;
+        Ok(id)
+    }
+
+    fn increment_job_count(&self, weight: usize) {
+        let mut count = self.shared.job_count.lock().unwrap();
+        *count += 1;
+        self.shared.submitted.fetch_add(1, Ordering::SeqCst);
+        self.shared.weighted_load.fetch_add(weight, Ordering::SeqCst);
+        self.shared.counters.lock().unwrap().record("submitted");
+    }
+
+    fn send(&self, message: Message) -> Result<(), PoolError> {
+        if let Err(e) = self.take_slot() {
+            self.discard(message, true);
+            return Err(e);
+        }
+        self.send_reserved(message)
+    }
+
+    // Take a queue slot as the overflow policy allows
+    fn take_slot(&self) -> Result<(), PoolError> {
+        match self.overflow {
+            OverflowPolicy::Block => self.shared.slots.acquire_many(1),
+            OverflowPolicy::DropNew => {
+                if !self.shared.slots.try_acquire_many(1) {
+                    return Err(PoolError::QueueFull);
+                }
+            }
+            OverflowPolicy::DropOld => {
+                // The evicted message hands its slot over, and an evicted
+                // NewJob's job gives its own back. Other messages without
+                // one go back on the end. If no queued message holds a slot,
+                // the slots are all reserved and cannot be freed.
+                let mut skippable = self.channel_len();
+                while !self.shared.slots.try_acquire_many(1) {
+                    match self.shared.receiver.try_recv() {
+                        Ok((oldest @ Message::NewJob(_), _)) => self.discard(oldest, false),
+                        Ok((oldest, sent)) if !oldest.holds_slot() && skippable > 0 => {
+                            skippable -= 1;
+                            let _ = self.shared.resend.send((oldest, sent));
+                        }
+                        Ok((oldest, _)) if oldest.holds_slot() => {
+                            self.discard(oldest, false);
+                            break;
+                        }
+                        Ok((oldest, sent)) => {
+                            let _ = self.shared.resend.send((oldest, sent));
+                            return Err(PoolError::QueueFull);
+                        }
+                        Err(_) => return Err(PoolError::QueueFull),
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Send a message whose queue slot is already held
+    fn send_reserved(&self, message: Message) -> Result<(), PoolError> {
+        self.ensure_worker();
+
+        let sender = self.sender.as_ref().ok_or(PoolError::Shutdown)?;
+        // Before sending, so the worker that empties the queue sees it
+        self.shared.backlog.store(true, Ordering::SeqCst);
+        sender.send((message, Instant::now())).map_err(|_| PoolError::Shutdown)?;
+        self.shared.check_watermark();
+        self.shared.check_backlog();
+        self.shared.check_full();
+        Ok(())
+    }
+
+    // Hold `n` queue slots so a later burst of submissions is admitted
+    // without competing with other producers
+    fn reserve(&self, n: usize) -> Result<(), PoolError> {
+        if !self.is_running.load(Ordering::SeqCst) {
+            return Err(PoolError::Shutdown);
+        }
+
+        let capacity = &self.shared.capacity;
+        let over_capacity = || PoolError::OverCapacity(n, capacity.load(Ordering::SeqCst));
+        if n > capacity.load(Ordering::SeqCst) {
+            return Err(over_capacity());
+        }
+
+        match self.overflow {
+            // set_capacity may shrink the queue below n while this waits
+            OverflowPolicy::Block => {
+                if !self.shared.slots.acquire_many_unless(n, || n > capacity.load(Ordering::SeqCst)) {
+                    return Err(over_capacity());
+                }
+            }
+            OverflowPolicy::DropNew | OverflowPolicy::DropOld => {
+                if !self.shared.slots.try_acquire_many(n) {
+                    return Err(PoolError::QueueFull);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Throw away a job message that will never reach a worker. Grouped and
+    // LIFO jobs are not tied to their message, so remove the newest one for
+    // a rejected submission and the oldest one for an eviction.
+    fn discard(&self, message: Message, newest: bool) {
+        let job = match message {
+            Message::NewJob(id) => match self.shared.take_new_job(id, newest) {
+                Some(job) => Some(job),
+                // Already settled when the GC cleared it
+                None => return,
+            },
+            Message::LimitedJob(id, _, _) | Message::Requeued(id) => match self.shared.take_queued(id) {
+                Some(job) => Some(job),
+                None => return,
+            },
+            // Tell the caller, who may be waiting with a sender of its own
+            Message::Apply(apply) => {
+                self.shared.bare_messages.fetch_sub(1, Ordering::SeqCst);
+                let dropped = QueueFullError::new_err("Job dropped by the overflow policy");
+                let _ = apply.results.send((apply.index, Err(dropped)));
+                None
+            }
+            Message::Native(_) => {
+                self.shared.bare_messages.fetch_sub(1, Ordering::SeqCst);
+                None
+            }
+            // The retirement never happens, so the worker keeps its slot
+            Message::Retire => {
+                self.shared.live_workers.fetch_add(1, Ordering::SeqCst);
+                return;
+            }
+            Message::GroupedJob(group) => {
+                let job = if newest {
+                    self.shared.groups.pop_newest(group)
+                } else {
+                    self.shared.groups.pop(group)
+                };
+                // The batching worker took it already, so there is nothing to drop
+                if job.is_none() {
+                    return;
+                }
+                job
+            }
+            Message::WeightedJob(index) => match self.shared.weighted.remove(index, newest) {
+                Some(job) => Some(job),
+                None => return,
+            },
+            Message::KeyedJob(key) => {
+                let job = if newest {
+                    self.shared.keyed.pop_newest(key)
+                } else {
+                    self.shared.keyed.pop(key)
+                };
+                if job.is_none() {
+                    return;
+                }
+                job
+            }
+            Message::Terminate => return,
+        };
+        self.drop_job(job);
+    }
+
+    // Count a job, or an apply() call or native job when None, as dropped
+    fn drop_job(&self, job: Option<Job>) {
+        match &job {
+            Some(job) => self.shared.settle(job, "dropped"),
+            None => {
+                self.shared.weighted_load.fetch_sub(1, Ordering::SeqCst);
+                self.shared.counters.lock().unwrap().record("dropped");
+            }
+        }
+
+        let needs_gil = |job: &Job| {
+            job.future.is_some() || job.event.is_some() || self.shared.on_finally.is_set() || self.shared.reorder.is_some()
+        };
+        if let Some(job) = job.filter(needs_gil) {
+            Python::with_gil(|py| {
+                job.abandon(py, "Job dropped by the overflow policy");
+                self.shared.notify_finally(py, &job, "dropped");
+            });
+        }
+
+        self.dropped.fetch_add(1, Ordering::SeqCst);
+        finish_job(&self.shared);
+    }
+
+    fn shutdown(&mut self) {
+        println!("Shutting down thread pool");
+        self.is_running.store(false, Ordering::SeqCst);
+
+        // Stop the autoscaler first so the worker count holds still
+        if let Some((autoscaler, controller)) = self.autoscaler.take() {
+            autoscaler.stop();
+            let _ = controller.join();
+        }
+
+        // Likewise the recycler, after it spawns any replacements already
+        // asked for; workers leaving later just exit
+        let recycle_requests = self.shared.recycle_requests.lock().unwrap().take();
+        drop(recycle_requests);
+        if let Some(recycler) = self.recycler.take() {
+            let _ = recycler.join();
+        }
+
+        if let Some((stop, monitor)) = self.slow_job_monitor.take() {
+            drop(stop);
+            let _ = monitor.join();
+        }
+
+        // Parked workers are needed for the shutdown jobs and must see their
+        // Terminate
+        for status in self.shared.worker_status.lock().unwrap().values_mut() {
+            status.quiesced = false;
+        }
+        self.shared.unquiesced.notify_all();
+        self.shared.stopping.store(true, Ordering::SeqCst);
+        {
+            let _guard = PAUSE_LOCK.lock().unwrap();
+            RESUMED.notify_all();
+        }
+
+        self.run_shutdown_jobs();
+        
+        // Send termination messages to all workers
+        if let Some(sender) = &self.sender {
+            for _ in 0..self.live_workers() {
+                let _ = sender.send((Message::Terminate, Instant::now()));
+            }
+        }
+        
+        // Drop the sender to close the channel
+        self.sender.take();
+        
+        // Join all worker threads
+        for worker in self.workers.threads.lock().unwrap().iter_mut() {
+            if let Some(thread) = worker.thread.take() {
+                match thread.join() {
+                    Ok(_) => println!("Worker {} thread joined successfully", worker.id),
+                    Err(e) => eprintln!("Error joining worker {} thread: {:?}", worker.id, e),
+                }
+            }
+        }
+
+        // The callback thread finishes what the workers sent it, then exits
+        let notifications = self.shared.notifications.lock().unwrap().take();
+        drop(notifications);
+        if let Some(callback_thread) = self.callback_thread.take() {
+            let _ = callback_thread.join();
+        }
+
+        // Let results() iterators finish once they drain what is left
+        let results = self.shared.results.lock().unwrap().take();
+        drop(results);
+
+        // Flush whatever errors the last window collected
+        if let (Some(log), Some(flusher)) = (&self.shared.error_log, self.error_flusher.take()) {
+            log.stop();
+            let _ = flusher.join();
+        }
+        
+        // Reset job count and release anyone still waiting on it
+        let mut count = self.shared.job_count.lock().unwrap();
+        *count = 0;
+        self.shared.weighted_load.store(0, Ordering::SeqCst);
+        let submitted = self.shared.submitted.load(Ordering::SeqCst);
+        self.shared.settled.store(submitted, Ordering::SeqCst);
+        self.shared.job_done.notify_all();
+    }
+
+    fn active_jobs(&self) -> usize {
+        *self.shared.job_count.lock().unwrap()
+    }
+
+    // Active jobs not in flight yet, including delayed ones and any waiting
+    // for a limiter. A finishing job is briefly counted here once it has
+    // left in_flight.
+    fn queued_jobs(&self) -> usize {
+        self.active_jobs().saturating_sub(self.in_flight())
+    }
+
+    fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::SeqCst)
+    }
+
+    // Ids and callables of the jobs waiting to run, oldest first. Taken
+    // without stopping the workers, so it may be stale by the time it's read.
+    fn pending_jobs(&self, py: Python<'_>) -> Vec<PendingJob> {
+        let mut jobs: Vec<PendingJob> = self
+            .shared
+            .queued
+            .lock()
+            .unwrap()
+            .values()
+            .map(|job| (job.id, job.func.clone_ref(py), job.metadata(py)))
+            .collect();
+        jobs.extend(self.shared.groups.snapshot(py));
+        jobs.extend(self.shared.keyed.snapshot(py));
+        jobs.extend(self.shared.weighted.snapshot(py));
+        jobs.sort_by_key(|(id, _, _)| *id);
+        jobs
+    }
+
+    fn weighted_load(&self) -> usize {
+        self.shared.weighted_load.load(Ordering::SeqCst)
+    }
+
+    fn capacity(&self) -> usize {
+        self.shared.capacity.load(Ordering::SeqCst)
+    }
+
+    // Growing frees the new slots at once. Shrinking takes back free slots
+    // and withholds the rest as jobs leave the queue, so nothing queued is
+    // dropped; submissions wait or overflow until it is below the new cap.
+    fn set_capacity(&self, capacity: usize) {
+        let old = self.shared.capacity.swap(capacity, Ordering::SeqCst);
+        if capacity > old {
+            self.shared.slots.release_many(capacity - old);
+        } else {
+            self.shared.slots.shrink(old - capacity);
+        }
+        self.shared.check_full();
+    }
+
+    fn queue_len(&self) -> usize {
+        self.shared.queue_len()
+    }
+
+    // Messages waiting for a worker, stale ones included
+    fn channel_len(&self) -> usize {
+        self.sender.as_ref().map_or(0, Sender::len)
+    }
+
+    fn avg_exec_time(&self) -> f64 {
+        f64::from_bits(self.shared.avg_exec_time.load(Ordering::Relaxed))
+    }
+
+    fn avg_cpu_time(&self) -> f64 {
+        f64::from_bits(self.shared.avg_cpu_time.load(Ordering::Relaxed))
+    }
+
+    // Seconds until the queue empties if jobs keep taking the average time
+    // and every worker keeps busy. Ignores jobs already running.
+    fn estimated_drain_time(&self) -> f64 {
+        let workers = self.live_workers().max(1);
+        self.queue_len() as f64 * self.avg_exec_time() / workers as f64
+    }
+
+    // Wait for the workers spawned at construction to run on_worker_start,
+    // failing if every one of them gave up
+    fn wait_for_init(&self) -> Result<(), String> {
+        let initial = if self.lazy || self.autoscaler.is_some() { self.shared.min_workers } else { self.size };
+        let errors = self.shared.init_log.wait(initial);
+        if initial > 0 && errors.len() == initial {
+            return Err(format!("All {} workers failed to start: {}", initial, errors.join("; ")));
+        }
+        Ok(())
+    }
+
+    fn in_flight(&self) -> usize {
+        self.shared.in_flight.load(Ordering::SeqCst)
+    }
+
+    // (upper bound in seconds, count) per dispatch_latency bucket, the
+    // last bound being infinite
+    fn dispatch_latency(&self) -> Vec<(f64, u64)> {
+        let bounds = DISPATCH_BUCKETS_NANOS
+            .iter()
+            .map(|&nanos| Duration::from_nanos(nanos).as_secs_f64())
+            .chain([f64::INFINITY]);
+        bounds
+            .zip(&self.shared.dispatch_latency)
+            .map(|(bound, count)| (bound, count.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    fn gil_wait_total(&self) -> Duration {
+        Duration::from_nanos(self.shared.gil_wait_nanos.load(Ordering::Relaxed))
+    }
+
+    fn live_workers(&self) -> usize {
+        self.shared.live_workers.load(Ordering::SeqCst)
+            + self.shared.burst_workers.load(Ordering::SeqCst)
+    }
+
+    // Block until the job count reaches zero or the timeout elapses.
+    // Returns true if the pool is idle.
+    fn wait_idle_for(&self, timeout: Duration) -> bool {
+        let guard = self.shared.job_count.lock().unwrap();
+        let (guard, _) = self
+            .shared
+            .job_done
+            .wait_timeout_while(guard, timeout, |count| *count > 0)
+            .unwrap();
+        *guard == 0
+    }
+
+    // Wait until no jobs in the group are pending, returning its tally
+    fn wait_group_for(&self, tag: u64, timeout: Duration) -> GroupTally {
+        let tally = || self.shared.tallies.lock().unwrap().get(&tag).copied().unwrap_or_default();
+        let guard = self.shared.job_count.lock().unwrap();
+        let _ = self
+            .shared
+            .job_done
+            .wait_timeout_while(guard, timeout, |_| tally().pending > 0)
+            .unwrap();
+        tally()
+    }
+
+    // Forget a finished group so its tally does not linger
+    fn take_group(&self, tag: u64) -> GroupTally {
+        let mut tallies = self.shared.tallies.lock().unwrap();
+        match tallies.get(&tag) {
+            Some(tally) if tally.pending == 0 => tallies.remove(&tag).unwrap_or_default(),
+            Some(tally) => *tally,
+            None => GroupTally::default(),
+        }
+    }
+
+    fn submitted(&self) -> u64 {
+        let _count = self.shared.job_count.lock().unwrap();
+        self.shared.submitted.load(Ordering::SeqCst)
+    }
+
+    fn completed(&self) -> u64 {
+        self.shared.completed.load(Ordering::SeqCst)
+    }
+
+    // The running totals behind stats() and the waits are left alone
+    fn drain_stats(&self) -> JobCounters {
+        std::mem::take(&mut *self.shared.counters.lock().unwrap())
+    }
+
+    fn throughput(&self, window: u64) -> f64 {
+        self.shared.throughput.rate(window)
+    }
+
+    // Wait until `target` jobs in total have settled, returning whether
+    // they did before the timeout
+    fn wait_settled_for(&self, target: u64, timeout: Duration) -> bool {
+        self.wait_counter_for(&self.shared.settled, target, timeout)
+    }
+
+    // The same for jobs that completed successfully
+    fn wait_completed_for(&self, target: u64, timeout: Duration) -> bool {
+        self.wait_counter_for(&self.shared.completed, target, timeout)
+    }
+
+    // Every finished job wakes job_done, so it serves any running total
+    fn wait_counter_for(&self, counter: &AtomicU64, target: u64, timeout: Duration) -> bool {
+        let reached = || counter.load(Ordering::SeqCst) >= target;
+        let guard = self.shared.job_count.lock().unwrap();
+        let _ = self
+            .shared
+            .job_done
+            .wait_timeout_while(guard, timeout, |_| !reached())
+            .unwrap();
+        reached()
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        if self.sender.is_some() {
+            self.shutdown();
+        }
+    }
+}
+
+// Concurrency cap shared by the jobs submitted with it
+#[pyclass]
+struct Limiter {
+    semaphore: Arc<Semaphore>,
+    max_concurrent: usize,
+}
+
+#[pymethods]
+impl Limiter {
+    #[getter]
+    fn max_concurrent(&self) -> usize {
+        self.max_concurrent
+    }
+
+    #[getter]
+    fn available(&self) -> usize {
+        self.semaphore.available()
+    }
+}
+
+// A done callback on a submit() future. Cancelling the future settles its
+// job at once if it is still queued, so its queue slot is not held until a
+// worker reaches it. Weak, since the future may outlive the pool.
+#[pyclass]
+struct CancelHook {
+    shared: Weak<Shared>,
+    job_id: u64,
+}
+
+#[pymethods]
+impl CancelHook {
+    fn __call__(&self, future: &PyAny) -> PyResult<()> {
+        if !future.call_method0("cancelled")?.is_true()? {
+            return Ok(());
+        }
+        if let Some(shared) = self.shared.upgrade() {
+            if let Some(job) = shared.take_queued(self.job_id) {
+                shared.cancel_taken(job);
+            }
+        }
+        Ok(())
+    }
+}
+
+// The add_job_main queue of an Aqueue, from main_thread_queue, for code
+// that should only be able to hand work to the main thread
+#[pyclass]
+struct MainThreadQueue {
+    aqueue: Py<Aqueue>,
+}
+
+#[pymethods]
+impl MainThreadQueue {
+    fn put(&self, py: Python<'_>, job: PyObject) -> PyResult<u64> {
+        self.aqueue.borrow(py).add_job_main(py, job)
+    }
+
+    #[pyo3(signature = (max_jobs=None))]
+    fn pump(&self, py: Python<'_>, max_jobs: Option<usize>) -> PyResult<usize> {
+        self.aqueue.borrow(py).pump_main(py, max_jobs)
+    }
+
+    fn __len__(&self, py: Python<'_>) -> usize {
+        self.aqueue.borrow(py).main_pending()
+    }
+}
+
+// Queue slots held for one producer's burst of submissions
+#[pyclass]
+struct Reservation {
+    aqueue: Py<Aqueue>,
+    slots: Arc<Semaphore>,
+    remaining: usize,
+}
+
+#[pymethods]
+impl Reservation {
+    #[getter]
+    fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    fn submit(&mut self, py: Python<'_>, job: PyObject) -> PyResult<u64> {
+        if self.remaining == 0 {
+            return Err(PyValueError::new_err("Reservation has no slots left"));
+        }
+
+        let aqueue = self.aqueue.borrow(py);
+        let pool = aqueue.pool()?;
+        let job = pool.apply_middleware(py, job)?;
+        let id = py.allow_threads(|| pool.execute_reserved(job))?;
+        self.remaining -= 1;
+        Ok(id)
+    }
+
+    // Hand the unused slots back to the pool
+    fn release(&mut self) {
+        self.slots.release_many(self.remaining);
+        self.remaining = 0;
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __exit__(
+        &mut self,
+        _exc_type: Option<&PyAny>,
+        _exc_val: Option<&PyAny>,
+        _exc_tb: Option<&PyAny>,
+    ) {
+        self.release();
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+// A function wrapped by Aqueue.task; calling it submits the function.
+// The dict holds the wrapped function's metadata copied by update_wrapper.
+#[pyclass(dict)]
+struct Task {
+    aqueue: Py<Aqueue>,
+    func: PyObject,
+}
+
+#[pymethods]
+impl Task {
+    #[pyo3(signature = (*args, **kwargs))]
+    fn __call__(&self, py: Python<'_>, args: &PyTuple, kwargs: Option<&PyDict>) -> PyResult<PyObject> {
+        self.aqueue.borrow(py).submit(py, self.func.clone_ref(py), args, None, None, None, kwargs)
+    }
+}
+
+// Awaitable from add_job_async. Each step tries to queue the job without
+// blocking; while the queue is full it hands the event loop a future that
+// a timer resolves, backing off up to ASYNC_SUBMIT_MAX_RETRY.
+#[pyclass]
+struct AsyncSubmit {
+    aqueue: Py<Aqueue>,
+    // Taken once queued, so the awaitable cannot submit twice
+    job: Option<PyObject>,
+    retry: Duration,
+}
+
+#[pymethods]
+impl AsyncSubmit {
+    fn __await__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<IterNextOutput<PyObject, u64>> {
+        let job = self
+            .job
+            .as_ref()
+            .ok_or_else(|| PyRuntimeError::new_err("add_job_async awaitable already awaited"))?
+            .clone_ref(py);
+        let aqueue = self.aqueue.borrow(py);
+        let pool = aqueue.pool()?;
+        if let Some(id) = py.allow_threads(|| pool.try_execute(job))? {
+            self.job = None;
+            return Ok(IterNextOutput::Return(id));
+        }
+
+        let event_loop = py.import("asyncio")?.call_method0("get_running_loop")?;
+        let waiter: PyObject = event_loop.call_method0("create_future")?.into();
+        // What Future.__await__ sets, telling the task to wait on it
+        waiter.setattr(py, "_asyncio_future_blocking", true)?;
+        // The task cancels the waiter if it is cancelled meanwhile
+        let pending = waiter.clone_ref(py);
+        let wake = PyCFunction::new_closure(py, None, None, move |args: &PyTuple, _: Option<&PyDict>| -> PyResult<()> {
+            let waiter = pending.as_ref(args.py());
+            if !waiter.call_method0("done")?.is_true()? {
+                waiter.call_method1("set_result", (args.py().None(),))?;
+            }
+            Ok(())
+        })?;
+        event_loop.call_method1("call_later", (self.retry.as_secs_f64(), wake))?;
+        self.retry = (self.retry * 2).min(ASYNC_SUBMIT_MAX_RETRY);
+        Ok(IterNextOutput::Yield(waiter))
+    }
+}
+
+// Iterator from Aqueue.parallel_map. Items are pulled from the source only
+// to refill the window, so at most max_outstanding results are in flight
+// or waiting to be yielded at a time.
+#[pyclass]
+struct ParallelMap {
+    aqueue: Py<Aqueue>,
+    func: PyObject,
+    // None once exhausted
+    source: Option<Py<PyIterator>>,
+    max_outstanding: usize,
+    ordered: bool,
+    results_tx: Sender<(usize, PyResult<PyObject>)>,
+    results_rx: Receiver<(usize, PyResult<PyObject>)>,
+    // Items submitted so far, and so the index of the next one
+    submitted: usize,
+    // Submitted and not yet yielded
+    outstanding: usize,
+    // In ordered mode, results that came in ahead of next_index
+    held: BTreeMap<usize, PyResult<PyObject>>,
+    next_index: usize,
+}
+
+impl ParallelMap {
+    fn refill(&mut self, py: Python<'_>) -> PyResult<()> {
+        while self.outstanding < self.max_outstanding {
+            let Some(source) = &self.source else { return Ok(()) };
+            let Some(item) = source.as_ref(py).next() else {
+                self.source = None;
+                return Ok(());
+            };
+            let apply = Apply {
+                func: self.func.clone_ref(py),
+                item: item?.into(),
+                index: self.submitted,
+                results: self.results_tx.clone(),
+            };
+            let aqueue = self.aqueue.borrow(py);
+            let pool = aqueue.pool()?;
+            py.allow_threads(|| pool.execute_apply(apply))?;
+            self.submitted += 1;
+            self.outstanding += 1;
+        }
+        Ok(())
+    }
+}
+
+#[pymethods]
+impl ParallelMap {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    // A failed item raises its exception in place of its result; iterating
+    // again carries on with the next one
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        loop {
+            self.refill(py)?;
+            if let Some(result) = self.held.remove(&self.next_index) {
+                self.next_index += 1;
+                self.outstanding -= 1;
+                return result.map(Some);
+            }
+            if self.outstanding == 0 {
+                return Ok(None);
+            }
+
+            match py.allow_threads(|| self.results_rx.recv_timeout(SIGNAL_CHECK_INTERVAL)) {
+                Ok((index, result)) if self.ordered => {
+                    self.held.insert(index, result);
+                }
+                Ok((_, result)) => {
+                    self.outstanding -= 1;
+                    return result.map(Some);
+                }
+                Err(_) => {
+                    py.check_signals()?;
+                    if self.aqueue.borrow(py).pool.is_none() {
+                        return Err(PoolError::Shutdown.into());
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Per-worker storage; each worker thread of the pool sees its own value
+#[pyclass]
+struct WorkerLocal {
+    slots: Arc<WorkerSlots>,
+}
+
+impl WorkerLocal {
+    fn not_a_worker() -> PyErr {
+        PyRuntimeError::new_err("WorkerLocal can only be used from the pool's worker threads")
+    }
+}
+
+#[pymethods]
+impl WorkerLocal {
+    #[pyo3(signature = (default=None))]
+    fn get(&self, py: Python<'_>, default: Option<PyObject>) -> PyResult<PyObject> {
+        let slots = self.slots.lock().unwrap();
+        match slots.get(&thread::current().id()) {
+            Some(Some(value)) => Ok(value.clone_ref(py)),
+            Some(None) => Ok(default.unwrap_or_else(|| py.None())),
+            None => Err(WorkerLocal::not_a_worker()),
+        }
+    }
+
+    fn set(&self, value: PyObject) -> PyResult<()> {
+        let mut slots = self.slots.lock().unwrap();
+        let slot = slots
+            .get_mut(&thread::current().id())
+            .ok_or_else(WorkerLocal::not_a_worker)?;
+        let old = slot.replace(value);
+        // Drop the old value outside the lock, since a decref can run Python
+        drop(slots);
+        drop(old);
+        Ok(())
+    }
+}
+
+// Iterator over finished jobs' outcomes, from Aqueue.results
+#[pyclass]
+struct Results {
+    receiver: Receiver<Outcome>,
+}
+
+#[pymethods]
+impl Results {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    // Wait in short slices with the GIL released so signals still get through
+    fn __next__(&self, py: Python<'_>) -> PyResult<Option<(u64, PyObject)>> {
+        loop {
+            match py.allow_threads(|| self.receiver.recv_timeout(SIGNAL_CHECK_INTERVAL)) {
+                Ok(outcome) => return Ok(Some(outcome)),
+                Err(RecvTimeoutError::Timeout) => py.check_signals()?,
+                Err(RecvTimeoutError::Disconnected) => return Ok(None),
+            }
+        }
+    }
+}
+
+/// A job for [`NativePool`].
+pub type NativeJob = Box<dyn FnOnce() + Send + 'static>;
+
+/// The thread pool behind `Aqueue`, driven with plain Rust closures, for
+/// using nornir from Rust code without Python.
+///
+/// Jobs go through the same queue and workers as Python jobs but never
+/// take the GIL, so Python need not be initialized. A job that panics is
+/// logged and counted as failed, and its worker carries on. Dropping the
+/// pool shuts it down.
+pub struct NativePool {
+    pool: ThreadPool,
+}
+
+impl NativePool {
+    /// Start a pool with `size` worker threads.
+    pub fn new(size: usize) -> Result<NativePool, String> {
+        let pool = ThreadPool::new(PoolConfig::native(size))?;
+        Ok(NativePool { pool })
+    }
+
+    /// Queue a job for the next free worker, blocking while the queue is
+    /// full. Fails only after shutdown.
+    pub fn execute(&self, job: NativeJob) -> Result<(), String> {
+        self.pool.execute_native(job).map_err(|e| e.to_string())
+    }
+
+    /// Jobs submitted and not yet finished.
+    pub fn active_jobs(&self) -> usize {
+        self.pool.active_jobs()
+    }
+
+    /// Jobs that have finished without panicking.
+    pub fn completed(&self) -> u64 {
+        self.pool.completed()
+    }
+
+    /// Block until every submitted job has finished.
+    pub fn wait_completion(&self) {
+        self.pool.wait_drained();
+    }
+
+    /// Stop accepting jobs, let the workers finish the queued ones, and
+    /// join them.
+    pub fn shutdown(&mut self) {
+        if self.pool.sender.is_some() {
+            self.pool.shutdown();
+        }
+    }
+}
+
+// Python module implementation
+#[pyclass]
+struct Aqueue {
+    pool: Option<ThreadPool>,
+    size: usize,
+    // Outlives the pool, so it can still be dumped after shutdown
+    flight_recorder: Option<Arc<FlightRecorder>>,
+}
+
+#[pymethods]
+impl Aqueue {
+    #[new]
+    #[pyo3(signature = (
+        max_workers=None,
+        group_batch_limit=DEFAULT_GROUP_BATCH_LIMIT,
+        lazy=false,
+        min_workers=0,
+        idle_timeout=DEFAULT_IDLE_TIMEOUT_SECS,
+        overflow="block",
+        lifo=false,
+        on_worker_start=None,
+        on_worker_stop=None,
+        on_complete_timed=None,
+        on_finally=None,
+        submit_middleware=None,
+        error_log_window=None,
+        stack_size=None,
+        warn_oversubscribe=true,
+        workload="cpu",
+        autoscale=false,
+        on_scale=None,
+        max_in_flight=None,
+        on_queue_empty=None,
+        init_failure="log",
+        init_retries=3,
+        soft_capacity=None,
+        hard_capacity=None,
+        on_watermark=None,
+        max_reschedules=DEFAULT_MAX_RESCHEDULES,
+        queue=None,
+        callback_thread=false,
+        housekeeping_interval=DEFAULT_HOUSEKEEPING_INTERVAL_SECS,
+        flight_recorder=None,
+        traceback_depth=None,
+        on_log=None,
+        sub_queues=None,
+        context_getter=None,
+        context_setter=None,
+        max_worker_rss=None,
+        backlog_alarm=None,
+        on_backlog_alarm=None,
+        ordered_callbacks=false,
+        on_full=None,
+        on_not_full=None,
+        picklable=false,
+        restore=None,
+        slow_job_threshold=None,
+        on_slow_job=None,
+        coalesce=None,
+        max_overtakes=None,
+        callback_metadata=false,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        py: Python<'_>,
+        max_workers: Option<usize>,
+        group_batch_limit: usize,
+        lazy: bool,
+        min_workers: usize,
+        idle_timeout: f64,
+        overflow: &str,
+        lifo: bool,
+        on_worker_start: Option<PyObject>,
+        on_worker_stop: Option<PyObject>,
+        on_complete_timed: Option<PyObject>,
+        on_finally: Option<PyObject>,
+        submit_middleware: Option<PyObject>,
+        error_log_window: Option<f64>,
+        stack_size: Option<usize>,
+        warn_oversubscribe: bool,
+        workload: &str,
+        autoscale: bool,
+        on_scale: Option<PyObject>,
+        max_in_flight: Option<usize>,
+        on_queue_empty: Option<PyObject>,
+        init_failure: &str,
+        init_retries: usize,
+        soft_capacity: Option<usize>,
+        hard_capacity: Option<usize>,
+        on_watermark: Option<PyObject>,
+        max_reschedules: usize,
+        queue: Option<PyObject>,
+        callback_thread: bool,
+        housekeeping_interval: f64,
+        flight_recorder: Option<usize>,
+        traceback_depth: Option<usize>,
+        on_log: Option<PyObject>,
+        sub_queues: Option<&PyDict>,
+        context_getter: Option<PyObject>,
+        context_setter: Option<PyObject>,
+        max_worker_rss: Option<usize>,
+        backlog_alarm: Option<(usize, f64)>,
+        on_backlog_alarm: Option<PyObject>,
+        ordered_callbacks: bool,
+        on_full: Option<PyObject>,
+        on_not_full: Option<PyObject>,
+        picklable: bool,
+        restore: Option<PathBuf>,
+        slow_job_threshold: Option<f64>,
+        on_slow_job: Option<PyObject>,
+        coalesce: Option<usize>,
+        max_overtakes: Option<usize>,
+        callback_metadata: bool,
+    ) -> PyResult<Self> {
+        // Use provided thread count or CPU count as default
+        let size = resolve_size(max_workers)?;
+        println!("Initializing thread pool with requested size: {}", size);
+
+        if !idle_timeout.is_finite() || idle_timeout <= 0.0 {
+            return Err(PyValueError::new_err("idle_timeout must be a positive number"));
+        }
+
+        if !housekeeping_interval.is_finite() || housekeeping_interval <= 0.0 {
+            return Err(PyValueError::new_err("housekeeping_interval must be a positive number"));
+        }
+
+        if max_in_flight == Some(0) {
+            return Err(PyValueError::new_err("max_in_flight must be greater than 0"));
+        }
+
+        if coalesce == Some(0) {
+            return Err(PyValueError::new_err("coalesce must be greater than 0"));
+        }
+
+        // A worker waiting for an in-flight permit with the GIL held would
+        // keep the running jobs from finishing to free one
+        if coalesce.is_some() && max_in_flight.is_some() {
+            return Err(PyValueError::new_err("coalesce cannot be combined with max_in_flight"));
+        }
+
+        if flight_recorder == Some(0) {
+            return Err(PyValueError::new_err("flight_recorder must be greater than 0"));
+        }
+
+        if hard_capacity == Some(0) {
+            return Err(PyValueError::new_err("hard_capacity must be greater than 0"));
+        }
+
+        if let Some(soft) = soft_capacity {
+            let hard = hard_capacity.unwrap_or(size * 2);
+            if soft >= hard {
+                return Err(PyValueError::new_err(format!(
+                    "soft_capacity ({}) must be less than the queue capacity ({})",
+                    soft, hard
+                )));
+            }
+        }
+
+        if let Some((_, duration)) = backlog_alarm {
+            if !duration.is_finite() || duration < 0.0 {
+                return Err(PyValueError::new_err("backlog_alarm duration must be a non-negative number"));
+            }
+        }
+
+        if let Some(threshold) = slow_job_threshold {
+            if !threshold.is_finite() || threshold <= 0.0 {
+                return Err(PyValueError::new_err("slow_job_threshold must be a positive number"));
+            }
+        } else if on_slow_job.is_some() {
+            return Err(PyValueError::new_err("on_slow_job requires slow_job_threshold"));
+        }
+
+        if restore.is_some() && !picklable {
+            return Err(PyValueError::new_err("restore requires picklable=True"));
+        }
+
+        if context_getter.is_some() != context_setter.is_some() {
+            return Err(PyValueError::new_err("context_getter and context_setter must be set together"));
+        }
+
+        if autoscale && lazy {
+            return Err(PyValueError::new_err("autoscale and lazy cannot both be set"));
+        }
+
+        if let Some(queue) = &queue {
+            let queue = queue.as_ref(py);
+            if !queue.hasattr("put")? || !queue.hasattr("get")? {
+                return Err(PyValueError::new_err("queue must have put() and get() methods"));
+            }
+            if lifo {
+                return Err(PyValueError::new_err("lifo and queue cannot both be set"));
+            }
+            // Evicting would mean taking a job back out of the queue
+            if overflow == "drop_old" {
+                return Err(PyValueError::new_err("overflow=\"drop_old\" cannot be used with a custom queue"));
+            }
+        }
+
+        if let Some(window) = error_log_window {
+            if !window.is_finite() || window <= 0.0 {
+                return Err(PyValueError::new_err("error_log_window must be a positive number"));
+            }
+        }
+
+        if let Some(stack_size) = stack_size {
+            if stack_size < MIN_STACK_SIZE {
+                return Err(PyValueError::new_err(format!(
+                    "stack_size must be at least {} bytes",
+                    MIN_STACK_SIZE
+                )));
+            }
+        }
+
+        let mut weights = Vec::new();
+        for (name, weight) in sub_queues.into_iter().flatten() {
+            let name: String = name.extract()?;
+            let weight: usize = weight.extract()?;
+            if weight == 0 {
+                return Err(PyValueError::new_err(format!(
+                    "Weight of sub-queue {:?} must be at least 1",
+                    name
+                )));
+            }
+            weights.push((name, weight));
+        }
+
+        let overflow = OverflowPolicy::parse(overflow).ok_or_else(|| {
+            PyValueError::new_err(format!(
+                "Unknown overflow policy {:?}; expected \"block\", \"drop_new\" or \"drop_old\"",
+                overflow
+            ))
+        })?;
+
+        let workload = Workload::parse(workload).ok_or_else(|| {
+            PyValueError::new_err(format!(
+                "Unknown workload {:?}; expected \"cpu\", \"io\" or \"blocking\"",
+                workload
+            ))
+        })?;
+
+        let init_failure = InitFailure::parse(init_failure, init_retries).ok_or_else(|| {
+            PyValueError::new_err(format!(
+                "Unknown init_failure policy {:?}; expected \"log\", \"retry\" or \"fail\"",
+                init_failure
+            ))
+        })?;
+
+        let config = PoolConfig {
+            size,
+            group_batch_limit,
+            lazy,
+            min_workers,
+            idle_timeout: Duration::from_secs_f64(idle_timeout),
+            overflow,
+            lifo,
+            on_worker_start,
+            on_worker_stop,
+            on_complete_timed,
+            on_finally,
+            callback_metadata,
+            submit_middleware,
+            error_log_window: error_log_window.map(Duration::from_secs_f64),
+            stack_size,
+            // I/O-bound pools are oversubscribed on purpose
+            warn_oversubscribe: warn_oversubscribe && workload == Workload::Cpu,
+            autoscale,
+            on_scale,
+            max_in_flight,
+            on_queue_empty,
+            init_failure,
+            soft_capacity,
+            hard_capacity,
+            on_watermark,
+            max_reschedules,
+            queue,
+            callback_thread,
+            ordered_callbacks,
+            interpreter: Interpreter::current(py),
+            housekeeping_interval: Duration::from_secs_f64(housekeeping_interval),
+            flight_recorder,
+            traceback_depth,
+            on_log,
+            sub_queues: weights,
+            context_getter,
+            context_setter,
+            max_worker_rss,
+            backlog_alarm: backlog_alarm
+                .map(|(threshold, duration)| (threshold, Duration::from_secs_f64(duration))),
+            on_backlog_alarm,
+            on_full,
+            on_not_full,
+            picklable,
+            slow_job_threshold: slow_job_threshold.map(Duration::from_secs_f64),
+            on_slow_job,
+            coalesce,
+            max_overtakes,
+        };
+
+        match ThreadPool::new(config) {
+            Ok(pool) => {
+                // Under "log" a failed start is never fatal, so there is nothing to wait for
+                if init_failure != InitFailure::Log {
+                    if let Err(e) = py.allow_threads(|| pool.wait_for_init()) {
+                        py.allow_threads(|| drop(pool));
+                        return Err(PyRuntimeError::new_err(e));
+                    }
+                }
+                if let Some(path) = restore {
+                    match pool.restore(py, path) {
+                        Ok(count) => println!("Restored {} jobs from checkpoint", count),
+                        Err(e) => {
+                            py.allow_threads(|| drop(pool));
+                            return Err(e);
+                        }
+                    }
+                }
+                let flight_recorder = pool.shared.flight_recorder.clone();
+                let aqueue = Aqueue { pool: Some(pool), size, flight_recorder };
+                println!("Thread pool created with {} workers", size);
+                Ok(aqueue)
+            },
+            Err(e) => Err(PyRuntimeError::new_err(e)),
+        }
+    }
+
+    #[getter]
+    fn pool_size(&self) -> usize {
+        self.size
+    }
+
+    #[getter]
+    fn active_workers(&self) -> usize {
+        if let Some(pool) = &self.pool {
+            pool.live_workers()
+        } else {
+            0
+        }
+    }
+
+    #[getter]
+    fn dropped(&self) -> usize {
+        if let Some(pool) = &self.pool {
+            pool.dropped()
+        } else {
+            0
+        }
+    }
+
+    #[getter]
+    fn worker_local(&self) -> PyResult<WorkerLocal> {
+        Ok(WorkerLocal {
+            slots: Arc::clone(&self.pool()?.shared.worker_slots),
+        })
+    }
+
+    #[getter]
+    fn in_flight(&self) -> usize {
+        self.pool.as_ref().map_or(0, ThreadPool::in_flight)
+    }
+
+    // Dicts of event, job_id and time (seconds since the epoch), oldest first
+    fn flight_recorder_dump(&self, py: Python<'_>) -> PyResult<Vec<PyObject>> {
+        let Some(recorder) = &self.flight_recorder else {
+            return Err(PyRuntimeError::new_err("The flight recorder is not enabled"));
+        };
+
+        recorder
+            .dump()
+            .into_iter()
+            .map(|(time, job_id, event)| {
+                let entry = PyDict::new(py);
+                entry.set_item("event", event)?;
+                entry.set_item("job_id", job_id)?;
+                let time = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+                entry.set_item("time", time.as_secs_f64())?;
+                Ok(entry.into())
+            })
+            .collect()
+    }
+
+    // Seconds since each live worker last woke between jobs. Idle workers
+    // refresh it every housekeeping_interval, so a large age means a worker
+    // has been inside one job that long.
+    fn heartbeats(&self) -> HashMap<usize, f64> {
+        let Some(pool) = &self.pool else { return HashMap::new() };
+        let status = pool.shared.worker_status.lock().unwrap();
+        status
+            .iter()
+            .filter(|(_, status)| !status.exited)
+            .map(|(&id, status)| (id, status.heartbeat.elapsed().as_secs_f64()))
+            .collect()
+    }
+
+    // The worker finishes its current job, if any, then parks without
+    // taking another until unquiesced
+    fn quiesce_worker(&self, id: usize) -> PyResult<()> {
+        let pool = self.pool()?;
+        if !pool.shared.set_quiesced(id, true) {
+            return Err(PyValueError::new_err(format!("No live worker with id {}", id)));
+        }
+        Ok(())
+    }
+
+    fn unquiesce_worker(&self, id: usize) -> PyResult<()> {
+        let pool = self.pool()?;
+        if !pool.shared.set_quiesced(id, false) {
+            return Err(PyValueError::new_err(format!("No live worker with id {}", id)));
+        }
+        Ok(())
+    }
+
+    // One dict per worker, by id. Names are looked up after the lock is
+    // released, since that runs Python.
+    fn worker_states(&self, py: Python<'_>) -> PyResult<Vec<PyObject>> {
+        let Some(pool) = &self.pool else { return Ok(Vec::new()) };
+        let mut states: Vec<_> = {
+            let status = pool.shared.worker_status.lock().unwrap();
+            status
+                .iter()
+                .map(|(&id, status)| {
+                    let current = status.current.as_ref().map(|(job_id, func, since)| (*job_id, func.clone_ref(py), *since));
+                    (id, status.exited, status.quiesced, current, status.jobs_executed, status.rss_growth)
+                })
+                .collect()
+        };
+        states.sort_by_key(|(id, ..)| *id);
+
+        states
+            .into_iter()
+            .map(|(id, exited, quiesced, current, jobs_executed, rss_growth)| {
+                let state = PyDict::new(py);
+                state.set_item("id", id)?;
+                let label = match (&current, exited, quiesced) {
+                    (_, true, _) => "dead",
+                    (Some(_), false, true) => "quiescing",
+                    (None, false, true) => "quiesced",
+                    (Some(_), false, false) => "busy",
+                    (None, false, false) => "idle",
+                };
+                state.set_item("state", label)?;
+                let (job_id, name, since) = match current {
+                    Some((job_id, func, since)) => {
+                        let since = since.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+                        (job_id, Some(job_name(py, &func)), Some(since.as_secs_f64()))
+                    }
+                    None => (None, None, None),
+                };
+                state.set_item("current_job_id", job_id)?;
+                state.set_item("current_job_name", name)?;
+                state.set_item("jobs_executed", jobs_executed)?;
+                state.set_item("rss_growth", rss_growth)?;
+                state.set_item("busy_since", since)?;
+                Ok(state.into())
+            })
+            .collect()
+    }
+
+    #[getter]
+    fn running(&self) -> bool {
+        if let Some(pool) = &self.pool {
+            pool.is_running.load(Ordering::SeqCst)
+        } else {
+            false
+        }
+    }
+
+    #[pyo3(signature = (job, weight=1, category=None, metadata=None))]
+    fn add_job(
+        &self,
+        py: Python<'_>,
+        job: PyObject,
+        weight: usize,
+        category: Option<String>,
+        metadata: Option<Py<PyDict>>,
+    ) -> PyResult<u64> {
+        check_weight(weight)?;
+        let pool = self.pool()?;
+        let job = pool.apply_middleware(py, job)?;
+        let metadata = metadata.map(|metadata| metadata.into_py(py));
+        // Release the GIL in case the queue is full, so workers can
+        // finish their jobs and make room
+        Ok(py.allow_threads(|| pool.execute_tagged(job, weight, category, metadata))?)
+    }
+
+    // Replaces any earlier rate for the category, starting with a full bucket
+    fn set_category_rate(&self, category: String, rate: f64) -> PyResult<()> {
+        if !rate.is_finite() || rate <= 0.0 {
+            return Err(PyValueError::new_err("rate must be a positive number"));
+        }
+        let pool = self.pool()?;
+        pool.shared.category_rates.lock().unwrap().insert(category, TokenBucket::new(rate));
+        Ok(())
+    }
+
+    fn add_job_async(slf: PyRef<'_, Self>, py: Python<'_>, job: PyObject) -> PyResult<AsyncSubmit> {
+        let job = slf.pool()?.apply_middleware(py, job)?;
+        Ok(AsyncSubmit {
+            aqueue: slf.into(),
+            job: Some(job),
+            retry: ASYNC_SUBMIT_RETRY,
+        })
+    }
+
+    // Caller-runs: a full queue makes the submitting thread do the work
+    fn submit_or_run(&self, py: Python<'_>, job: PyObject) -> PyResult<&'static str> {
+        let pool = self.pool()?;
+        let job = pool.apply_middleware(py, job)?;
+        let inline = job.clone_ref(py);
+
+        match py.allow_threads(|| pool.try_execute(job))? {
+            Some(_) => Ok("queued"),
+            None => {
+                inline.call0(py)?;
+                Ok("inline")
+            }
+        }
+    }
+
+    // Only marks the running job; the worker puts it back once it returns
+    #[pyo3(signature = (delay=0.0))]
+    fn reschedule(&self, delay: f64) -> PyResult<()> {
+        if !delay.is_finite() || delay < 0.0 {
+            return Err(PyValueError::new_err("delay must be a non-negative number"));
+        }
+        let pool = self.pool()?;
+        let Some((job_id, reschedules)) = pool.shared.current_job() else {
+            return Err(PyRuntimeError::new_err("reschedule() must be called from a job running on this pool"));
+        };
+        if reschedules >= pool.shared.max_reschedules {
+            return Err(PyRuntimeError::new_err(format!(
+                "Job {} has already been rescheduled {} times",
+                job_id, reschedules
+            )));
+        }
+        RESCHEDULE.with(|reschedule| reschedule.set(Some(Duration::from_secs_f64(delay))));
+        Ok(())
+    }
+
+    // Tag a line with the running job's id, forwarding it straight to on_log
+    fn log(&self, py: Python<'_>, message: String) -> PyResult<()> {
+        let pool = self.pool()?;
+        let Some((job_id, _)) = pool.shared.current_job() else {
+            return Err(PyRuntimeError::new_err("log() must be called from a job running on this pool"));
+        };
+        match pool.shared.on_log.get(py) {
+            Some(callback) => {
+                if let Err(e) = callback.call1(py, (job_id, message)) {
+                    eprintln!("on_log callback error for job {}: {:?}", job_id, e);
+                }
+            }
+            None => pool.shared.job_logs.lock().unwrap().entry(job_id).or_default().push(message),
+        }
+        Ok(())
+    }
+
+    // Hands the lines over, so each job's buffer is freed once read
+    fn job_logs(&self, job_id: u64) -> Vec<String> {
+        let Some(pool) = &self.pool else { return Vec::new() };
+        pool.shared.job_logs.lock().unwrap().remove(&job_id).unwrap_or_default()
+    }
+
+    // The depth is read right after the send, before the GIL is taken back,
+    // so it is fresher than a separate call could be
+    #[pyo3(signature = (job, weight=1))]
+    fn add_job_with_depth(&self, py: Python<'_>, job: PyObject, weight: usize) -> PyResult<(u64, usize)> {
+        check_weight(weight)?;
+        let pool = self.pool()?;
+        let job = pool.apply_middleware(py, job)?;
+        Ok(py.allow_threads(|| pool.execute(job, weight).map(|id| (id, pool.queue_len())))?)
+    }
+
+    #[pyo3(signature = (func, *args, map_result=None, result_type=None, metadata=None, **kwargs))]
+    #[allow(clippy::too_many_arguments)]
+    fn submit(
+        &self,
+        py: Python<'_>,
+        func: PyObject,
+        args: &PyTuple,
+        map_result: Option<PyObject>,
+        result_type: Option<PyObject>,
+        metadata: Option<Py<PyDict>>,
+        kwargs: Option<&PyDict>,
+    ) -> PyResult<PyObject> {
+        let pool = self.pool()?;
+        if let Some(result_type) = &result_type {
+            // isinstance rejects what it cannot check, such as List[int],
+            // so that fails here instead of on the worker
+            py.None().as_ref(py).is_instance(result_type.as_ref(py))?;
+        }
+
+        // Bind the arguments up front so the worker only makes a plain call
+        let func = if args.is_empty() && kwargs.is_none_or(|kwargs| kwargs.is_empty()) {
+            func
+        } else {
+            let mut bound = vec![func];
+            bound.extend(args.iter().map(|arg| arg.into_py(py)));
+            let partial = py.import("functools")?.getattr("partial")?;
+            partial.call(PyTuple::new(py, bound), kwargs)?.into()
+        };
+        let func = pool.apply_middleware(py, func)?;
+        let metadata = metadata.map(|metadata| metadata.into_py(py));
+        let (_, future) = self.submit_future(py, func, map_result, result_type, metadata)?;
+        Ok(future)
+    }
+
+    // The future plus its bound cancel, for callers that only want something
+    // to call later; cancel() is False once a worker has started the job
+    fn submit_cancellable(&self, py: Python<'_>, job: PyObject) -> PyResult<(PyObject, PyObject)> {
+        let future = self.submit(py, job, PyTuple::empty(py), None, None, None, None)?;
+        let cancel = future.getattr(py, "cancel")?;
+        Ok((future, cancel))
+    }
+
+    // submit's future together with the id it was queued under, so callers
+    // matching ids from job_logs or pending_jobs keep no mapping of their own
+    fn submit_tracked(&self, py: Python<'_>, job: PyObject) -> PyResult<(u64, PyObject)> {
+        let job = self.pool()?.apply_middleware(py, job)?;
+        self.submit_future(py, job, None, None, None)
+    }
+
+    // A future that already holds the value, for call sites that sometimes
+    // have the result up front; nothing is queued
+    fn submit_value(&self, py: Python<'_>, value: PyObject) -> PyResult<PyObject> {
+        self.pool()?;
+        let future = py.import("concurrent.futures")?.getattr("Future")?.call0()?;
+        future.call_method1("set_result", (value,))?;
+        Ok(future.into())
+    }
+
+    fn add_job_event(&self, py: Python<'_>, job: PyObject, event: PyObject) -> PyResult<u64> {
+        let pool = self.pool()?;
+        let job = pool.apply_middleware(py, job)?;
+        Ok(py.allow_threads(|| pool.execute_event(job, event))?)
+    }
+
+    fn submit_into(
+        &self,
+        py: Python<'_>,
+        job: PyObject,
+        results_list: Py<PyList>,
+        index: usize,
+    ) -> PyResult<u64> {
+        let pool = self.pool()?;
+        let job = pool.apply_middleware(py, job)?;
+        Ok(py.allow_threads(|| pool.execute_into(job, results_list, index))?)
+    }
+
+    fn add_job_grouped(&self, py: Python<'_>, job: PyObject, group: u64) -> PyResult<u64> {
+        let pool = self.pool()?;
+        let job = pool.apply_middleware(py, job)?;
+        Ok(py.allow_threads(|| pool.execute_grouped(job, group))?)
+    }
+
+    fn add_job_to(&self, py: Python<'_>, queue_name: &str, job: PyObject) -> PyResult<u64> {
+        let pool = self.pool()?;
+        let index = pool
+            .shared
+            .weighted
+            .index(queue_name)
+            .ok_or_else(|| PyValueError::new_err(format!("Unknown sub-queue {:?}", queue_name)))?;
+        let job = pool.apply_middleware(py, job)?;
+        Ok(py.allow_threads(|| pool.execute_weighted(job, index))?)
+    }
+
+    // Jobs waiting in each add_job_to sub-queue
+    fn queue_depths(&self) -> HashMap<String, usize> {
+        self.pool
+            .as_ref()
+            .map_or_else(Vec::new, |pool| pool.shared.weighted.depths())
+            .into_iter()
+            .collect()
+    }
+
+    fn add_job_idempotent(&self, py: Python<'_>, job: PyObject, key: String) -> PyResult<u64> {
+        let pool = self.pool()?;
+        let job = pool.apply_middleware(py, job)?;
+        Ok(py.allow_threads(|| pool.execute_idempotent(job, key))?)
+    }
+
+    fn add_job_replace(&self, py: Python<'_>, job: PyObject, key: String) -> PyResult<u64> {
+        let pool = self.pool()?;
+        let job = pool.apply_middleware(py, job)?;
+        Ok(py.allow_threads(|| pool.execute_replacing(job, key))?)
+    }
 
-                match message {
-                    Message::NewJob(job) => {
-                        // Execute the Python callback with GIL
-                        Python::with_gil(|py| {
-                            if let Err(e) = job.call0(py) {
-                                eprintln!("Worker {}: Job execution error: {:?}", id, e);
-                            }
-                        });
+    fn add_shutdown_job(&self, py: Python<'_>, job: PyObject) -> PyResult<u64> {
+        let pool = self.pool()?;
+        let job = pool.apply_middleware(py, job)?;
+        Ok(pool.execute_shutdown(job)?)
+    }
 
-                        // Decrement job count after executing
-                        let mut count = job_count.lock().unwrap();
-                        *count = count.saturating_sub(1);
-                    }
-                    Message::Terminate => {
-                        println!("Worker {} terminating", id);
-                        break;
-                    }
-                }
-            }
-        });
+    fn add_job_main(&self, py: Python<'_>, job: PyObject) -> PyResult<u64> {
+        let pool = self.pool()?;
+        let job = pool.apply_middleware(py, job)?;
+        Ok(pool.execute_main(job)?)
+    }
 
-        Worker {
-            id,
-            thread: Some(thread),
+    // Run up to max_jobs of the add_job_main jobs, oldest first, returning
+    // how many ran
+    #[pyo3(signature = (max_jobs=None))]
+    fn pump_main(&self, py: Python<'_>, max_jobs: Option<usize>) -> PyResult<usize> {
+        let threading = py.import("threading")?;
+        if !threading.call_method0("current_thread")?.is(threading.call_method0("main_thread")?) {
+            return Err(PyRuntimeError::new_err("pump_main must be called from the main thread"));
         }
+
+        let pool = self.pool()?;
+        let mut ran = 0;
+        while max_jobs.is_none_or(|max_jobs| ran < max_jobs) {
+            // Not held while the job runs, since it may queue more
+            let job = pool.shared.main_jobs.lock().unwrap().pop_front();
+            let Some(job) = job else { break };
+            if let Err(e) = job.func.call0(py) {
+                eprintln!(
+                    "Main thread: Job {} ({}) failed:\n{}",
+                    job.id,
+                    job_name(py, &job.func),
+                    format_traceback(py, &e, pool.shared.traceback_depth)
+                );
+            }
+            ran += 1;
+        }
+        Ok(ran)
     }
-}
 
-// Thread pool implementation
-struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<Sender<Message>>,
-    is_running: Arc<AtomicBool>,
-    job_count: Arc<Mutex<usize>>,
-}
+    fn main_pending(&self) -> usize {
+        self.pool.as_ref().map_or(0, |pool| pool.shared.main_jobs.lock().unwrap().len())
+    }
 
-impl ThreadPool {
-    fn new(size: usize) -> Result<ThreadPool, String> {
-        if size == 0 {
-            return Err("Thread pool size must be greater than 0".to_string());
-        }
+    fn main_thread_queue(slf: PyRef<'_, Self>) -> PyResult<MainThreadQueue> {
+        slf.pool()?;
+        Ok(MainThreadQueue { aqueue: slf.into() })
+    }
 
-        // Get CPU count and calculate recommended maximum
-        let cpu_count = num_cpus::get();
-        let max_recommended = cpu_count * 2;
+    // Keys are compared by hash, so colliding keys only lose parallelism
+    #[pyo3(signature = (job, key, priority=0))]
+    fn add_job_keyed(&self, py: Python<'_>, job: PyObject, key: &PyAny, priority: i64) -> PyResult<u64> {
+        let key = key.hash()? as u64;
+        let pool = self.pool()?;
+        let job = pool.apply_middleware(py, job)?;
+        Ok(py.allow_threads(|| pool.execute_keyed(job, key, priority))?)
+    }
 
-        // Warn if thread count exceeds recommended maximum
-        if size > max_recommended {
-            eprintln!(
-                "Warning: Requested thread count ({}) exceeds recommended maximum ({}) for {} CPUs",
-                size, max_recommended, cpu_count
-            );
-        }
+    // Shorthand for add_job, so the pool reads naturally as a decorator
+    fn __call__(&self, py: Python<'_>, job: PyObject) -> PyResult<u64> {
+        self.add_job(py, job, 1, None, None)
+    }
 
-        println!("Creating thread pool with {} workers (CPU count: {})", size, cpu_count);
+    fn task(slf: PyRef<'_, Self>, py: Python<'_>, func: PyObject) -> PyResult<Py<Task>> {
+        let task = Py::new(
+            py,
+            Task {
+                aqueue: slf.into(),
+                func: func.clone_ref(py),
+            },
+        )?;
 
-        // Create channel with appropriate buffer size
-        let (sender, receiver) = bounded::<Message>(size * 2); // Increase buffer size based on thread count
-        let receiver = Arc::new(Mutex::new(receiver));
-        let mut workers = Vec::with_capacity(size);
-        let is_running = Arc::new(AtomicBool::new(true));
-        let job_count = Arc::new(Mutex::new(0));
-
-        // Create exactly the number of workers requested
-        for id in 0..size {
-            workers.push(Worker::new(
-                id,
-                Arc::clone(&receiver),
-                Arc::clone(&job_count)
-            ));
-        }
+        // Carry over __name__, __doc__ and __wrapped__ so introspection
+        // and inspect.signature see the original function
+        py.import("functools")?
+            .getattr("update_wrapper")?
+            .call1((task.clone_ref(py), func))?;
+        Ok(task)
+    }
 
-        Ok(ThreadPool {
-            workers,
-            sender: Some(sender),
-            is_running,
-            job_count,
+    fn add_job_in_group(&self, py: Python<'_>, job: PyObject, group_id: u64) -> PyResult<u64> {
+        let pool = self.pool()?;
+        let job = pool.apply_middleware(py, job)?;
+        Ok(py.allow_threads(|| pool.execute_in_group(job, group_id))?)
+    }
+
+    #[pyo3(signature = (group_id, timeout=None))]
+    fn wait_group(&self, py: Python<'_>, group_id: u64, timeout: Option<f64>) -> PyResult<PyObject> {
+        let pool = self.pool()?;
+        wait_until(py, pool, timeout, None, |pool, slice| {
+            pool.wait_group_for(group_id, slice).pending == 0
+        })?;
+
+        let tally = pool.take_group(group_id);
+        let summary = PyDict::new(py);
+        summary.set_item("completed", tally.completed)?;
+        summary.set_item("failed", tally.failed)?;
+        summary.set_item("cancelled", tally.cancelled)?;
+        summary.set_item("dropped", tally.dropped)?;
+        summary.set_item("pending", tally.pending)?;
+        Ok(summary.into())
+    }
+
+    fn cancel_group(&self, py: Python<'_>, group_id: u64) -> PyResult<PyObject> {
+        let pool = self.pool()?;
+        let (cancelled, running) = py.allow_threads(|| pool.shared.cancel_group(group_id));
+        let summary = PyDict::new(py);
+        summary.set_item("cancelled", cancelled)?;
+        summary.set_item("running", running)?;
+        Ok(summary.into())
+    }
+
+    fn reserve(slf: PyRef<'_, Self>, py: Python<'_>, n: usize) -> PyResult<Reservation> {
+        let pool = slf.pool()?;
+        // Release the GIL while waiting, so workers can free up slots
+        py.allow_threads(|| pool.reserve(n))?;
+        let slots = Arc::clone(&pool.shared.slots);
+        Ok(Reservation {
+            aqueue: slf.into(),
+            slots,
+            remaining: n,
         })
     }
 
-    fn execute(&self, job: Job) -> Result<(), String> {
-        if !self.is_running.load(Ordering::SeqCst) {
-            return Err("ThreadPool has been shutdown".to_string());
+    fn new_limiter(&self, max_concurrent: usize) -> PyResult<Limiter> {
+        if max_concurrent == 0 {
+            return Err(PyValueError::new_err("max_concurrent must be greater than 0"));
         }
 
-        // Increment the job counter
-        {
-            let mut count = self.job_count.lock().unwrap();
-            *count += 1;
+        Ok(Limiter {
+            semaphore: Arc::new(Semaphore::new(max_concurrent)),
+            max_concurrent,
+        })
+    }
+
+    #[pyo3(signature = (job, limiter, weight=1))]
+    fn add_job_limited(&self, py: Python<'_>, job: PyObject, limiter: &Limiter, weight: usize) -> PyResult<u64> {
+        check_weight(weight)?;
+        // A job heavier than the limiter could never get its permits
+        if weight > limiter.max_concurrent {
+            return Err(PyValueError::new_err(format!(
+                "weight ({}) cannot exceed the limiter's max_concurrent ({})",
+                weight, limiter.max_concurrent
+            )));
         }
 
-        // Send the job to the channel
-        match &self.sender {
-            Some(sender) => {
-                if let Err(e) = sender.send(Message::NewJob(job)) {
-                    return Err(format!("Failed to send job: {:?}", e));
-                }
-                Ok(())
-            }
-            None => Err("ThreadPool has been shutdown".to_string()),
+        let pool = self.pool()?;
+        let job = pool.apply_middleware(py, job)?;
+        let semaphore = Arc::clone(&limiter.semaphore);
+        Ok(py.allow_threads(|| pool.execute_limited(job, semaphore, weight))?)
+    }
+
+    fn burst(&self, extra: usize, ttl: f64) -> PyResult<()> {
+        if !ttl.is_finite() || ttl <= 0.0 {
+            return Err(PyValueError::new_err("ttl must be a positive number"));
         }
+
+        self.pool()?.burst(extra, Duration::from_secs_f64(ttl))?;
+        Ok(())
     }
 
-    fn shutdown(&mut self) {
-        println!("Shutting down thread pool");
-        self.is_running.store(false, Ordering::SeqCst);
-        
-        // Send termination messages to all workers
-        if let Some(sender) = &self.sender {
-            for _ in &self.workers {
-                let _ = sender.send(Message::Terminate);
+    fn reduce(
+        &self,
+        py: Python<'_>,
+        func: PyObject,
+        iterable: &PyAny,
+        reducer: PyObject,
+        initial: PyObject,
+    ) -> PyResult<PyObject> {
+        let pool = self.pool()?;
+        let (results_tx, results_rx) = unbounded();
+        let mut acc = initial;
+        let mut pending = 0usize;
+
+        for (index, item) in iterable.iter()?.enumerate() {
+            let apply = Apply {
+                func: func.clone_ref(py),
+                item: item?.into(),
+                index,
+                results: results_tx.clone(),
+            };
+            py.allow_threads(|| pool.execute_apply(apply))?;
+            pending += 1;
+
+            // Fold whatever has already finished so results don't pile up
+            while let Ok((_, result)) = results_rx.try_recv() {
+                pending -= 1;
+                acc = reducer.call1(py, (acc, result?))?;
             }
         }
-        
-        // Drop the sender to close the channel
-        self.sender.take();
-        
-        // Join all worker threads
-        for worker in &mut self.workers {
-            if let Some(thread) = worker.thread.take() {
-                match thread.join() {
-                    Ok(_) => println!("Worker thread joined successfully"),
-                    Err(e) => eprintln!("Error joining worker thread: {:?}", e),
+
+        // Only the submitted jobs hold senders now, so the channel
+        // disconnects if any of them are discarded before running
+        drop(results_tx);
+
+        while pending > 0 {
+            match py.allow_threads(|| results_rx.recv_timeout(SIGNAL_CHECK_INTERVAL)) {
+                Ok((_, result)) => {
+                    pending -= 1;
+                    acc = reducer.call1(py, (acc, result?))?;
+                }
+                Err(RecvTimeoutError::Timeout) => py.check_signals()?,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(PyRuntimeError::new_err(format!(
+                        "reduce lost {} results: their jobs were discarded before running",
+                        pending
+                    )));
                 }
             }
         }
-        
-        // Reset job count
-        let mut count = self.job_count.lock().unwrap();
-        *count = 0;
+
+        Ok(acc)
     }
 
-    fn active_jobs(&self) -> usize {
-        *self.job_count.lock().unwrap()
+    #[pyo3(signature = (func, iterable, max_outstanding, ordered=true))]
+    fn parallel_map(
+        slf: PyRef<'_, Self>,
+        func: PyObject,
+        iterable: &PyAny,
+        max_outstanding: usize,
+        ordered: bool,
+    ) -> PyResult<ParallelMap> {
+        slf.pool()?;
+        if max_outstanding == 0 {
+            return Err(PyValueError::new_err("max_outstanding must be greater than 0"));
+        }
+
+        let (results_tx, results_rx) = unbounded();
+        Ok(ParallelMap {
+            aqueue: slf.into(),
+            func,
+            source: Some(iterable.iter()?.into()),
+            max_outstanding,
+            ordered,
+            results_tx,
+            results_rx,
+            submitted: 0,
+            outstanding: 0,
+            held: BTreeMap::new(),
+            next_index: 0,
+        })
     }
-}
 
-impl Drop for ThreadPool {
-    fn drop(&mut self) {
-        if self.sender.is_some() {
-            self.shutdown();
+    fn active_jobs(&self) -> usize {
+        if let Some(pool) = &self.pool {
+            pool.active_jobs()
+        } else {
+            0
         }
     }
-}
 
-// Python module implementation
-#[pyclass]
-struct Aqueue {
-    pool: Option<ThreadPool>,
-    size: usize,
-}
+    fn queued_jobs(&self) -> usize {
+        self.pool.as_ref().map_or(0, ThreadPool::queued_jobs)
+    }
 
-#[pymethods]
-impl Aqueue {
-    #[new]
-    fn new(max_workers: Option<usize>) -> PyResult<Self> {
-        // Use provided thread count or CPU count as default
-        let size = max_workers.unwrap_or_else(|| num_cpus::get());
-        println!("Initializing thread pool with requested size: {}", size);
-        
-        match ThreadPool::new(size) {
-            Ok(pool) => {
-                let aqueue = Aqueue { pool: Some(pool), size };
-                println!("Thread pool created with {} workers", size);
-                Ok(aqueue)
-            },
-            Err(e) => Err(PyRuntimeError::new_err(e)),
+    fn queue_capacity(&self) -> PyResult<usize> {
+        Ok(self.pool()?.capacity())
+    }
+
+    fn set_queue_capacity(&self, n: usize) -> PyResult<()> {
+        let pool = self.pool()?;
+        if n == 0 {
+            return Err(PyValueError::new_err("queue capacity must be greater than 0"));
         }
+        if let Some(soft) = pool.shared.soft_capacity {
+            if n <= soft {
+                return Err(PyValueError::new_err(format!(
+                    "queue capacity ({}) must be greater than soft_capacity ({})",
+                    n, soft
+                )));
+            }
+        }
+        pool.set_capacity(n);
+        Ok(())
     }
 
-    #[getter]
-    fn pool_size(&self) -> usize {
-        self.size
+    #[pyo3(signature = (with_metadata=false))]
+    fn pending_jobs(&self, py: Python<'_>, with_metadata: bool) -> Vec<PyObject> {
+        let Some(pool) = &self.pool else {
+            return Vec::new();
+        };
+
+        // Names are looked up after the queue locks are released, since
+        // that can run arbitrary Python
+        pool.pending_jobs(py)
+            .into_iter()
+            .map(|(id, func, metadata)| {
+                let name = job_name(py, &func);
+                if with_metadata {
+                    (id, name, metadata).into_py(py)
+                } else {
+                    (id, name).into_py(py)
+                }
+            })
+            .collect()
     }
 
-    #[getter]
-    fn active_workers(&self) -> usize {
-        if let Some(pool) = &self.pool {
-            pool.workers.len()
+    // Written to a temporary file that then replaces the old checkpoint, so
+    // a crash part way through never leaves a truncated one behind
+    fn checkpoint(&self, py: Python<'_>, path: PathBuf) -> PyResult<usize> {
+        let pool = self.pool()?;
+        if !pool.picklable {
+            return Err(PyRuntimeError::new_err("checkpoint requires a pool created with picklable=True"));
+        }
+
+        let jobs: Vec<PyObject> = pool.pending_jobs(py).into_iter().map(|(_, func, _)| func).collect();
+        let count = jobs.len();
+        let data = py.import("pickle")?.call_method1("dumps", (PyList::new(py, jobs),))?;
+        let data: &[u8] = data.extract()?;
+
+        let mut partial = path.clone().into_os_string();
+        partial.push(".tmp");
+        py.allow_threads(|| fs::write(&partial, data).and_then(|_| fs::rename(&partial, &path)))?;
+        Ok(count)
+    }
+
+    #[pyo3(signature = (clear=false))]
+    fn last_error(&self, py: Python<'_>, clear: bool) -> PyResult<Option<PyObject>> {
+        let pool = self.pool()?;
+        let mut last_error = pool.shared.last_error.lock().unwrap();
+        Ok(if clear {
+            last_error.take()
         } else {
-            0
+            last_error.as_ref().map(|error| error.clone_ref(py))
+        })
+    }
+
+    fn results(&self) -> PyResult<Results> {
+        Ok(Results {
+            receiver: self.pool()?.shared.results(),
+        })
+    }
+
+    fn estimated_drain_time(&self) -> f64 {
+        self.pool.as_ref().map_or(0.0, ThreadPool::estimated_drain_time)
+    }
+
+    fn stats(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let stats = PyDict::new(py);
+        let pool = self.pool.as_ref();
+        stats.set_item("active_jobs", pool.map_or(0, ThreadPool::active_jobs))?;
+        stats.set_item("queued_jobs", pool.map_or(0, ThreadPool::queued_jobs))?;
+        stats.set_item("active_workers", pool.map_or(0, ThreadPool::live_workers))?;
+        stats.set_item("dropped", pool.map_or(0, ThreadPool::dropped))?;
+        stats.set_item("completed", pool.map_or(0, ThreadPool::completed))?;
+        stats.set_item("weighted_load", pool.map_or(0, ThreadPool::weighted_load))?;
+        stats.set_item("in_flight", pool.map_or(0, ThreadPool::in_flight))?;
+        stats.set_item("queue_len", pool.map_or(0, ThreadPool::queue_len))?;
+        stats.set_item("avg_exec_time", pool.map_or(0.0, ThreadPool::avg_exec_time))?;
+        stats.set_item("avg_cpu_time", pool.map_or(0.0, ThreadPool::avg_cpu_time))?;
+        stats.set_item(
+            "gil_wait_total",
+            pool.map_or(0.0, |pool| pool.gil_wait_total().as_secs_f64()),
+        )?;
+        stats.set_item("dispatch_latency", pool.map_or_else(Vec::new, ThreadPool::dispatch_latency))?;
+        Ok(stats.into())
+    }
+
+    // Read and zero the job counters in one swap. stats() is not reset.
+    fn drain_stats(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let counters = self.pool.as_ref().map(ThreadPool::drain_stats).unwrap_or_default();
+        let stats = PyDict::new(py);
+        stats.set_item("submitted", counters.submitted)?;
+        stats.set_item("completed", counters.completed)?;
+        stats.set_item("failed", counters.failed)?;
+        stats.set_item("retried", counters.retried)?;
+        stats.set_item("cancelled", counters.cancelled)?;
+        stats.set_item("expired", counters.expired)?;
+        Ok(stats.into())
+    }
+
+    #[pyo3(signature = (timeout=None, progress_callback=None))]
+    fn wait_completion(
+        &self,
+        py: Python<'_>,
+        timeout: Option<f64>,
+        progress_callback: Option<PyObject>,
+    ) -> PyResult<bool> {
+        match &self.pool {
+            Some(pool) => wait_until(py, pool, timeout, progress_callback.as_ref(), |pool, slice| {
+                pool.wait_idle_for(slice)
+            }),
+            None => Ok(true),
         }
     }
 
-    #[getter]
-    fn running(&self) -> bool {
-        if let Some(pool) = &self.pool {
-            pool.is_running.load(Ordering::SeqCst)
+    fn wait_idle(&self, py: Python<'_>, timeout: f64) -> PyResult<&'static str> {
+        let pool = match &self.pool {
+            Some(pool) => pool,
+            None => return Ok("shutdown"),
+        };
+
+        if wait_until(py, pool, Some(timeout), None, ThreadPool::wait_idle_for)? {
+            Ok("idle")
         } else {
-            false
+            Ok("timeout")
+        }
+    }
+
+    #[pyo3(signature = (timeout=None))]
+    fn flush(&self, py: Python<'_>, timeout: Option<f64>) -> PyResult<bool> {
+        let pool = match &self.pool {
+            Some(pool) => pool,
+            None => return Ok(true),
+        };
+
+        // Jobs submitted after this point are not waited for
+        let target = pool.submitted();
+        wait_until(py, pool, timeout, None, |pool, slice| pool.wait_settled_for(target, slice))
+    }
+
+    // How many jobs completed while waiting, which may overshoot n. Failed,
+    // cancelled and dropped jobs don't count.
+    #[pyo3(signature = (n, timeout=None))]
+    fn wait_for_count(&self, py: Python<'_>, n: u64, timeout: Option<f64>) -> PyResult<u64> {
+        let pool = match &self.pool {
+            Some(pool) => pool,
+            None => return Ok(0),
+        };
+
+        let start = pool.completed();
+        wait_until(py, pool, timeout, None, |pool, slice| pool.wait_completed_for(start + n, slice))?;
+        Ok(pool.completed() - start)
+    }
+
+    fn throughput(&self, window_secs: u64) -> PyResult<f64> {
+        if !(1..=THROUGHPUT_WINDOW_SECS).contains(&window_secs) {
+            return Err(PyValueError::new_err(format!(
+                "window_secs must be between 1 and {}",
+                THROUGHPUT_WINDOW_SECS
+            )));
         }
+        Ok(self.pool.as_ref().map_or(0.0, |pool| pool.throughput(window_secs)))
     }
 
-    fn add_job(&self, job: Job) -> PyResult<()> {
+    // Against the running total rather than a count taken at the call, so
+    // waves of work can be waited for without races between them
+    #[pyo3(signature = (target, timeout=None))]
+    fn wait_until_completed(&self, py: Python<'_>, target: u64, timeout: Option<f64>) -> PyResult<bool> {
+        let pool = match &self.pool {
+            Some(pool) => pool,
+            None => return Ok(false),
+        };
+
+        wait_until(py, pool, timeout, None, |pool, slice| pool.wait_completed_for(target, slice))
+    }
+
+    // Stop accepting jobs and hand back the callables of those not started,
+    // in submission order; running jobs finish as usual
+    fn drain_pending(&self) -> Vec<PyObject> {
+        let pool = match &self.pool {
+            Some(pool) => pool,
+            None => return Vec::new(),
+        };
+
+        pool.is_running.store(false, Ordering::SeqCst);
+        let mut jobs = pool.shared.cancel_queued();
+        jobs.sort_by_key(|job| job.id);
+        jobs.into_iter().map(|job| job.func).collect()
+    }
+
+    // Stop accepting jobs but let the workers drain the queue
+    fn close(&self) {
         if let Some(pool) = &self.pool {
-            match pool.execute(job) {
-                Ok(_) => Ok(()),
-                Err(e) => Err(PyRuntimeError::new_err(e)),
+            pool.is_running.store(false, Ordering::SeqCst);
+        }
+    }
+
+    fn join(&mut self, py: Python<'_>) -> PyResult<()> {
+        if self.running() {
+            return Err(PyValueError::new_err("Pool is still running; call close() first"));
+        }
+
+        self.shutdown_pool(py);
+        Ok(())
+    }
+
+    // Running jobs cannot be interrupted, so once the budget runs out
+    // nothing else is started, and the workers are joined in the background
+    // as those jobs return
+    fn graceful_stop(&mut self, py: Python<'_>, budget_secs: f64) -> PyResult<usize> {
+        if !budget_secs.is_finite() || budget_secs < 0.0 {
+            return Err(PyValueError::new_err("budget_secs must be a non-negative number"));
+        }
+        let Some(pool) = &self.pool else { return Ok(0) };
+
+        pool.is_running.store(false, Ordering::SeqCst);
+        let drained = wait_until(py, pool, Some(budget_secs), None, |pool, slice| pool.wait_idle_for(slice))?;
+        if drained {
+            self.shutdown_pool(py);
+            return Ok(0);
+        }
+
+        let cancelled = pool.shared.cancel_queued().len() + pool.shared.cancel_shutdown_jobs(py);
+        let abandoned = cancelled + pool.active_jobs();
+        if let Some(mut pool) = self.pool.take() {
+            thread::spawn(move || pool.shutdown());
+        }
+        Ok(abandoned)
+    }
+
+    #[pyo3(signature = (wait=true, cancel_futures=false))]
+    fn shutdown(&mut self, py: Python<'_>, wait: bool, cancel_futures: bool) {
+        if cancel_futures {
+            if let Some(pool) = &self.pool {
+                pool.shared.cancel_queued();
             }
-        } else {
-            Err(PyRuntimeError::new_err("ThreadPool has been shutdown"))
+        }
+
+        if wait {
+            self.shutdown_pool(py);
+        } else if let Some(mut pool) = self.pool.take() {
+            // Workers still finish the queued jobs; nobody waits for them
+            thread::spawn(move || pool.shutdown());
         }
     }
 
-    fn active_jobs(&self) -> usize {
+    fn __traverse__(&self, visit: PyVisit<'_>) -> Result<(), PyTraverseError> {
         if let Some(pool) = &self.pool {
-            *pool.job_count.lock().unwrap()
-        } else {
-            0
+            pool.shared.traverse(&visit)?;
+        }
+        Ok(())
+    }
+
+    fn __clear__(&mut self) {
+        if let Some(pool) = &self.pool {
+            pool.shared.clear();
         }
     }
 
@@ -252,20 +5828,183 @@ impl Aqueue {
 
     fn __exit__(
         &mut self,
+        py: Python<'_>,
         _exc_type: Option<&PyAny>,
         _exc_val: Option<&PyAny>,
         _exc_tb: Option<&PyAny>,
     ) -> PyResult<()> {
+        self.shutdown_pool(py);
+        Ok(())
+    }
+}
+
+// Block until `wait` reports the pool done or the timeout elapses,
+// returning whether it finished
+fn wait_until(
+    py: Python<'_>,
+    pool: &ThreadPool,
+    timeout: Option<f64>,
+    progress_callback: Option<&PyObject>,
+    wait: impl Fn(&ThreadPool, Duration) -> bool + Send + Sync,
+) -> PyResult<bool> {
+    let deadline = match timeout {
+        Some(secs) if secs.is_nan() || secs < 0.0 => {
+            return Err(PyValueError::new_err("timeout must be non-negative"));
+        }
+        Some(secs) if secs.is_infinite() => None,
+        Some(secs) => Some(Instant::now() + Duration::from_secs_f64(secs)),
+        None => None,
+    };
+
+    // Outstanding jobs when the wait began, raised if more arrive meanwhile
+    let mut total = pool.active_jobs();
+
+    // Wait in short slices with the GIL released, checking for signals
+    // in between so Ctrl-C raises KeyboardInterrupt promptly
+    loop {
+        let slice = match deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                remaining.min(SIGNAL_CHECK_INTERVAL)
+            }
+            None => SIGNAL_CHECK_INTERVAL,
+        };
+
+        let done = py.allow_threads(|| wait(pool, slice));
+
+        if let Some(callback) = progress_callback {
+            let remaining = pool.active_jobs();
+            total = total.max(remaining);
+            callback.call1(py, (remaining, total))?;
+        }
+
+        if done {
+            return Ok(true);
+        }
+
+        py.check_signals()?;
+
+        if let Some(deadline) = deadline {
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+// Some cgroup-limited containers report no CPUs at all
+fn cpu_count() -> usize {
+    num_cpus::get().max(1)
+}
+
+// The requested size, or the CPU count, capped by NORNIR_MAX_WORKERS
+fn resolve_size(max_workers: Option<usize>) -> PyResult<usize> {
+    let size = max_workers.unwrap_or_else(cpu_count);
+    // Set but empty counts as unset
+    let cap = match std::env::var(MAX_WORKERS_ENV) {
+        Ok(value) if value.trim().is_empty() => None,
+        Ok(value) => match value.trim().parse::<usize>() {
+            Ok(cap) if cap > 0 => Some(cap),
+            _ => {
+                return Err(PyValueError::new_err(format!(
+                    "{} must be a positive integer, got {:?}",
+                    MAX_WORKERS_ENV, value
+                )))
+            }
+        },
+        Err(_) => None,
+    };
+
+    match cap {
+        Some(cap) if size > cap => {
+            println!("Capping thread pool size {} to {} from {}", size, cap, MAX_WORKERS_ENV);
+            Ok(cap)
+        }
+        // A size of 0 can only come from max_workers, which ThreadPool::new rejects
+        _ => Ok(size),
+    }
+}
+
+fn check_weight(weight: usize) -> PyResult<()> {
+    if weight == 0 {
+        return Err(PyValueError::new_err("weight must be greater than 0"));
+    }
+    Ok(())
+}
+
+impl Aqueue {
+    fn pool(&self) -> Result<&ThreadPool, PoolError> {
+        self.pool.as_ref().ok_or(PoolError::Shutdown)
+    }
+
+    // Queue a job with a fresh future the worker resolves, returning both
+    fn submit_future(
+        &self,
+        py: Python<'_>,
+        func: PyObject,
+        map_result: Option<PyObject>,
+        result_type: Option<PyObject>,
+        metadata: Option<PyObject>,
+    ) -> PyResult<(u64, PyObject)> {
+        let pool = self.pool()?;
+        let future: PyObject = py.import("concurrent.futures")?.getattr("Future")?.call0()?.into();
+        let job_future = future.clone_ref(py);
+        let job_id = py.allow_threads(|| pool.execute_future(func, job_future, map_result, result_type, metadata))?;
+        let hook = CancelHook {
+            shared: Arc::downgrade(&pool.shared),
+            job_id,
+        };
+        future.call_method1(py, "add_done_callback", (hook,))?;
+        Ok((job_id, future))
+    }
+
+    // Shut the pool down with the GIL released, since workers need it to
+    // finish queued jobs and run their stop callbacks before they can join
+    fn shutdown_pool(&mut self, py: Python<'_>) {
         if let Some(mut pool) = self.pool.take() {
-            pool.shutdown();
+            py.allow_threads(|| pool.shutdown());
+        }
+    }
+}
+
+impl Drop for Aqueue {
+    fn drop(&mut self) {
+        if self.pool.is_some() {
+            Python::with_gil(|py| self.shutdown_pool(py));
         }
-        Ok(())
     }
 }
 
+// Hold the workers of every pool in the process before their next job,
+// for inspecting state in a debugger
+#[pyfunction]
+fn pause_all() {
+    PAUSED.store(true, Ordering::SeqCst);
+}
+
+#[pyfunction]
+fn resume_all() {
+    let _guard = PAUSE_LOCK.lock().unwrap();
+    PAUSED.store(false, Ordering::SeqCst);
+    RESUMED.notify_all();
+}
+
 /// A Python module implemented in Rust.
 #[pymodule]
-fn nornir(_py: Python, m: &PyModule) -> PyResult<()> {
+fn nornir(py: Python, m: &PyModule) -> PyResult<()> {
     m.add_class::<Aqueue>()?;
+    m.add_class::<AsyncSubmit>()?;
+    m.add_class::<Limiter>()?;
+    m.add_class::<MainThreadQueue>()?;
+    m.add_class::<ParallelMap>()?;
+    m.add_class::<Reservation>()?;
+    m.add_class::<Results>()?;
+    m.add_class::<Task>()?;
+    m.add_class::<WorkerLocal>()?;
+    m.add_function(wrap_pyfunction!(pause_all, m)?)?;
+    m.add_function(wrap_pyfunction!(resume_all, m)?)?;
+    m.add("QueueFullError", py.get_type::<QueueFullError>())?;
+    m.add("PoolShutdownError", py.get_type::<PoolShutdownError>())?;
+    m.add("JobExecutionError", py.get_type::<JobExecutionError>())?;
     Ok(())
 }
\ No newline at end of file
@@ -1,4 +1,233 @@
-from typing import Optional, Callable, Any
+from concurrent.futures import Future
+from typing import Optional, Callable, Any, Dict, Generator, Generic, Iterable, List, Tuple, TypeVar
+
+T = TypeVar("T")
+R = TypeVar("R")
+A = TypeVar("A")
+
+__all__ = ["Aqueue", "AsyncSubmit", "Limiter", "ParallelMap", "Reservation", "Results", "Task", "WorkerLocal", "pause_all", "resume_all", "QueueFullError", "PoolShutdownError", "JobExecutionError"]
+
+class QueueFullError(RuntimeError):
+    """Raised when a job is rejected because the pool's queue is full."""
+
+class PoolShutdownError(RuntimeError):
+    """Raised when a job is submitted to a pool that has been shut down."""
+
+class JobExecutionError(RuntimeError):
+    """Reserved for job failures; nothing raises it yet.
+    
+    A failing job's own exception reaches the caller unchanged, through its
+    future, reduce, parallel_map and last_error, so it can be caught by its
+    usual type. This class is exported so handlers can already name it.
+    """
+
+def pause_all() -> None:
+    """Stop the workers of every pool in the process from starting new jobs.
+    
+    A debugging aid for inspecting a whole system's state, not a production
+    control. Running jobs finish, then each worker waits, and jobs submitted
+    meanwhile stay queued. Pools created while paused start paused. A pool
+    being shut down ignores the pause so its shutdown can complete.
+    """
+    ...
+
+def resume_all() -> None:
+    """Let every pool's workers take jobs again after pause_all()."""
+    ...
+
+class Limiter:
+    """A concurrency cap shared by the jobs submitted with it.
+    
+    Created with Aqueue.new_limiter and passed to Aqueue.add_job_limited. A worker
+    that picks up a limited job waits for a free permit before running it and
+    returns the permit afterwards, even if the job raises.
+    """
+    
+    @property
+    def max_concurrent(self) -> int:
+        """The maximum number of jobs holding this limiter that may run at once."""
+        ...
+
+    @property
+    def available(self) -> int:
+        """The number of permits currently free."""
+        ...
+
+class ParallelMap(Generic[R]):
+    """Iterator over func's results, obtained from Aqueue.parallel_map.
+    
+    Items are pulled from the source only as results are consumed, so at most
+    max_outstanding are submitted and not yet yielded at any time. Waiting
+    for a result releases the GIL.
+    """
+    
+    def __iter__(self) -> 'ParallelMap[R]':
+        ...
+
+    def __next__(self) -> R:
+        """Block until the next result is available.
+        
+        An item whose job raised raises that exception here instead of
+        yielding a result; iterating again continues with the next item.
+        
+        Raises:
+            StopIteration: Once the source is exhausted and every result has
+                           been yielded.
+            QueueFullError: If the overflow policy dropped an item's job.
+            PoolShutdownError: If the pool is shut down while waiting.
+            KeyboardInterrupt: If interrupted by a signal while waiting.
+        """
+        ...
+
+class MainThreadQueue:
+    """The queue of main-thread jobs of a pool, created with Aqueue.main_thread_queue.
+    
+    It shares the pool's add_job_main queue, so it can be handed to code that
+    should only schedule main-thread work without giving it the whole pool.
+    """
+    
+    def put(self, job: Callable[[], Any]) -> int:
+        """Queue a job to run on the main thread; the same as Aqueue.add_job_main.
+        
+        Args:
+            job: A callable to run on the main thread.
+        
+        Returns:
+            The job's id, unique within the pool.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def pump(self, max_jobs: Optional[int] = None) -> int:
+        """Run the queued jobs on the calling thread; the same as Aqueue.pump_main.
+        
+        Args:
+            max_jobs: The most jobs to run in this call. If None, run until none
+                      are left.
+        
+        Returns:
+            The number of jobs that ran.
+        
+        Raises:
+            RuntimeError: If called from any thread other than the main thread.
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def __len__(self) -> int:
+        """The number of jobs waiting, as Aqueue.main_pending."""
+        ...
+
+class Reservation:
+    """Queue slots held by one producer, created with Aqueue.reserve.
+    
+    Jobs submitted through the reservation use its slots, so they are admitted
+    without competing with other producers. Unused slots go back to the pool
+    when the reservation is released, when its with block exits or when it is
+    garbage collected.
+    """
+    
+    @property
+    def remaining(self) -> int:
+        """The number of slots still unused."""
+        ...
+
+    def submit(self, job: Callable[[], Any]) -> int:
+        """Add a job to the pool using one of the reserved slots.
+        
+        Args:
+            job: A callable that will be executed by one of the worker threads.
+        
+        Returns:
+            The job's id, unique within this pool.
+        
+        Raises:
+            ValueError: If no slots are left.
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def release(self) -> None:
+        """Return the unused slots to the pool."""
+        ...
+
+    def __enter__(self) -> 'Reservation':
+        ...
+
+    def __exit__(self, exc_type: Any, exc_val: Any, exc_tb: Any) -> None:
+        """Release the unused slots."""
+        ...
+
+class AsyncSubmit:
+    """Awaitable returned by Aqueue.add_job_async.
+    
+    Awaiting it queues the job and returns its id. While the queue is full it
+    waits on the event loop, retrying after 1ms and backing off to at most
+    50ms between attempts, instead of blocking the thread. It can be awaited
+    once; wrap it in a coroutine to pass it to asyncio.create_task.
+    """
+    
+    def __await__(self) -> Generator[Any, None, int]:
+        ...
+
+class Task:
+    """A function wrapped by Aqueue.task.
+    
+    Calling it submits the function with the given arguments instead of running
+    it. The wrapper carries the function's __name__, __doc__ and __wrapped__, so
+    inspect.signature reports the original signature.
+    """
+    
+    def __call__(self, *args: Any, **kwargs: Any) -> Future:
+        """Submit the wrapped function, like Aqueue.submit(func, *args, **kwargs)."""
+        ...
+
+class Results:
+    """Iterator over finished jobs, obtained from Aqueue.results.
+    
+    Yields (job_id, outcome) pairs, where outcome is the job's return value or
+    the exception it raised. Waiting for the next one releases the GIL.
+    """
+    
+    def __iter__(self) -> 'Results':
+        ...
+
+    def __next__(self) -> Tuple[int, Any]:
+        """Block until another job finishes.
+        
+        Raises:
+            StopIteration: Once the pool has been shutdown and every outcome
+                           has been yielded.
+            KeyboardInterrupt: If interrupted by a signal while waiting.
+        """
+        ...
+
+class WorkerLocal:
+    """Per-worker storage, obtained from Aqueue.worker_local.
+    
+    Each worker thread of the pool has its own slot, seeded with what
+    on_worker_start returned, so jobs can share state such as a database
+    connection with the other jobs that run on the same worker. A slot is
+    released when its worker exits.
+    """
+    
+    def get(self, default: Any = None) -> Any:
+        """Return the current worker's value, or default if it has none.
+        
+        Raises:
+            RuntimeError: If called from outside the pool's worker threads.
+        """
+        ...
+
+    def set(self, value: Any) -> None:
+        """Replace the current worker's value.
+        
+        Raises:
+            RuntimeError: If called from outside the pool's worker threads.
+        """
+        ...
 
 class Aqueue:
     """A high-performance thread pool implementation for Python.
@@ -8,75 +237,1697 @@ class Aqueue:
     
     Warning:
         Setting max_workers higher than (CPU count * 2) will generate a warning as it
-        may not provide better performance and could cause resource contention.
+        may not provide better performance and could cause resource contention. Pass
+        workload="io" or warn_oversubscribe=False to silence it.
+    
+    Note:
+        The pool takes part in Python's cyclic garbage collection. If a pool that is
+        part of an unreachable reference cycle gets collected, its callbacks and any
+        jobs still queued are released without running.
+    
+    Note:
+        A pool created inside a subinterpreter runs its jobs and callbacks in that
+        interpreter rather than the main one. The extension module can only be
+        imported by one interpreter per process, and the pool must be shut down
+        before its interpreter is destroyed.
     
     Example:
         >>> with Aqueue(4) as pool:  # Creates a pool with 4 worker threads
         ...     pool.add_job(lambda: print("Hello"))
     """
     
-    def __init__(self, max_workers: Optional[int] = None) -> None:
+    def __init__(
+        self,
+        max_workers: Optional[int] = None,
+        group_batch_limit: int = 8,
+        lazy: bool = False,
+        min_workers: int = 0,
+        idle_timeout: float = 60.0,
+        overflow: str = "block",
+        lifo: bool = False,
+        on_worker_start: Optional[Callable[[int], Any]] = None,
+        on_worker_stop: Optional[Callable[[int], Any]] = None,
+        on_complete_timed: Optional[Callable[..., Any]] = None,
+        on_finally: Optional[Callable[..., Any]] = None,
+        submit_middleware: Optional[Callable[[Callable[..., Any]], Callable[..., Any]]] = None,
+        error_log_window: Optional[float] = None,
+        stack_size: Optional[int] = None,
+        warn_oversubscribe: bool = True,
+        workload: str = "cpu",
+        autoscale: bool = False,
+        on_scale: Optional[Callable[[int, int, int, int], Any]] = None,
+        max_in_flight: Optional[int] = None,
+        on_queue_empty: Optional[Callable[[], Any]] = None,
+        init_failure: str = "log",
+        init_retries: int = 3,
+        soft_capacity: Optional[int] = None,
+        hard_capacity: Optional[int] = None,
+        on_watermark: Optional[Callable[[str], Any]] = None,
+        max_reschedules: int = 100,
+        queue: Optional[Any] = None,
+        callback_thread: bool = False,
+        housekeeping_interval: float = 5.0,
+        flight_recorder: Optional[int] = None,
+        traceback_depth: Optional[int] = None,
+        on_log: Optional[Callable[[int, str], Any]] = None,
+        sub_queues: Optional[Dict[str, int]] = None,
+        context_getter: Optional[Callable[[], Any]] = None,
+        context_setter: Optional[Callable[[Any], Optional[Callable[[], Any]]]] = None,
+        max_worker_rss: Optional[int] = None,
+        backlog_alarm: Optional[Tuple[int, float]] = None,
+        on_backlog_alarm: Optional[Callable[[str], Any]] = None,
+        ordered_callbacks: bool = False,
+        on_full: Optional[Callable[[], Any]] = None,
+        on_not_full: Optional[Callable[[], Any]] = None,
+        picklable: bool = False,
+        restore: Optional[str] = None,
+        slow_job_threshold: Optional[float] = None,
+        on_slow_job: Optional[Callable[[Optional[int], str, float], Any]] = None,
+        coalesce: Optional[int] = None,
+        max_overtakes: Optional[int] = None,
+        callback_metadata: bool = False,
+    ) -> None:
         """Initialize the thread pool.
         
         Args:
             max_workers: The number of worker threads to create. If None, defaults to
-                        the number of CPU cores available. A warning is issued if this
-                        exceeds (CPU count * 2).
+                        the number of CPU cores available, or 1 if none are
+                        reported. A warning is issued if this exceeds
+                        (CPU count * 2). The NORNIR_MAX_WORKERS environment
+                        variable, read at construction, caps it either way.
+            group_batch_limit: The maximum number of jobs from the same group a worker
+                        runs back to back before returning to the shared queue. Must
+                        be greater than 0.
+            lazy: If True, start with only min_workers threads and spawn more on demand,
+                        up to max_workers, as jobs arrive. Idle workers above the
+                        min_workers floor retire after idle_timeout seconds.
+            min_workers: The number of workers a lazy or autoscaling pool starts with
+                        and shrinks back to. Cannot exceed max_workers.
+            idle_timeout: Seconds a lazy pool's worker may sit idle before retiring,
+                        or an autoscaling pool must stay quiet before it shrinks.
+            overflow: What submission does when the queue is full. "block" waits for
+                        space (with the GIL released), "drop_new" rejects the incoming
+                        job with QueueFullError, and "drop_old" evicts the oldest
+                        queued job to make room. Discarded jobs are counted in dropped.
+            lifo: If True, jobs from add_job run newest first instead of in submission
+                        order, which suits workloads where the latest request matters
+                        most. Under sustained load old jobs can starve. Grouped and
+                        limited jobs keep their usual order.
+            on_worker_start: Called with the worker id on the worker's own thread when
+                        it starts, including workers spawned lazily. Its return value
+                        becomes the worker's worker_local value.
+            on_worker_stop: Called with the worker id on the worker's own thread when
+                        it exits, whether by shutdown or idle retirement. Exceptions
+                        raised by on_worker_stop are logged and otherwise ignored;
+                        for on_worker_start, see init_failure.
+            on_complete_timed: Called on the worker thread after each job submitted with
+                        add_job, add_job_grouped or add_job_limited returns
+                        successfully, with (job_id, result, exec_time). exec_time is
+                        the seconds spent inside the job itself, excluding queueing and
+                        GIL waits. Exceptions it raises are logged and ignored.
+            on_finally: Called exactly once per job that was given an id (any job
+                        except reduce's) when it reaches a terminal state, with
+                        (job_id, status). status is "completed", "failed",
+                        "dropped" if the overflow policy discarded the job, or
+                        "cancelled" if its future was cancelled first. It runs
+                        after on_complete_timed, so it suits cleanup work. Exceptions
+                        it raises are logged and ignored.
+            submit_middleware: Called with every callable submitted through add_job,
+                        submit, task, submit_into, add_job_grouped, add_job_in_group,
+                        add_job_limited or a Reservation, on the submitting thread.
+                        It returns the callable to queue instead, e.g. one wrapped
+                        with a timeout or tracing. An exception it raises rejects the
+                        submission and propagates to the caller. For submit, it
+                        receives fn with its arguments already bound.
+            error_log_window: If set, job errors are not logged one by one. Failures
+                        are counted by exception type and flushed every this many
+                        seconds as one line per type, e.g.
+                        "1523 jobs failed: ConnectionRefusedError (last 10s)". The
+                        last window is flushed on shutdown.
+            stack_size: Stack size in bytes for each worker thread, for jobs that
+                        recurse deeply. Defaults to the platform's thread stack size.
+                        Every worker reserves this much address space, so large values
+                        multiply quickly with max_workers. Must be at least 65536.
+            warn_oversubscribe: Whether to warn when max_workers exceeds
+                        (CPU count * 2).
+            workload: What the jobs mostly do: "cpu" (the default), or "io" (alias
+                        "blocking") for jobs that mostly wait, where oversubscribing
+                        is intended and never warned about.
+            autoscale: If True, start with min_workers threads and let a controller
+                        thread resize the pool between min_workers and max_workers.
+                        It samples the queue every 0.1 seconds. Once more jobs have
+                        been waiting than there are idle workers for three samples in
+                        a row, it adds one worker per sample until that stops being
+                        true. Once the queue has been empty with workers idle for
+                        idle_timeout seconds, it retires one worker per sample. A pool
+                        with no workers grows as soon as a job is queued. Cannot be
+                        combined with lazy.
+            on_scale: Called on the controller thread after each autoscaling change,
+                        with (old_workers, new_workers, queue_len, busy_workers).
+                        Without it, each change is printed to stderr. Exceptions it
+                        raises are logged and ignored.
+            max_in_flight: If set, at most this many jobs run at once, even with
+                        more workers. Spare workers still take jobs off the queue
+                        and wait with them for a turn, so dispatch stays warm while
+                        headroom is kept free. Must be greater than 0.
+            on_queue_empty: Called with no arguments on the worker thread that takes
+                        the last queued job, once per transition from a backlog to
+                        an empty queue. Workers may still be running jobs. With idle
+                        workers, each job briefly queues on its own, so this can
+                        fire for single jobs too. Exceptions it raises are logged
+                        and ignored.
+            init_failure: What a worker does when on_worker_start raises. "log" (the
+                        default) logs the error and runs the worker with no
+                        worker_local value. "fail" logs it and the worker exits
+                        without running jobs. "retry" calls on_worker_start again
+                        up to init_retries times, pausing 0.1 seconds and doubling
+                        each time, then gives up like "fail". Under "fail" and
+                        "retry", construction waits for the initial workers to
+                        start and raises RuntimeError if none of them did.
+            init_retries: How many times "retry" calls on_worker_start again after
+                        the first failure.
+            soft_capacity: A queue length that gives early warning before the queue
+                        fills. Submissions are still accepted above it, but
+                        on_watermark is called, or a warning printed without one.
+                        Must be less than hard_capacity.
+            hard_capacity: How many jobs the queue holds before the overflow policy
+                        applies. Defaults to twice max_workers. Pair it with
+                        overflow="drop_new" to reject jobs above it with
+                        QueueFullError. Must be greater than 0.
+            on_watermark: Called with "high" when the queue grows past
+                        soft_capacity and "low" once it is back at or below it,
+                        once per crossing. It runs on the submitting or worker
+                        thread that caused the crossing. Exceptions it raises are
+                        logged and ignored.
+            max_reschedules: How many times one job may call reschedule() before
+                        it raises instead. Guards against jobs that requeue
+                        themselves forever.
+            queue: An object with put(job) and get() that decides the order
+                        add_job() jobs run in, for trying out priority or
+                        fairness policies in Python; queue.Queue works as is.
+                        The pool puts each job's callable after taking a queue
+                        slot for it, and a worker calls get() once per put(),
+                        holding the GIL, to pick what to run. get() must return
+                        each callable it was given exactly once, and both
+                        methods must be safe to call from several threads at
+                        once. An exception from put() is raised to the
+                        submitter; get() must not raise. Other kinds of jobs
+                        (groups, keys, limiters) bypass it. Cannot be combined
+                        with lifo or overflow="drop_old", since the pool cannot
+                        take a job back out to evict it.
+            callback_thread: Call on_complete_timed and on_finally from one
+                        dedicated thread, in the order jobs finished, instead of
+                        on the worker. Workers move on to the next job straight
+                        away, which helps when callbacks do real work such as
+                        database writes. Callbacks may then still be running
+                        when wait_completion() returns; shutdown() waits for
+                        them all.
+            housekeeping_interval: The longest, in seconds, a waiting worker goes
+                        without waking to refresh its heartbeat and check its
+                        idle timeout. Waking costs nothing when jobs arrive, so
+                        the default is long. Must be positive.
+            flight_recorder: Keep the last this many job lifecycle events for
+                        flight_recorder_dump(). The buffer is allocated up front,
+                        so recording costs a lock and no allocation. Must be
+                        greater than 0.
+            traceback_depth: How many of the innermost frames to keep when a
+                        failed job's traceback is logged to stderr. None logs the
+                        whole traceback. Has no effect with error_log_window,
+                        which logs summaries only.
+            on_log: Called with (job_id, message) on the job's thread for each
+                        log() call, instead of buffering the line for
+                        job_logs(). Exceptions it raises are logged and ignored.
+            sub_queues: Named sub-queues for add_job_to(), mapped to their
+                        weights. Workers serve them in weighted round-robin
+                        order: up to weight jobs from one sub-queue, then on to
+                        the next non-empty one, in the dict's order. Weights must
+                        be at least 1.
+            context_getter: Called with no arguments on the submitting thread
+                        each time a job is added, to capture a context such as
+                        OpenTelemetry's opentelemetry.context.get_current. The
+                        value is kept with the job. Map items are not covered.
+            context_setter: Called on the worker with the captured context just
+                        before the job runs. It may return a callable, run once
+                        the job finishes, that restores the worker's previous
+                        context, for example
+                        ``lambda ctx: functools.partial(context.detach, context.attach(ctx))``.
+                        Must be given together with context_getter. Exceptions
+                        from either hook are logged and the job runs anyway.
+            max_worker_rss: Recycle a worker once the jobs it has run have grown
+                        the process's resident set size by more than this many
+                        bytes. The worker exits after its current job, running
+                        on_worker_stop, and a fresh one takes its place. RSS is
+                        per process, so growth is approximated by the change
+                        across each job, which also counts whatever other
+                        workers allocated meanwhile. Only measured on Linux;
+                        elsewhere it has no effect.
+            backlog_alarm: A (threshold, seconds) pair. The alarm is raised once
+                        queue_len from stats() has stayed above threshold for
+                        that many seconds, and cleared once it has stayed at or
+                        below it as long, so a brief spike or dip changes
+                        nothing. It is checked on each submission and by workers
+                        between jobs and at least every housekeeping_interval.
+                        The duration must be non-negative.
+            on_backlog_alarm: Called with "raised" or "cleared" once per change
+                        of the backlog alarm, on the submitting or worker thread
+                        that noticed it. Without it a warning is printed when
+                        the alarm is raised. Exceptions it raises are logged and
+                        ignored.
+            ordered_callbacks: Call on_complete_timed and on_finally in the order
+                        jobs were submitted rather than the order they finished,
+                        for callbacks with ordered side effects such as appending
+                        to a log. A job's callbacks wait until every earlier
+                        job has finished, including failed, dropped and
+                        cancelled ones, so one slow job holds back the callbacks
+                        of all later ones. Implies callback_thread. Jobs still
+                        held back at shutdown have their callbacks skipped.
+            on_full: Called with no arguments when a submission takes the last
+                        free queue slot, the moment from which producers block
+                        or the overflow policy applies. Fires again only after
+                        on_not_full. Runs on the submitting thread; exceptions
+                        it raises are logged and ignored.
+            on_not_full: Called with no arguments once a full queue has a tenth
+                        of its capacity (at least one slot) free again, on the
+                        worker thread that freed the slot. Slots held by
+                        reserve() count as taken.
+            picklable: Reject jobs that pickle cannot serialize, with TypeError
+                        at submission, so checkpoint() can save the whole queue.
+                        Jobs must then be module-level functions, or partials
+                        and instances built from them, rather than lambdas or
+                        nested functions. Each job is pickled once when it is
+                        submitted to check.
+            restore: Path of a file written by checkpoint(). Its jobs are queued
+                        once the workers have started, in their original order,
+                        as plain add_job jobs; the constructor blocks while the
+                        queue is full. Requires picklable=True.
+            slow_job_threshold: Seconds after which a running job counts as slow.
+                        A monitor thread scans the workers a few times per
+                        threshold (and at least every housekeeping_interval)
+                        and reports each slow job once. The job is not
+                        interrupted. Must be positive.
+            on_slow_job: Called on the monitor thread with the job's id (None for
+                        reduce and parallel_map items), its __qualname__ and the
+                        seconds it has run so far. Without it a warning is
+                        printed. Exceptions it raises are logged and ignored.
+                        Requires slow_job_threshold.
+            coalesce: Most add_job jobs a worker runs per GIL acquisition. After
+                        one job it keeps the GIL and takes up to coalesce - 1
+                        more that are already queued, which cuts the switching
+                        cost of many tiny jobs (roughly halving the time for a
+                        million no-op jobs). The tradeoff is fairness: other
+                        workers and Python threads wait for the whole batch, so
+                        a slow job late in a batch delays everyone, and a
+                        non-add_job message taken mid-batch goes back to the
+                        end of the queue. None (the default) acquires the GIL
+                        once per job. Cannot be combined with max_in_flight.
+            max_overtakes: How many times an add_job_keyed job can have a
+                        higher-priority job queued ahead of it on its key.
+                        After that it is not passed again, so it runs once the
+                        jobs already ahead of it have, whatever the priority of
+                        later jobs. This bounds how long a low-priority job can
+                        be starved, counted in jobs rather than seconds. 0
+                        makes each key first come, first served. None (the
+                        default) lets priority always win. Only add_job_keyed
+                        jobs have a priority, so no other submission method is
+                        affected.
+            callback_metadata: If True, on_complete_timed is called with
+                        (job_id, result, exec_time, metadata) and on_finally
+                        with (job_id, status, metadata), where metadata is the
+                        dict given to add_job or submit, or None. Off by
+                        default so existing callbacks keep their signature.
+        
+        Raises:
+            RuntimeError: If max_workers is 0, min_workers exceeds it, a worker
+                        thread cannot be created, or every initial worker failed
+                        on_worker_start under init_failure "fail" or "retry". The
+                        message lists each worker's exception.
+            ValueError: If idle_timeout or error_log_window is not a positive number,
+                        stack_size is too small, overflow or workload is not a
+                        known value, max_in_flight or hard_capacity is 0,
+                        soft_capacity is not below hard_capacity, autoscale and
+                        lazy are both set, init_failure is not "log", "retry"
+                        or "fail", NORNIR_MAX_WORKERS is set to anything but
+                        a positive integer, restore is given without picklable,
+                        on_slow_job without a positive slow_job_threshold, or
+                        coalesce is 0 or combined with max_in_flight.
+            OSError: If the restore file cannot be read.
         """
         ...
 
-    def add_job(self, job: Callable[[], Any]) -> None:
+    def add_job(
+        self,
+        job: Callable[[], Any],
+        weight: int = 1,
+        category: Optional[str] = None,
+        metadata: Optional[Dict[str, Any]] = None,
+    ) -> int:
         """Add a job to the thread pool's queue.
         
         Args:
             job: A callable (function or method) that will be executed by one of the
                  worker threads.
+            weight: How many units the job counts as in the weighted_load stat.
+            category: Throttle the job by this category's rate from
+                 set_category_rate. Categories without a rate, and jobs without
+                 a category, run as soon as a worker is free.
+            metadata: A dict that travels with the job, such as a request id.
+                 It is listed by pending_jobs(with_metadata=True) and passed to
+                 the callbacks of a pool created with callback_metadata=True.
+                 The dict itself is kept, not a copy.
+        
+        Returns:
+            The job's id, unique within this pool.
         
         Raises:
-            RuntimeError: If the thread pool has been shutdown.
+            PoolShutdownError: If the thread pool has been shutdown.
+            QueueFullError: If the queue is full and the overflow policy is "drop_new".
+            ValueError: If weight is 0.
         """
         ...
 
-    def stop(self) -> None: ...
+    def set_category_rate(self, category: str, rate: float) -> None:
+        """Limit jobs in a category to rate starts per second.
+        
+        Lets one pool respect several independent downstream limits, such as
+        "email jobs at 10/s, everything else unlimited". Bursts of up to one
+        second's worth (at least one job) start at once. A worker that picks up
+        a job over the rate reserves the next token for it and puts it aside
+        until then, so throttled jobs keep their order and never hold a worker
+        while they wait. Setting a new rate replaces the old one.
+        
+        Args:
+            category: The category passed to add_job.
+            rate: Jobs per second. Must be positive.
+        
+        Raises:
+            ValueError: If rate is not a positive number.
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
 
-    def active_jobs(self) -> int:
-        """Get the number of jobs currently being processed.
+    def log(self, message: str) -> None:
+        """Record a log line for the job that is running, tagged with its id.
+        
+        Call it from inside a job so its output can be told apart in a shared
+        pool. The line goes to on_log if set, or is kept for job_logs(). To
+        route the standard logging module, add a handler whose emit() calls
+        pool.log(self.format(record)).
+        
+        Args:
+            message: The line to record.
+        
+        Raises:
+            RuntimeError: If not called from a job running on this pool.
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def job_logs(self, job_id: int) -> List[str]:
+        """Collect the lines a job recorded with log(), oldest first.
+        
+        The lines are handed over and forgotten, so a second call returns only
+        what was logged since. Nothing is buffered while on_log is set.
+        
+        Args:
+            job_id: The id returned when the job was submitted.
         
         Returns:
-            The number of jobs that have been submitted but not yet completed.
+            The job's lines, or an empty list if it logged nothing.
         """
         ...
 
-    def __enter__(self) -> 'Aqueue':
-        """Context manager entry.
+    def add_job_with_depth(self, job: Callable[[], Any], weight: int = 1) -> Tuple[int, int]:
+        """Add a job like add_job and report the queue depth right after.
+        
+        For producers that back off as the queue fills. The depth is read
+        straight after the job is queued, before control returns to Python, so
+        it tracks the queue more closely than polling separately.
+        
+        Args:
+            job: A callable that will be executed by one of the worker threads.
+            weight: How many units the job counts as in the weighted_load stat.
         
         Returns:
-            The Aqueue instance itself.
+            A (job_id, depth) tuple, where depth is the number of queued jobs
+            waiting for a worker, including this one unless a worker already
+            took it. It counts like queue_len in stats(), so jobs cancelled or
+            replaced while queued are left out.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+            QueueFullError: If the queue is full and the overflow policy is "drop_new".
+            ValueError: If weight is 0.
         """
         ...
 
-    def __exit__(self, exc_type: Any, exc_value: Any, traceback: Any) -> None:
-        """Context manager exit.
+    def __call__(self, job: Callable[[], Any]) -> int:
+        """Shorthand for add_job, so pool(fn) or @pool submits fn once.
         
-        This ensures proper cleanup of the thread pool when used with a 'with' statement.
+        Note that @pool rebinds the decorated name to the returned job id.
+        
+        Returns:
+            The job's id, unique within this pool.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+            QueueFullError: If the queue is full and the overflow policy is "drop_new".
         """
         ...
 
-    @property
-    def pool_size(self) -> int:
-        """Get the total number of worker threads in the pool.
+    def submit(
+        self,
+        fn: Callable[..., Any],
+        /,
+        *args: Any,
+        map_result: Optional[Callable[[Any], Any]] = None,
+        result_type: Optional[Any] = None,
+        metadata: Optional[Dict[str, Any]] = None,
+        **kwargs: Any,
+    ) -> "Future[Any]":
+        """Schedule fn(*args, **kwargs) and return a concurrent.futures.Future for it.
+        
+        This matches Executor.submit, so as_completed, wait and add_done_callback
+        from the standard library work unchanged. The worker resolves the future
+        with set_result or set_exception while holding the GIL; Future guards
+        its state with its own lock, so this is safe to observe from any thread,
+        and done callbacks run on the worker thread. The worker marks the
+        future running just before calling fn, so running() tells a job in
+        progress from one still queued. Cancelling the future before a worker
+        picks the job up settles the job as "cancelled" at once and frees its
+        queue slot, and a job discarded by the overflow policy fails its
+        future with QueueFullError.
+        
+        Args:
+            fn: The callable to execute.
+            *args: Positional arguments for fn.
+            map_result: Called on the worker with fn's return value, and the
+                        Future gets its result instead. If it raises, the job
+                        fails with that exception. Not passed on to fn.
+            result_type: A type, or tuple of types, that the delivered value
+                         (after map_result) must be an instance of. Otherwise
+                         the job fails with TypeError. Not passed on to fn.
+            metadata: A dict that travels with the job, as for add_job. Not
+                      passed on to fn.
+            **kwargs: Keyword arguments for fn.
         
         Returns:
-            The number of worker threads configured for this thread pool.
+            A Future resolved with fn's return value, or map_result's, or the
+            exception.
+        
+        Raises:
+            TypeError: If result_type cannot be used with isinstance.
+            PoolShutdownError: If the thread pool has been shutdown.
+            QueueFullError: If the queue is full and the overflow policy is "drop_new".
         """
         ...
 
-    @property
-    def active_workers(self) -> int:
-        """Get the number of currently active worker threads.
+    def submit_cancellable(self, job: Callable[[], Any]) -> Tuple["Future[Any]", Callable[[], bool]]:
+        """Submit a job and get back its Future along with a function that cancels it.
+        
+        The function is the Future's bound cancel, so it takes no arguments and
+        returns whether the job was cancelled. It succeeds while the job is still
+        queued; the worker then skips the job and it settles as "cancelled".
+        Once the job has started it returns False and the job runs to the end.
+        
+        Args:
+            job: A callable that will be executed by one of the worker threads.
         
         Returns:
-            The number of worker threads that are currently running.
+            A (future, cancel) pair.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+            QueueFullError: If the queue is full and the overflow policy is "drop_new".
+        """
+        ...
+
+    def submit_tracked(self, job: Callable[[], Any]) -> Tuple[int, "Future[Any]"]:
+        """Submit a job and get back its id along with its Future.
+        
+        Both refer to the same queued job: the id is the one job_logs,
+        pending_jobs and the callbacks report, and the Future behaves as one
+        from submit(). This saves keeping a mapping from ids to futures.
+        
+        Args:
+            job: A callable that will be executed by one of the worker threads.
+        
+        Returns:
+            A (job_id, future) pair.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+            QueueFullError: If the queue is full and the overflow policy is "drop_new".
+        """
+        ...
+
+    def add_job_async(self, job: Callable[[], Any]) -> AsyncSubmit:
+        """Add a job from a coroutine without blocking the event loop.
+        
+            >>> job_id = await pool.add_job_async(work)
+        
+        Where add_job would block the thread on a full queue, and with it the
+        loop, the returned awaitable yields to the loop and retries until a
+        slot is free. The overflow policy does not apply, since waiting is the
+        backpressure. submit_middleware runs when add_job_async is called, and
+        cancelling the awaiting task leaves the job unsubmitted.
+        
+        Args:
+            job: A callable that will be executed by one of the worker threads.
+        
+        Returns:
+            An AsyncSubmit that resolves to the job's id.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown, here or
+                               while awaiting.
+            RuntimeError: If awaited outside a running event loop while the
+                          queue is full, or awaited a second time.
+        """
+        ...
+
+    def submit_or_run(self, job: Callable[[], Any]) -> str:
+        """Queue a job, or run it on the calling thread if the queue is full.
+        
+        This is the "caller runs" policy of Java's ThreadPoolExecutor. It never
+        blocks and never drops the job, whatever the overflow policy, and a
+        busy producer slows down by doing the work itself. A job run inline
+        raises its exceptions to the caller, gets no job id and skips the
+        pool's callbacks and counters. submit_middleware applies either way.
+        
+        Args:
+            job: A callable to run on a worker thread or, failing that, inline.
+        
+        Returns:
+            "queued" if a worker will run the job, or "inline" if it already ran.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+            Exception: Whatever an inline job raises.
+        """
+        ...
+
+    def reschedule(self, delay: float = 0.0) -> None:
+        """Run the calling job again once it returns, after an optional delay.
+        
+        Call it from inside a job, for example to poll until something is
+        ready. The job keeps its id and counts as pending until a run returns
+        without rescheduling, so wait_completion() and its future wait for the
+        last run. Callbacks such as on_finally fire only then too. The delay
+        does not hold a worker, and a rescheduled job skips the queue capacity.
+        
+        It comes back the way it was submitted: later runs still wait for
+        their limiter's permits, run one at a time with the rest of their
+        add_job_keyed key and take their group's or add_job_to queue's turn.
+        Only the priority among a key's waiting jobs is used again; a key's
+        next job may run during the delay. A run that raises after calling
+        reschedule() fails as usual instead of running again. Jobs still
+        waiting out a delay are dropped at shutdown, or cancelled by
+        shutdown(cancel_futures=True).
+        
+        Args:
+            delay: Seconds to wait before the job goes back on the queue.
+        
+        Raises:
+            ValueError: If delay is negative or not finite.
+            RuntimeError: If not called from a job running on this pool, or the
+                        job has already been rescheduled max_reschedules times.
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def submit_value(self, value: T) -> "Future[T]":
+        """Return a Future already resolved with value, without queueing any work.
+        
+        For call sites that sometimes compute a result and sometimes already
+        have it, so both paths hand back a Future. The value skips the pool
+        entirely: it gets no job id, is not counted in active_jobs or stats,
+        and on_finally and submit_middleware are not called.
+        
+        Args:
+            value: The result the Future holds.
+        
+        Returns:
+            A completed Future whose result() is value.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def task(self, func: Callable[..., Any]) -> Task:
+        """Wrap func so that calling it submits it to the pool.
+        
+        Meant as a decorator:
+        
+            >>> @pool.task
+            ... def fetch(url): ...
+            >>> future = fetch("https://example.com")
+        
+        Args:
+            func: The function to wrap.
+        
+        Returns:
+            A Task that submits func on each call and returns its Future.
+        """
+        ...
+
+    def add_job_event(self, job: Callable[[], Any], event: Any) -> int:
+        """Add a job that sets a threading.Event when it finishes.
+        
+        The worker calls event.set() with the GIL held once the job reaches a
+        terminal state, whether it succeeded, failed, was dropped by the overflow
+        policy or was cancelled at shutdown. Useful for blocking on a single job
+        without a Future.
+        
+        Args:
+            job: A callable that will be executed by one of the worker threads.
+            event: An object with a set() method, usually a threading.Event.
+        
+        Returns:
+            The job's id, unique within this pool.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+            QueueFullError: If the queue is full and the overflow policy is "drop_new".
+        """
+        ...
+
+    def submit_into(self, job: Callable[[], Any], results_list: List[Any], index: int) -> int:
+        """Add a job whose return value is stored in results_list[index].
+        
+        The worker writes the result with the GIL held once the job finishes,
+        so a preallocated list can be filled without per-job handles. If the
+        index is out of range by then, the job is logged and counted as failed;
+        the worker keeps running.
+        
+        Args:
+            job: A callable that will be executed by one of the worker threads.
+            results_list: The list that receives the result.
+            index: The position in results_list to write to.
+        
+        Returns:
+            The job's id, unique within this pool.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+            QueueFullError: If the queue is full and the overflow policy is "drop_new".
+        """
+        ...
+
+    def add_job_in_group(self, job: Callable[[], Any], group_id: int) -> int:
+        """Add a job that counts towards a group awaited with wait_group.
+        
+        These groups only track outcomes and do not affect scheduling; they are
+        unrelated to the batching groups of add_job_grouped.
+        
+        Args:
+            job: A callable that will be executed by one of the worker threads.
+            group_id: The group the job belongs to.
+        
+        Returns:
+            The job's id, unique within this pool.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+            QueueFullError: If the queue is full and the overflow policy is "drop_new".
+        """
+        ...
+
+    def wait_group(self, group_id: int, timeout: Optional[float] = None) -> Dict[str, int]:
+        """Block until every job added to a group has reached a terminal state.
+        
+        Once the group has finished, its tally is reset, so a later call only
+        counts jobs added after this one returned.
+        
+        Args:
+            group_id: The group passed to add_job_in_group.
+            timeout: Maximum number of seconds to wait. If None, wait indefinitely.
+        
+        Returns:
+            A summary with the group's "completed", "failed", "cancelled" and
+            "dropped" job counts, and "pending" for jobs still unfinished when
+            the timeout elapsed (0 if the group finished).
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+            ValueError: If timeout is negative.
+            KeyboardInterrupt: If interrupted by a signal while waiting.
+        """
+        ...
+
+    def cancel_group(self, group_id: int) -> Dict[str, int]:
+        """Cancel the jobs of one group that have not started yet.
+        
+        Jobs of other groups are untouched. A cancelled job's future is
+        cancelled, its event is set and on_finally gets "cancelled"; it counts
+        as "cancelled" in wait_group. Jobs already running are left to finish.
+        
+        Args:
+            group_id: The group passed to add_job_in_group.
+        
+        Returns:
+            A summary with the number of jobs "cancelled", and "running" for
+            the group's jobs that had already started and are still unfinished.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def add_job_grouped(self, job: Callable[[], Any], group: int) -> int:
+        """Add a job tagged with a grouping key.
+        
+        After finishing a grouped job, a worker preferentially runs other queued
+        jobs from the same group, up to group_batch_limit in a row, so bursts of
+        similar jobs run consecutively on one worker with warm caches. Groups
+        with queued jobs take these batches in turn, so once a group has had
+        its batch the next one goes to another group, and a busy group cannot
+        hold back the rest.
+        
+        Args:
+            job: A callable that will be executed by one of the worker threads.
+            group: An unsigned integer identifying the job's group.
+        
+        Returns:
+            The job's id, unique within this pool.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def add_job_to(self, queue_name: str, job: Callable[[], Any]) -> int:
+        """Add a job to one of the sub_queues given at construction.
+        
+        Sub-queue jobs share the pool's capacity and overflow policy with other
+        jobs, but among themselves run in weighted round-robin order, so a busy
+        sub-queue cannot starve the others.
+        
+        Args:
+            queue_name: The sub-queue to add the job to.
+            job: A callable that will be executed by one of the worker threads.
+        
+        Returns:
+            The job's id, unique within this pool.
+        
+        Raises:
+            ValueError: If there is no sub-queue called queue_name.
+            PoolShutdownError: If the thread pool has been shutdown.
+            QueueFullError: If the queue is full and the overflow policy is "drop_new".
+        """
+        ...
+
+    def queue_depths(self) -> Dict[str, int]:
+        """Get the number of jobs waiting in each sub-queue.
+        
+        Returns:
+            A dict mapping each sub-queue's name to its waiting job count.
+            Empty if the pool has no sub_queues or has been shutdown.
+        """
+        ...
+
+    def add_job_idempotent(self, job: Callable[[], Any], key: str) -> int:
+        """Add a job unless one with the same key is already queued or running.
+        
+        For at-least-once producers that may resubmit the same logical job. While
+        a job submitted with key has not finished, further submissions with that
+        key return its id and queue nothing. Once it completes, fails, is dropped
+        or is cancelled, the key is free again. submit_middleware still runs for
+        a submission that turns out to be a duplicate.
+        
+        Args:
+            job: A callable that will be executed by one of the worker threads.
+            key: Identifies the logical job.
+        
+        Returns:
+            The id of the job queued now, or of the one already holding key.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+            QueueFullError: If the queue is full and the overflow policy is "drop_new".
+        """
+        ...
+
+    def add_job_replace(self, job: Callable[[], Any], key: str) -> int:
+        """Add a job that supersedes any job with the same key still waiting.
+        
+        For "latest value wins" updates: if the last job submitted with key has
+        not started, it is skipped and settles as cancelled, so on_finally sees
+        "cancelled" and its event is set. The new job takes over its queue
+        slot, so replacing never blocks or meets the overflow policy. A job
+        for key that is already running is left alone, and the new one runs
+        after it as usual.
+        
+        Args:
+            job: A callable that will be executed by one of the worker threads.
+            key: Identifies what the job computes.
+        
+        Returns:
+            The job's id, unique within this pool.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+            QueueFullError: If the queue is full and the overflow policy is
+                        "drop_new". The previous job then stays queued.
+        """
+        ...
+
+    def add_shutdown_job(self, job: Callable[[], Any]) -> int:
+        """Queue a job to run on a worker during shutdown, after all other jobs.
+        
+        For finalization work such as flushing buffers or closing connections.
+        Shutdown waits for the regular jobs to settle, however they ended, then
+        runs the shutdown jobs one at a time in the order they were added, each
+        as a normal job with the usual callbacks. They still run when
+        shutdown(cancel_futures=True) cancels the queued jobs. Until shutdown
+        starts they are not counted by active_jobs and wait_completion does not
+        wait for them.
+        
+        Args:
+            job: A callable to run during shutdown.
+        
+        Returns:
+            The job's id, unique within this pool.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def add_job_main(self, job: Callable[[], Any]) -> int:
+        """Queue a job to run on the main thread the next time pump_main is called.
+        
+        For follow-up work that must happen on the main interpreter thread, such
+        as GUI updates, scheduled from inside worker jobs. These jobs bypass the
+        workers: they are not counted by active_jobs and wait_completion does not
+        wait for them, since the main thread would otherwise wait on itself.
+        Pending ones are discarded at shutdown.
+        
+        Args:
+            job: A callable to run on the main thread.
+        
+        Returns:
+            The job's id, unique within this pool.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def pump_main(self, max_jobs: Optional[int] = None) -> int:
+        """Run jobs queued with add_job_main, oldest first, on the calling thread.
+        
+        Call this from the main thread's event loop. Jobs queued while pumping
+        are run in the same call, up to max_jobs. A job that raises is logged
+        and the rest still run. on_complete_timed and on_finally are not called
+        for these jobs.
+        
+        Args:
+            max_jobs: The most jobs to run in this call. If None, run until none
+                      are left.
+        
+        Returns:
+            The number of jobs that ran.
+        
+        Raises:
+            RuntimeError: If called from any thread other than the main thread.
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def main_thread_queue(self) -> MainThreadQueue:
+        """Get a handle on the queue that add_job_main and pump_main use.
+        
+        Returns:
+            A MainThreadQueue sharing this pool's main-thread jobs.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def main_pending(self) -> int:
+        """Get the number of add_job_main jobs waiting for pump_main.
+        
+        Returns:
+            The number of jobs waiting, or 0 after shutdown.
+        """
+        ...
+
+    def add_job_keyed(self, job: Callable[[], Any], key: Any, priority: int = 0) -> int:
+        """Add a job that never runs at the same time as another job with its key.
+        
+        Jobs with the same key run one at a time, in priority order and then in
+        submission order. Jobs with different keys run in parallel as usual,
+        which gives per-entity serialization (e.g. per account) on a shared
+        pool. A worker that picks up a job whose key is busy leaves it to the
+        worker running that key, which runs it next. Keys are compared by hash,
+        so two keys with colliding hashes are also serialized with each other.
+        
+        Priority only orders the jobs waiting on one key, to avoid priority
+        inversion: a high-priority job queued behind a running low-priority job
+        with the same key cannot preempt it, but it runs as soon as that job
+        returns, ahead of every lower-priority job waiting on the key. It does
+        not move the key ahead of other keys or other jobs in the pool.
+        
+        Args:
+            job: A callable that will be executed by one of the worker threads.
+            key: Any hashable value identifying the entity the job touches.
+            priority: Higher runs first among the jobs waiting on the same key,
+                        within the pool's max_overtakes limit.
+        
+        Returns:
+            The job's id, unique within this pool.
+        
+        Raises:
+            TypeError: If key is not hashable.
+            PoolShutdownError: If the thread pool has been shutdown.
+            QueueFullError: If the queue is full and the overflow policy is "drop_new".
+        """
+        ...
+
+    def reserve(self, n: int) -> Reservation:
+        """Hold n queue slots for a burst of jobs.
+        
+        The slots count against the queue's capacity (hard_capacity) until
+        they are used or released, so other producers see a fuller queue in the
+        meantime. With the "block" overflow policy this waits, with the GIL
+        released, until n slots are free at once; with the other policies it
+        fails straight away instead.
+        
+        Args:
+            n: The number of slots to hold.
+        
+        Raises:
+            ValueError: If n exceeds the queue's capacity, including when
+                        set_queue_capacity shrinks it below n while waiting.
+            QueueFullError: If the overflow policy is not "block" and n slots are
+                        not free.
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def new_limiter(self, max_concurrent: int) -> Limiter:
+        """Create a limiter capping how many of its jobs run concurrently.
+        
+        Args:
+            max_concurrent: The number of permits. Must be greater than 0.
+        
+        Raises:
+            ValueError: If max_concurrent is 0.
+        """
+        ...
+
+    def add_job_limited(self, job: Callable[[], Any], limiter: Limiter, weight: int = 1) -> int:
+        """Add a job that must hold the limiter's permits while it runs.
+        
+        The worker that picks up the job blocks until enough permits are free,
+        so a burst of limited jobs can temporarily occupy workers that would
+        otherwise run unlimited jobs.
+        
+        Args:
+            job: A callable that will be executed by one of the worker threads.
+            limiter: The limiter to acquire permits from.
+            weight: How many permits the job holds, so heavy jobs can count as
+                    several light ones. Also counted in the weighted_load stat.
+        
+        Returns:
+            The job's id, unique within this pool.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+            ValueError: If weight is 0 or exceeds the limiter's max_concurrent.
+        """
+        ...
+
+    def reduce(
+        self,
+        func: Callable[[T], R],
+        iterable: Iterable[T],
+        reducer: Callable[[A, R], A],
+        initial: A,
+    ) -> A:
+        """Apply func to every item on the pool and fold the results.
+        
+        Results are folded in completion order as they arrive, including while
+        items are still being submitted, so they never pile up in memory.
+        reducer runs only on the calling thread, one call at a time, so it
+        does not need to be thread-safe.
+        
+        Args:
+            func: Called with each item on a worker thread.
+            iterable: The items to process.
+            reducer: Called as reducer(acc, result) to produce the new accumulator.
+            initial: The starting accumulator.
+        
+        Returns:
+            The final accumulator.
+        
+        Raises:
+            Exception: The first exception raised by func or reducer. Jobs already
+                        submitted keep running, but their results are discarded.
+            QueueFullError: If a job was discarded by the overflow policy.
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def parallel_map(
+        self,
+        func: Callable[[T], R],
+        iterable: Iterable[T],
+        max_outstanding: int,
+        ordered: bool = True,
+    ) -> ParallelMap[R]:
+        """Lazily apply func to each item of an iterable on the pool.
+        
+        Unlike submitting everything up front, items are pulled from iterable
+        only to keep max_outstanding jobs in flight, refilling as results are
+        consumed. That makes it suitable for large or infinite generators with
+        bounded memory. The iterable is advanced on the consuming thread.
+        
+            >>> for row in pool.parallel_map(parse, read_lines(path), 64):
+            ...     store(row)
+        
+        Args:
+            func: Called with each item on a worker thread.
+            iterable: The items to process.
+            max_outstanding: The most items submitted and not yet yielded. In
+                             ordered mode results held back behind a slow item
+                             count towards it.
+            ordered: Yield results in input order. If False, yield each result
+                     as soon as its job completes.
+        
+        Returns:
+            An iterator over func's results.
+        
+        Raises:
+            ValueError: If max_outstanding is 0.
+            TypeError: If iterable is not iterable.
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def burst(self, extra: int, ttl: float) -> None:
+        """Temporarily add extra worker threads to absorb a spike in load.
+        
+        The extra workers take jobs like any other worker, but each one retires
+        on its own once it has been idle for ttl seconds. The pool's configured
+        workers are never retired by this.
+        
+        Args:
+            extra: The number of temporary workers to spawn.
+            ttl: Seconds a temporary worker may sit idle before retiring.
+        
+        Raises:
+            ValueError: If ttl is not a positive number.
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def stop(self) -> None: ...
+
+    def active_jobs(self) -> int:
+        """Get the number of jobs currently being processed.
+        
+        This is queued_jobs() plus in_flight().
+        
+        Returns:
+            The number of jobs that have been submitted but not yet completed.
+        """
+        ...
+
+    def queued_jobs(self) -> int:
+        """Get the number of active jobs that have not started running.
+        
+        Unlike active_jobs(), this leaves out the jobs workers are executing,
+        so it stays low when every worker is busy on a slow dependency and
+        nothing is waiting. Delayed and rescheduled jobs, and jobs a worker
+        holds while waiting for a Limiter or max_in_flight, count as queued.
+        
+        Returns:
+            active_jobs() minus in_flight(), or 0 after shutdown.
+        """
+        ...
+
+    def queue_capacity(self) -> int:
+        """Get how many jobs the queue holds before the overflow policy applies.
+        
+        This is hard_capacity (twice max_workers by default) until changed with
+        set_queue_capacity().
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def set_queue_capacity(self, n: int) -> None:
+        """Change the queue capacity of a running pool, for tuning backpressure.
+        
+        Growing takes effect at once and wakes blocked producers. Shrinking
+        drops nothing already queued: the queue stops admitting jobs, so
+        producers block or the overflow policy applies, until it has drained
+        below the new capacity. Slots held by reserve() are not touched, but
+        a reserve() still waiting for more slots than the new capacity fails.
+        
+        Args:
+            n: The new capacity.
+        
+        Raises:
+            ValueError: If n is 0 or not greater than soft_capacity.
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def pending_jobs(self, with_metadata: bool = False) -> List[Tuple[Any, ...]]:
+        """List the jobs waiting to run, for debugging a stuck pool.
+        
+        This is a best-effort snapshot taken while workers keep running. A job
+        listed may already have started by the time the list is returned, and
+        jobs submitted concurrently may be missing. Jobs from reduce are not
+        included.
+        
+        Args:
+            with_metadata: Add each job's metadata dict, or None, as a third
+                           element.
+        
+        Returns:
+            (job_id, name) pairs ordered by job id, where name is the callable's
+            __qualname__ (or "<unnamed>"), or (job_id, name, metadata) triples
+            with with_metadata.
+        """
+        ...
+
+    def checkpoint(self, path: str) -> int:
+        """Save the jobs waiting to run to a file, for restore= after a restart.
+        
+        The callables of the queued jobs are pickled in submission order. The
+        file is written next to path and then moved into place, so an earlier
+        checkpoint is only replaced by a complete one. Jobs keep running and
+        stay queued; like pending_jobs this is a snapshot, so stop submitting
+        first for an exact one.
+        
+        Only the callables are saved. Running jobs are not checkpointed, and a
+        restored job loses its future, group, key, weight and other submission
+        options. Jobs from reduce and similar helpers are not included.
+        
+        Args:
+            path: The file to write.
+        
+        Returns:
+            The number of jobs saved.
+        
+        Raises:
+            RuntimeError: If the pool was not created with picklable=True.
+            PoolShutdownError: If the thread pool has been shutdown.
+            OSError: If the file cannot be written.
+        """
+        ...
+
+    def last_error(self, clear: bool = False) -> Optional[BaseException]:
+        """Return the exception raised by the most recently failed job.
+        
+        A quick way to see why something just failed, for example from a REPL,
+        without handles or callbacks. The exception keeps its __traceback__, so
+        traceback.print_exception(pool.last_error()) shows where it was raised.
+        Each failure replaces the previous one, whether or not error_log_window
+        is set. The traceback keeps the failed job's frames and their locals
+        alive until the next failure or until it is cleared.
+        
+        Args:
+            clear: Forget the exception after returning it, so the next call
+                   returns None until another job fails.
+        
+        Returns:
+            The exception, or None if no job has failed (since the last clear).
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def results(self) -> Results:
+        """Iterate over jobs' outcomes as they finish, from any thread.
+        
+        Decouples consumers from producers: one thread submits while another loops
+        over this iterator, which ends once the pool is shut down and drained.
+        Outcomes are collected from the first call on, for every job that runs,
+        and are kept until consumed. Iterators from separate calls share them,
+        so each outcome goes to only one of them.
+        
+        Example:
+            >>> for job_id, outcome in pool.results():
+            ...     handle(job_id, outcome)
+        
+        Returns:
+            An iterator of (job_id, return value or exception) pairs.
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def estimated_drain_time(self) -> float:
+        """Estimate how many seconds until the queue is empty.
+        
+        Computed as queue_len * avg_exec_time / active_workers from stats, so it
+        assumes upcoming jobs take about as long as recent ones and every worker
+        stays busy. Jobs already running are not included. Good enough for a
+        "~2m remaining" indicator; use the stats inputs for a custom estimate.
+        
+        Returns:
+            The estimate in seconds, or 0.0 if nothing is queued, no job has run
+            yet, or the pool has been shutdown.
+        """
+        ...
+
+    def stats(self) -> Dict[str, Any]:
+        """Get a snapshot of the pool's counters.
+        
+        Returns:
+            A dict with the following keys:
+            
+            - active_jobs: Jobs submitted but not yet completed.
+            - queued_jobs: Active jobs not running yet; see queued_jobs().
+            - active_workers: Worker threads currently alive.
+            - dropped: Jobs discarded by the overflow policy.
+            - completed: Jobs that have completed successfully since the pool
+              started. It only ever grows; see wait_until_completed().
+            - weighted_load: Sum of the weights of the active jobs.
+            - in_flight: Jobs running right now.
+            - queue_len: Jobs waiting for a worker, in any of the pool's queues.
+              Jobs cancelled or replaced while queued and jobs waiting out a
+              reschedule delay are not counted.
+            - avg_exec_time: Moving average of the seconds jobs spend executing,
+              weighted towards recent jobs; 0.0 until a job has run.
+            - avg_cpu_time: The same average for CPU time the worker thread used
+              while executing jobs. Far below avg_exec_time means jobs mostly
+              block, so more workers may help; close to it means they are CPU
+              bound. Always 0.0 on platforms without per-thread CPU clocks.
+            - gil_wait_total: Total seconds workers spent waiting to acquire the
+              GIL before running jobs. If this grows about as fast as wall time
+              multiplied by the worker count, the workload is GIL-bound and more
+              workers will not help.
+            - dispatch_latency: How long job messages waited between being sent
+              and a worker receiving them, as a list of (upper_bound, count)
+              pairs. Bounds are in seconds, from 1e-05 to 1.0 in powers of ten
+              and then inf, and each count covers waits above the previous
+              bound. Workers that receive a message promptly but then stall
+              show up in gil_wait_total rather than here.
+        """
+        ...
+
+    def drain_stats(self) -> Dict[str, int]:
+        """Get the pool's cumulative job counters and reset them to zero.
+        
+        The counters are read and zeroed in one swap under a single lock, so a
+        reporter calling this once per interval gets per-interval deltas that
+        add up to the totals, with no job counted twice or missed between
+        intervals. Only these counters are reset: stats() is not, and neither
+        are the totals flush() and the wait methods rely on.
+        
+        Returns:
+            A dict with the following keys, all 0 after shutdown:
+            
+            - submitted: Jobs accepted by the pool.
+            - completed: Jobs that returned without raising.
+            - failed: Jobs that raised.
+            - retried: Times a job called reschedule() to run again; the job
+              is counted under completed or failed once it finally settles.
+            - cancelled: Jobs cancelled before running, through their future,
+              shutdown(cancel_futures=True), drain_pending, add_job_replace
+              or cancel_group.
+            - expired: Jobs the overflow policy gave up on before they ran.
+        """
+        ...
+
+    def wait_completion(
+        self,
+        timeout: Optional[float] = None,
+        progress_callback: Optional[Callable[[int, int], Any]] = None,
+    ) -> bool:
+        """Block until all submitted jobs have completed.
+        
+        The GIL is released while waiting, and pending signals are checked
+        periodically, so Ctrl-C raises KeyboardInterrupt promptly.
+        
+        Args:
+            timeout: Maximum number of seconds to wait. If None, waits indefinitely.
+            progress_callback: Called roughly every 100ms, and once more when the pool
+                        drains, with (remaining, total) where total is the number of
+                        outstanding jobs when the wait began (raised if more jobs are
+                        submitted meanwhile). An exception raised by the callback
+                        aborts the wait and propagates.
+        
+        Returns:
+            True if all jobs completed, False if the timeout elapsed first.
+        
+        Raises:
+            ValueError: If timeout is negative.
+            KeyboardInterrupt: If interrupted while waiting.
+        """
+        ...
+
+    def wait_idle(self, timeout: float) -> str:
+        """Block until the pool is idle or the deadline passes, and say which.
+        
+        Uses the same wait as wait_completion, with the GIL released and signals
+        checked periodically.
+        
+        Args:
+            timeout: Maximum number of seconds to wait.
+        
+        Returns:
+            "idle" if all submitted jobs completed, "timeout" if the deadline
+            passed first, or "shutdown" if the pool has already been shut down.
+        
+        Raises:
+            ValueError: If timeout is negative.
+        """
+        ...
+
+    def flush(self, timeout: Optional[float] = None) -> bool:
+        """Wait for every job submitted before the call, ignoring later ones.
+        
+        Unlike wait_completion, which waits for the pool to go idle and can keep
+        waiting while other threads submit more work, flush waits only until as
+        many jobs have finished as had been submitted when it was called. Dropped
+        jobs count as finished.
+        
+        Args:
+            timeout: Maximum number of seconds to wait. If None, wait indefinitely.
+        
+        Returns:
+            True if those jobs finished, False if the timeout elapsed first.
+        
+        Raises:
+            ValueError: If timeout is negative.
+            KeyboardInterrupt: If interrupted by a signal while waiting.
+        """
+        ...
+
+    def wait_for_count(self, n: int, timeout: Optional[float] = None) -> int:
+        """Wait until at least n jobs have completed successfully since the call.
+        
+        For acting on the first results as they arrive, e.g. showing "first 10
+        results ready", without tracking individual jobs. Only jobs that return
+        without raising count, against the same counter as stats()["completed"];
+        failed, cancelled and dropped jobs do not, so a burst of failures leaves
+        the wait running until enough results arrive or the timeout elapses.
+        
+        Args:
+            n: How many completed jobs to wait for.
+            timeout: Maximum number of seconds to wait. If None, wait indefinitely.
+        
+        Returns:
+            The number of jobs that completed during the wait. This is less than n
+            if the timeout elapsed first, and can be more than n if several
+            completed at once.
+        
+        Raises:
+            ValueError: If timeout is negative.
+            KeyboardInterrupt: If interrupted by a signal while waiting.
+        """
+        ...
+
+    def throughput(self, window_secs: int) -> float:
+        """Get how many jobs per second completed over the recent past.
+        
+        A live rate for dashboards, unlike the cumulative stats()["completed"].
+        Completions are counted in one-second buckets, and the current second
+        counts for as much of it as has passed. Jobs and map items that
+        completed successfully are counted; failures are not.
+        
+        Args:
+            window_secs: How many seconds back to look, from 1 to 60.
+        
+        Returns:
+            Completions per second over the window, or 0.0 after shutdown.
+        
+        Raises:
+            ValueError: If window_secs is outside 1 to 60.
+        """
+        ...
+
+    def wait_until_completed(self, target: int, timeout: Optional[float] = None) -> bool:
+        """Wait until stats()["completed"] reaches target.
+        
+        The target is absolute, not relative to the call, so work processed in
+        waves can wait for e.g. 1000, then 2000, without racing jobs that finish
+        between waves. Only jobs that completed successfully count; a failed or
+        dropped job never brings the target closer, so pass a timeout when that
+        can happen.
+        
+        Args:
+            target: The completed count to wait for.
+            timeout: Maximum number of seconds to wait. If None, wait indefinitely.
+        
+        Returns:
+            True if the count reached target, False if the timeout elapsed first
+            or the pool has been shut down.
+        
+        Raises:
+            ValueError: If timeout is negative.
+            KeyboardInterrupt: If interrupted by a signal while waiting.
+        """
+        ...
+
+    def drain_pending(self) -> List[Callable[[], Any]]:
+        """Stop accepting jobs and return the ones that have not started yet.
+        
+        For handing a backlog to another pool or persisting it. Like close, every
+        submission method raises PoolShutdownError afterwards, and jobs already
+        running finish as usual; call join to wait for them. The returned jobs
+        no longer count as active. They are treated as cancelled: futures from
+        submit are cancelled and on_finally reports "cancelled". Jobs from
+        submit come back with their arguments already bound.
+        
+        Returns:
+            The callables of the jobs that were waiting, in submission order.
+            Empty after shutdown.
+        """
+        ...
+
+    def close(self) -> None:
+        """Stop accepting new jobs while the workers keep draining the queue.
+        
+        Like multiprocessing.Pool.close. Afterwards every submission method raises
+        PoolShutdownError and running is False. Call join to wait for the queued
+        jobs.
+        """
+        ...
+
+    def join(self) -> None:
+        """Block until the queued jobs have finished and the workers have exited.
+        
+        Raises:
+            ValueError: If the pool is still running, i.e. close was not called.
+        """
+        ...
+
+    def shutdown(self, wait: bool = True, cancel_futures: bool = False) -> None:
+        """Stop accepting jobs and shut the pool down, like Executor.shutdown.
+        
+        Jobs already queued still run unless cancel_futures is set. Calling it
+        again, or using the pool as a context manager afterwards, is harmless.
+        
+        Args:
+            wait: If True, block until the queued jobs have finished and the workers
+                  have exited. If False, return immediately and let the workers
+                  finish in the background.
+            cancel_futures: If True, first cancel every job that has not started yet.
+                  Their futures are cancelled, and on_finally, group tallies and the
+                  stats all report them as "cancelled".
+        """
+        ...
+
+    def graceful_stop(self, budget_secs: float) -> int:
+        """Drain the pool within a time budget, then stop it, e.g. on SIGTERM.
+        
+        Meant for a termination grace period such as Kubernetes' preStop or
+        SIGTERM handling. New jobs are refused at once, and queued jobs keep
+        running for up to budget_secs. If everything finished by then, the
+        pool shuts down as shutdown() would. Otherwise the queued jobs are
+        cancelled as with shutdown(cancel_futures=True), along with any
+        add_shutdown_job jobs, so no job starts after this returns.
+        
+        Jobs already running when the budget runs out cannot be interrupted.
+        This method returns without waiting for them: they keep running in the
+        background until they return or the process exits, and their workers are
+        joined as they do. running is False and the pool accepts nothing more
+        from the moment this returns.
+        
+        Args:
+            budget_secs: The most seconds to spend draining.
+        
+        Returns:
+            How many jobs were abandoned: the cancelled ones plus those still
+            running in the background. 0 if the pool drained in time or was
+            already shut down.
+        
+        Raises:
+            ValueError: If budget_secs is negative or not finite.
+            KeyboardInterrupt: If interrupted by a signal while draining.
+        """
+        ...
+
+    def __enter__(self) -> 'Aqueue':
+        """Context manager entry.
+        
+        Returns:
+            The Aqueue instance itself.
+        """
+        ...
+
+    def __exit__(self, exc_type: Any, exc_value: Any, traceback: Any) -> None:
+        """Context manager exit.
+        
+        This ensures proper cleanup of the thread pool when used with a 'with' statement.
+        """
+        ...
+
+    @property
+    def pool_size(self) -> int:
+        """Get the total number of worker threads in the pool.
+        
+        Returns:
+            The number of worker threads configured for this thread pool.
+        """
+        ...
+
+    @property
+    def active_workers(self) -> int:
+        """Get the number of currently active worker threads.
+        
+        For lazy pools this grows and shrinks with demand, while pool_size stays
+        fixed at the configured maximum. Temporary workers added by burst() are
+        included.
+        
+        Returns:
+            The number of worker threads that are currently running.
+        """
+        ...
+
+    @property
+    def dropped(self) -> int:
+        """Get the number of jobs discarded by the overflow policy.
+        
+        Returns:
+            The number of jobs rejected under "drop_new" or evicted under "drop_old".
+        """
+        ...
+
+    @property
+    def worker_local(self) -> WorkerLocal:
+        """Storage with one slot per worker, for jobs to capture and use.
+        
+        Example:
+            >>> pool = Aqueue(4, on_worker_start=lambda worker_id: connect())
+            >>> local = pool.worker_local
+            >>> pool.add_job(lambda: local.get().execute("SELECT 1"))
+        
+        Raises:
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    @property
+    def in_flight(self) -> int:
+        """Get the number of jobs running right now.
+        
+        Jobs a worker has taken but is holding while it waits under
+        max_in_flight are not counted.
+        
+        Returns:
+            The number of jobs currently executing, or 0 after shutdown.
+        """
+        ...
+
+    def flight_recorder_dump(self) -> List[Dict[str, Any]]:
+        """Get the recent job lifecycle events kept by the flight recorder.
+        
+        For post-mortem debugging: after an incident, dump the exact recent
+        sequence of pool activity. It still works after shutdown.
+        
+        Returns:
+            One dict per event, oldest first, with keys:
+            
+            - event: "submitted", "started", "rescheduled", or the job's final
+              status: "completed", "failed", "dropped" or "cancelled".
+            - job_id: The job's id.
+            - time: When it happened, in seconds since the epoch.
+        
+        Raises:
+            RuntimeError: If the pool was created without flight_recorder.
+        """
+        ...
+
+    def worker_states(self) -> List[Dict[str, Any]]:
+        """Get a snapshot of what each worker is doing, for a live dashboard.
+        
+        Workers update their entry when they pick up a job and when it
+        finishes, so the snapshot gives a "top"-like view of the pool.
+        
+        Returns:
+            One dict per worker, ordered by id, with keys:
+            
+            - id: The worker id, as passed to on_worker_start.
+            - state: "busy" while running a job, "idle" while waiting for one,
+              "quiescing" while finishing its job after quiesce_worker(),
+              "quiesced" once parked, or "dead" once the thread has exited,
+              until a later spawn reaps it.
+            - current_job_id: The running job's id, or None when idle or
+              running a map item, which has no id.
+            - current_job_name: The running callable's qualified name, or None.
+            - jobs_executed: Jobs and map items this worker has run.
+            - rss_growth: Bytes of RSS growth attributed to this worker, for
+              max_worker_rss. Always 0 when that is not set.
+            - busy_since: When the current job started, in seconds since the
+              epoch, or None.
+            
+            Empty after shutdown.
+        """
+        ...
+
+    def quiesce_worker(self, id: int) -> None:
+        """Take one worker out of service without interrupting it.
+        
+        The worker finishes the job it is running, if any, then parks and takes
+        no more jobs until unquiesce_worker() is called, while the others carry
+        on. Parked workers still count toward the pool's size, and they are
+        released at shutdown.
+        
+        Args:
+            id: The worker id, as shown by worker_states().
+        
+        Raises:
+            ValueError: If there is no live worker with that id.
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def unquiesce_worker(self, id: int) -> None:
+        """Return a worker taken out of service by quiesce_worker().
+        
+        Args:
+            id: The worker id, as shown by worker_states().
+        
+        Raises:
+            ValueError: If there is no live worker with that id.
+            PoolShutdownError: If the thread pool has been shutdown.
+        """
+        ...
+
+    def heartbeats(self) -> Dict[int, float]:
+        """Get how long ago each live worker last woke between jobs.
+        
+        Waiting workers refresh their heartbeat at least every
+        housekeeping_interval, so an age well above that means the worker has
+        been inside a single job for about that long, which can point to a hung
+        job.
+        
+        Returns:
+            A dict of seconds since the last heartbeat, keyed by worker id. Empty
+            after shutdown.
         """
         ...
 
@@ -0,0 +1,32 @@
+"""Throughput of trivial add_job jobs with and without coalesce.
+
+Usage: python tests/bench_coalesce.py [jobs] [workers]
+
+Each job is a no-op, so the time is almost all per-job overhead: taking the
+message, acquiring the GIL and calling into Python. coalesce runs several jobs
+per GIL acquisition and should finish well ahead of the default.
+"""
+import sys
+import time
+
+import nornir
+
+def run(jobs, workers, coalesce):
+    queue = nornir.Aqueue(workers, hard_capacity=10_000, coalesce=coalesce, warn_oversubscribe=False)
+    job = lambda: None
+    start = time.perf_counter()
+    for _ in range(jobs):
+        queue.add_job(job)
+    queue.wait_completion()
+    elapsed = time.perf_counter() - start
+    completed = queue.stats()["completed"]
+    del queue
+    return elapsed, completed
+
+if __name__ == "__main__":
+    jobs = int(sys.argv[1]) if len(sys.argv) > 1 else 1_000_000
+    workers = int(sys.argv[2]) if len(sys.argv) > 2 else 4
+    print(f"{jobs} no-op jobs on {workers} workers")
+    for coalesce in [None, 8, 64]:
+        elapsed, completed = run(jobs, workers, coalesce)
+        print(f"coalesce={coalesce}: {elapsed:.2f}s, {jobs / elapsed:,.0f} jobs/s ({completed} completed)")
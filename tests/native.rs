@@ -0,0 +1,112 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+
+use nornir::NativePool;
+
+#[test]
+fn execute_runs_every_job() {
+    let pool = NativePool::new(4).unwrap();
+    let count = Arc::new(AtomicUsize::new(0));
+    for _ in 0..100 {
+        let count = Arc::clone(&count);
+        pool.execute(Box::new(move || {
+            count.fetch_add(1, Ordering::SeqCst);
+        }))
+        .unwrap();
+    }
+    pool.wait_completion();
+    assert_eq!(count.load(Ordering::SeqCst), 100);
+    assert_eq!(pool.completed(), 100);
+}
+
+#[test]
+fn jobs_run_in_parallel() {
+    // Every job waits for the others, so this only finishes if all four
+    // run at once
+    let pool = NativePool::new(4).unwrap();
+    let barrier = Arc::new(Barrier::new(4));
+    for _ in 0..4 {
+        let barrier = Arc::clone(&barrier);
+        pool.execute(Box::new(move || {
+            barrier.wait();
+        }))
+        .unwrap();
+    }
+    pool.wait_completion();
+}
+
+#[test]
+fn wait_completion_waits_for_running_jobs() {
+    let pool = NativePool::new(2).unwrap();
+    let done = Arc::new(AtomicUsize::new(0));
+    for _ in 0..4 {
+        let done = Arc::clone(&done);
+        pool.execute(Box::new(move || {
+            thread::sleep(Duration::from_millis(50));
+            done.fetch_add(1, Ordering::SeqCst);
+        }))
+        .unwrap();
+    }
+    assert!(pool.active_jobs() > 0);
+    pool.wait_completion();
+    assert_eq!(done.load(Ordering::SeqCst), 4);
+    assert_eq!(pool.active_jobs(), 0);
+}
+
+#[test]
+fn panicking_job_does_not_take_down_its_worker() {
+    let pool = NativePool::new(1).unwrap();
+    let count = Arc::new(AtomicUsize::new(0));
+    pool.execute(Box::new(|| panic!("intentional failure"))).unwrap();
+    let ran = Arc::clone(&count);
+    pool.execute(Box::new(move || {
+        ran.fetch_add(1, Ordering::SeqCst);
+    }))
+    .unwrap();
+    pool.wait_completion();
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+    assert_eq!(pool.completed(), 1);
+    assert_eq!(pool.active_jobs(), 0);
+}
+
+#[test]
+fn shutdown_finishes_queued_jobs_and_rejects_new_ones() {
+    let mut pool = NativePool::new(1).unwrap();
+    let count = Arc::new(AtomicUsize::new(0));
+    for _ in 0..2 {
+        let count = Arc::clone(&count);
+        pool.execute(Box::new(move || {
+            thread::sleep(Duration::from_millis(20));
+            count.fetch_add(1, Ordering::SeqCst);
+        }))
+        .unwrap();
+    }
+    pool.shutdown();
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+    assert!(pool.execute(Box::new(|| {})).is_err());
+    // A second shutdown, and the one on drop, do nothing
+    pool.shutdown();
+}
+
+#[test]
+fn drop_finishes_queued_jobs() {
+    let count = Arc::new(AtomicUsize::new(0));
+    {
+        let pool = NativePool::new(2).unwrap();
+        for _ in 0..10 {
+            let count = Arc::clone(&count);
+            pool.execute(Box::new(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            }))
+            .unwrap();
+        }
+    }
+    assert_eq!(count.load(Ordering::SeqCst), 10);
+}
+
+#[test]
+fn zero_workers_is_rejected() {
+    assert!(NativePool::new(0).is_err());
+}
@@ -1,6 +1,13 @@
 import nornir
 import time
 import threading
+import concurrent.futures
+import functools
+import heapq
+import os
+import subprocess
+import sys
+import tempfile
 
 # Simple worker wrapper
 class Worker:
@@ -120,6 +127,126 @@ def diagnose_multiple_jobs():
     # Close the worker
     worker.close()
 
+def diagnose_wait_completion():
+    """Test that wait_completion blocks until jobs finish and honours timeouts"""
+    print("\n==== Testing wait_completion ====")
+    
+    queue = nornir.Aqueue(2)
+    
+    for _ in range(3):
+        queue.add_job(simple_job)
+    
+    print("Waiting for jobs with a short timeout...")
+    print(f"Completed within 0.1s: {queue.wait_completion(0.1)}")
+    
+    print("Waiting for jobs without a timeout...")
+    completed = queue.wait_completion()
+    print(f"Completed: {completed}, active jobs: {queue.active_jobs()}")
+    
+    if not completed or queue.active_jobs() != 0:
+        print("WARNING: wait_completion returned before the pool was idle!")
+    
+    del queue
+
+def diagnose_grouped_jobs():
+    """Test that grouped jobs all run and respect the batch limit"""
+    print("\n==== Testing grouped jobs ====")
+    
+    queue = nornir.Aqueue(2, group_batch_limit=3)
+    order = []
+    order_lock = threading.Lock()
+    
+    def grouped_job(group, index):
+        with order_lock:
+            order.append((group, index))
+        time.sleep(0.01)
+    
+    for group in (1, 2):
+        for i in range(6):
+            queue.add_job_grouped(lambda g=group, idx=i: grouped_job(g, idx), group)
+    
+    completed = queue.wait_completion(10.0)
+    print(f"Completed: {completed}, jobs run: {len(order)}/12")
+    print(f"Execution order: {order}")
+    
+    if len(order) != 12:
+        print("WARNING: Not every grouped job ran exactly once!")
+    
+    del queue
+    
+    # With one worker held up while both groups queue, the batches alternate
+    queue = nornir.Aqueue(1, group_batch_limit=3, hard_capacity=100)
+    order.clear()
+    queue.add_job(lambda: time.sleep(0.2))
+    time.sleep(0.05)
+    for group in (1, 2):
+        for i in range(6):
+            queue.add_job_grouped(lambda g=group, idx=i: grouped_job(g, idx), group)
+    
+    queue.wait_completion(10.0)
+    groups = [group for group, _ in order]
+    print(f"Groups in execution order: {groups}")
+    
+    if groups != [1, 1, 1, 2, 2, 2, 1, 1, 1, 2, 2, 2]:
+        print("WARNING: group batches did not take turns!")
+    
+    del queue
+
+def diagnose_limiter():
+    """Test that a limiter caps concurrency and survives failing jobs"""
+    print("\n==== Testing limiter ====")
+    
+    queue = nornir.Aqueue(4)
+    limiter = queue.new_limiter(2)
+    running = 0
+    peak = 0
+    running_lock = threading.Lock()
+    
+    def limited_job(should_fail):
+        nonlocal running, peak
+        with running_lock:
+            running += 1
+            peak = max(peak, running)
+        time.sleep(0.05)
+        with running_lock:
+            running -= 1
+        if should_fail:
+            raise ValueError("Intentional failure")
+    
+    for i in range(8):
+        queue.add_job_limited(lambda fail=(i % 3 == 0): limited_job(fail), limiter)
+    
+    queue.wait_completion(10.0)
+    print(f"Peak concurrency: {peak} (limit {limiter.max_concurrent})")
+    print(f"Permits available after completion: {limiter.available}")
+    
+    if peak > limiter.max_concurrent or limiter.available != limiter.max_concurrent:
+        print("WARNING: Limiter did not enforce or release its permits correctly!")
+    
+    del queue
+
+def diagnose_reduce():
+    """Test that reduce folds every result and surfaces job errors"""
+    print("\n==== Testing reduce ====")
+    
+    queue = nornir.Aqueue(2)
+    
+    total = queue.reduce(lambda x: x * x, range(50), lambda acc, r: acc + r, 0)
+    expected = sum(x * x for x in range(50))
+    print(f"Sum of squares: {total} (expected {expected})")
+    
+    if total != expected:
+        print("WARNING: reduce returned the wrong result!")
+    
+    try:
+        queue.reduce(lambda x: 1 / x, [1, 0, 2], lambda acc, r: acc + r, 0)
+        print("WARNING: reduce did not raise the job's exception!")
+    except ZeroDivisionError as e:
+        print(f"Job error propagated: {e}")
+    
+    queue.wait_completion(5.0)
+    del queue
+
 def diagnostics_report():
     """Print diagnostic information"""
     print("\n==== Diagnostics Report ====")
@@ -196,6 +323,1258 @@ def simple_manual_test():
         except Exception as e:
             print(f"Error closing worker: {e}")
 
+def diagnose_lifo():
+    """Test that a LIFO pool runs the newest queued job first"""
+    print("\n==== Testing LIFO order ====")
+    
+    queue = nornir.Aqueue(1, lifo=True)
+    order = []
+    
+    # Occupy the only worker so the rest of the jobs queue up behind it
+    queue.add_job(lambda: time.sleep(0.2))
+    time.sleep(0.05)
+    for i in range(2):
+        queue.add_job(lambda i=i: order.append(i))
+    
+    queue.wait_completion(10.0)
+    print(f"Execution order: {order} (expected [1, 0])")
+    
+    if order != [1, 0]:
+        print("WARNING: LIFO pool did not run the newest job first!")
+    
+    del queue
+
+def diagnose_submit():
+    """Test that submit returns futures usable with concurrent.futures"""
+    print("\n==== Testing submit ====")
+    
+    queue = nornir.Aqueue(2)
+    futures = [queue.submit(pow, i, 2) for i in range(10)]
+    results = sorted(f.result(timeout=10.0) for f in concurrent.futures.as_completed(futures))
+    print(f"Results: {results}")
+    
+    failing = queue.submit(lambda: 1 / 0)
+    error = failing.exception(timeout=10.0)
+    print(f"Job error propagated: {error}")
+    
+    if results != [i * i for i in range(10)] or not isinstance(error, ZeroDivisionError):
+        print("WARNING: submit futures did not resolve correctly!")
+    
+    del queue
+
+def diagnose_cancel_futures():
+    """Test that cancelled queued jobs are reported as cancelled everywhere"""
+    print("\n==== Testing cancel_futures ====")
+    
+    statuses = []
+    queue = nornir.Aqueue(1, hard_capacity=100, on_finally=lambda job_id, status: statuses.append(status))
+    
+    # Occupy the only worker so the rest of the jobs queue up behind it
+    queue.add_job(lambda: time.sleep(0.2))
+    time.sleep(0.05)
+    futures = [queue.submit(time.sleep, 0) for _ in range(3)]
+    queue.shutdown(cancel_futures=True)
+    print(f"on_finally statuses: {sorted(statuses)}")
+    print(f"Futures cancelled: {[f.cancelled() for f in futures]}")
+    
+    if sorted(statuses) != ["cancelled"] * 3 + ["completed"] or not all(f.cancelled() for f in futures):
+        print("WARNING: shutdown(cancel_futures=True) did not cancel the queued jobs!")
+    
+    # drain_pending takes the same path but keeps the pool, so its stats can
+    # be compared with what on_finally saw
+    statuses.clear()
+    queue = nornir.Aqueue(1, hard_capacity=100, on_finally=lambda job_id, status: statuses.append(status))
+    queue.add_job(lambda: time.sleep(0.2))
+    time.sleep(0.05)
+    for _ in range(3):
+        queue.add_job(lambda: None)
+    queue.drain_pending()
+    queue.wait_completion(10.0)
+    stats = queue.drain_stats()
+    print(f"on_finally statuses: {sorted(statuses)}, stats: {stats}")
+    
+    if stats["cancelled"] != statuses.count("cancelled") or stats["completed"] != statuses.count("completed"):
+        print("WARNING: on_finally and the stats disagree about cancelled jobs!")
+    
+    del queue
+
+def diagnose_drain_stats():
+    """Test that drain_stats reports each job once across consecutive drains"""
+    print("\n==== Testing drain_stats ====")
+    
+    queue = nornir.Aqueue(2, hard_capacity=100)
+    for _ in range(5):
+        queue.add_job(lambda: None)
+    queue.add_job(lambda: 1 / 0)
+    queue.wait_completion(10.0)
+    
+    first = queue.drain_stats()
+    second = queue.drain_stats()
+    print(f"First drain: {first}")
+    print(f"Second drain: {second}")
+    
+    for _ in range(3):
+        queue.add_job(lambda: None)
+    queue.wait_completion(10.0)
+    third = queue.drain_stats()
+    print(f"Third drain: {third}")
+    
+    if (first["submitted"], first["completed"], first["failed"]) != (6, 5, 1):
+        print("WARNING: drain_stats miscounted the first batch!")
+    if any(second.values()):
+        print("WARNING: drain_stats did not reset its counters!")
+    if (third["submitted"], third["completed"]) != (3, 3) or queue.stats()["completed"] != 8:
+        print("WARNING: drain_stats double-counted or reset stats()!")
+    
+    del queue
+
+def diagnose_wait_for_count():
+    """Test that wait_for_count only counts jobs that completed successfully"""
+    print("\n==== Testing wait_for_count ====")
+    
+    queue = nornir.Aqueue(2, hard_capacity=100)
+    for _ in range(5):
+        queue.add_job(lambda: 1 / 0)
+    queue.wait_completion(10.0)
+    
+    # Only the failures have finished, so nothing counts yet
+    failed_only = queue.wait_for_count(1, timeout=0.2)
+    print(f"Completed after 5 failures: {failed_only} (expected 0)")
+    
+    for _ in range(3):
+        queue.add_job(lambda: time.sleep(0.05))
+    reached = queue.wait_for_count(2, timeout=10.0)
+    print(f"Completed while waiting for 2: {reached}")
+    
+    if failed_only != 0 or reached < 2:
+        print("WARNING: wait_for_count counted failed jobs or stopped early!")
+    
+    queue.wait_completion(10.0)
+    del queue
+
+def diagnose_graceful_stop():
+    """Test that graceful_stop starts nothing once a long job exhausts the budget"""
+    print("\n==== Testing graceful_stop ====")
+    
+    started = []
+    finished = threading.Event()
+    
+    def long_job():
+        started.append("long")
+        time.sleep(1.0)
+        finished.set()
+    
+    queue = nornir.Aqueue(1, hard_capacity=100)
+    queue.add_job(long_job)
+    time.sleep(0.05)
+    for i in range(3):
+        queue.add_job(lambda i=i: started.append(i))
+    queue.add_shutdown_job(lambda: started.append("shutdown"))
+    
+    begin = time.monotonic()
+    abandoned = queue.graceful_stop(0.2)
+    elapsed = time.monotonic() - begin
+    print(f"Abandoned: {abandoned} (expected 5) after {elapsed:.2f}s, running: {queue.running}")
+    
+    # The long job finishes in the background, and nothing runs after it
+    finished.wait(5.0)
+    time.sleep(0.2)
+    print(f"Jobs that started: {started}")
+    
+    if abandoned != 5 or elapsed > 0.5 or started != ["long"] or not finished.is_set():
+        print("WARNING: graceful_stop ran work past its budget or miscounted!")
+    
+    del queue
+
+def diagnose_submit_cancellable():
+    """Test that the cancel function from submit_cancellable cancels only queued jobs"""
+    print("\n==== Testing submit_cancellable ====")
+    
+    ran = []
+    queue = nornir.Aqueue(1, hard_capacity=100)
+    
+    # The first job holds the only worker, so the second is still queued
+    running, cancel_running = queue.submit_cancellable(lambda: (time.sleep(0.2), ran.append("running")))
+    time.sleep(0.05)
+    queued, cancel_queued = queue.submit_cancellable(lambda: ran.append("queued"))
+    
+    cancelled_queued = cancel_queued()
+    cancelled_running = cancel_running()
+    queue.wait_completion(10.0)
+    print(f"cancel() results: queued={cancelled_queued}, running={cancelled_running}, ran: {ran}")
+    
+    if not cancelled_queued or not queued.cancelled() or "queued" in ran:
+        print("WARNING: The cancel function did not cancel the queued job!")
+    if cancelled_running or ran != ["running"]:
+        print("WARNING: The cancel function stopped a job that had already started!")
+    
+    del queue
+    
+    # A cancelled job gives its queue slot back straight away
+    queue = nornir.Aqueue(1, hard_capacity=2, overflow="drop_new")
+    queue.add_job(lambda: time.sleep(0.3))
+    time.sleep(0.05)
+    cancels = [queue.submit_cancellable(lambda: None)[1] for _ in range(2)]
+    for cancel in cancels:
+        cancel()
+    try:
+        for _ in range(2):
+            queue.add_job(lambda: None)
+        print("Queued two jobs into the slots the cancelled jobs gave back")
+    except nornir.QueueFullError:
+        print("WARNING: Cancelled jobs kept their queue slots!")
+    queue.wait_completion(10.0)
+    del queue
+
+def diagnose_job_metadata():
+    """Test that job metadata reaches pending_jobs and the opted-in callbacks"""
+    print("\n==== Testing job metadata ====")
+    
+    completed = []
+    finished = []
+    queue = nornir.Aqueue(
+        1,
+        hard_capacity=100,
+        callback_metadata=True,
+        on_complete_timed=lambda job_id, result, exec_time, metadata: completed.append((result, metadata)),
+        on_finally=lambda job_id, status, metadata: finished.append((status, metadata)),
+    )
+    
+    # Hold the only worker so the tagged jobs are still listed as pending
+    queue.add_job(lambda: time.sleep(0.2))
+    time.sleep(0.05)
+    queue.add_job(lambda: "a", metadata={"request_id": "a"})
+    future = queue.submit(lambda: 1 / 0, metadata={"request_id": "b"})
+    pending = [metadata for _, _, metadata in queue.pending_jobs(with_metadata=True)]
+    queue.wait_completion(10.0)
+    print(f"pending metadata: {pending}")
+    print(f"on_complete_timed: {completed}, on_finally: {finished}")
+    
+    if pending != [{"request_id": "a"}, {"request_id": "b"}]:
+        print("WARNING: pending_jobs did not list the jobs' metadata!")
+    if completed != [(None, None), ("a", {"request_id": "a"})]:
+        print("WARNING: on_complete_timed did not get the job's metadata!")
+    if finished != [("completed", None), ("completed", {"request_id": "a"}), ("failed", {"request_id": "b"})]:
+        print("WARNING: on_finally did not get the job's metadata!")
+    if not isinstance(future.exception(), ZeroDivisionError):
+        print("WARNING: metadata changed how the submitted job ran!")
+    
+    # Without the flag the callbacks keep their usual signatures
+    statuses = []
+    queue = nornir.Aqueue(1, on_finally=lambda job_id, status: statuses.append(status))
+    queue.add_job(lambda: None, metadata={"request_id": "c"})
+    queue.wait_completion(10.0)
+    print(f"Untagged on_finally statuses: {statuses}")
+    
+    if statuses != ["completed"]:
+        print("WARNING: metadata broke a callback that did not opt in!")
+    
+    del queue
+
+def diagnose_main_thread_queue():
+    """Test that main_thread_queue routes jobs to the main thread"""
+    print("\n==== Testing main_thread_queue ====")
+    
+    queue = nornir.Aqueue(2)
+    main_queue = queue.main_thread_queue()
+    threads = []
+    
+    # Worker jobs hand follow-up work to the main thread through the handle
+    for _ in range(3):
+        queue.add_job(lambda: main_queue.put(lambda: threads.append(threading.current_thread())))
+    queue.wait_completion(10.0)
+    print(f"Waiting on the main thread: {len(main_queue)} (main_pending {queue.main_pending()})")
+    
+    if len(main_queue) != 3 or queue.main_pending() != 3:
+        print("WARNING: main_thread_queue did not share the add_job_main queue!")
+    
+    ran = main_queue.pump(2)
+    ran += queue.pump_main()
+    print(f"Ran {ran} jobs, all on the main thread: {all(t is threading.main_thread() for t in threads)}")
+    
+    if ran != 3 or len(threads) != 3 or not all(t is threading.main_thread() for t in threads):
+        print("WARNING: main_thread_queue jobs did not run on the main thread!")
+    
+    # Pumping from anywhere else is refused
+    errors = []
+    def pump_elsewhere():
+        try:
+            main_queue.pump()
+        except RuntimeError as e:
+            errors.append(e)
+    thread = threading.Thread(target=pump_elsewhere)
+    thread.start()
+    thread.join()
+    
+    if len(errors) != 1:
+        print("WARNING: MainThreadQueue.pump ran off the main thread!")
+    
+    del main_queue
+    del queue
+
+def diagnose_coalesce():
+    """Test that coalesced jobs keep their own callbacks, stats and errors"""
+    print("\n==== Testing coalesce ====")
+    
+    finished = {}
+    ran = []
+    queue = nornir.Aqueue(
+        1,
+        hard_capacity=100,
+        coalesce=8,
+        on_finally=lambda job_id, status: finished.setdefault(job_id, []).append(status),
+    )
+    
+    # Hold the worker so the jobs pile up and are taken in batches
+    queue.add_job(lambda: time.sleep(0.1))
+    time.sleep(0.05)
+    
+    def job(i):
+        if i % 5 == 0:
+            raise ValueError(f"job {i}")
+        ran.append(i)
+    
+    ids = [queue.add_job(lambda i=i: job(i)) for i in range(40)]
+    queue.wait_completion(10.0)
+    stats = queue.drain_stats()
+    failed = [i for i, job_id in enumerate(ids) if finished.get(job_id) == ["failed"]]
+    print(f"Ran: {len(ran)}, failed: {failed}, stats: {stats}")
+    
+    if any(len(finished.get(job_id, [])) != 1 for job_id in ids):
+        print("WARNING: A coalesced job did not get exactly one on_finally call!")
+    if failed != list(range(0, 40, 5)) or ran != [i for i in range(40) if i % 5]:
+        print("WARNING: A failing job affected the rest of its batch!")
+    if stats["completed"] != 33 or stats["failed"] != 8:
+        print("WARNING: The stats did not count each coalesced job!")
+    
+    del queue
+
+def diagnose_reserve():
+    """Test that reserve fails instead of waiting for more slots than the queue holds"""
+    print("\n==== Testing reserve ====")
+    
+    queue = nornir.Aqueue(1, hard_capacity=10)
+    
+    try:
+        queue.reserve(11)
+        print("WARNING: reserve accepted more slots than the queue holds!")
+    except ValueError as e:
+        print(f"Oversized reserve rejected: {e}")
+    
+    # Hold most of the slots so the next reservation has to wait
+    held = queue.reserve(6)
+    outcome = []
+    def reserve_blocked():
+        try:
+            queue.reserve(8)
+            outcome.append("reserved")
+        except ValueError:
+            outcome.append("rejected")
+    thread = threading.Thread(target=reserve_blocked)
+    thread.start()
+    time.sleep(0.1)
+    
+    # Shrinking below what the waiter wants must wake it rather than leave it stuck
+    queue.set_queue_capacity(5)
+    thread.join(5.0)
+    print(f"Waiting reserve after shrinking: {outcome}")
+    
+    if thread.is_alive() or outcome != ["rejected"]:
+        print("WARNING: reserve kept waiting after the capacity shrank below it!")
+    
+    held.release()
+    del queue
+
+def diagnose_max_overtakes():
+    """Test that max_overtakes stops a stream of higher-priority jobs starving a keyed job"""
+    print("\n==== Testing max_overtakes ====")
+    
+    def run(max_overtakes):
+        order = []
+        queue = nornir.Aqueue(1, hard_capacity=100, max_overtakes=max_overtakes)
+        # The running job holds the key, so everything after it waits on it
+        queue.add_job_keyed(lambda: time.sleep(0.2), "account")
+        time.sleep(0.05)
+        queue.add_job_keyed(lambda: order.append("low"), "account", priority=0)
+        for i in range(5):
+            queue.add_job_keyed(lambda i=i: order.append(f"high{i}"), "account", priority=5)
+        queue.wait_completion(10.0)
+        del queue
+        return order
+    
+    bounded = run(2)
+    unbounded = run(None)
+    print(f"max_overtakes=2: {bounded}")
+    print(f"max_overtakes=None: {unbounded}")
+    
+    if bounded.index("low") != 2:
+        print("WARNING: The low-priority job was passed more than max_overtakes times!")
+    if unbounded[-1] != "low":
+        print("WARNING: Without max_overtakes the higher-priority jobs did not all go first!")
+    if [name for name in bounded if name != "low"] != [f"high{i}" for i in range(5)]:
+        print("WARNING: The higher-priority jobs lost their submission order!")
+
+def diagnose_overflow_policies():
+    """Test what each overflow policy does with a job for a full queue"""
+    print("\n==== Testing overflow policies ====")
+    
+    def fill(overflow):
+        ran = []
+        statuses = []
+        queue = nornir.Aqueue(
+            1,
+            hard_capacity=2,
+            overflow=overflow,
+            on_finally=lambda job_id, status: statuses.append(status),
+        )
+        # The busy worker leaves room for exactly two waiting jobs
+        queue.add_job(lambda: time.sleep(0.2))
+        time.sleep(0.05)
+        queue.add_job(lambda: ran.append(1))
+        queue.add_job(lambda: ran.append(2))
+        return queue, ran, statuses
+    
+    queue, ran, statuses = fill("drop_new")
+    try:
+        queue.add_job(lambda: ran.append(3))
+        print("WARNING: drop_new accepted a job for a full queue!")
+    except nornir.QueueFullError:
+        pass
+    queue.wait_completion(10.0)
+    print(f"drop_new ran {ran}, dropped {queue.stats()['dropped']}")
+    
+    if ran != [1, 2] or queue.stats()["dropped"] != 1:
+        print("WARNING: drop_new did not reject only the incoming job!")
+    del queue
+    
+    queue, ran, statuses = fill("drop_old")
+    queue.add_job(lambda: ran.append(3))
+    queue.wait_completion(10.0)
+    print(f"drop_old ran {ran}, dropped {queue.stats()['dropped']}, statuses {sorted(statuses)}")
+    
+    if ran != [2, 3] or queue.stats()["dropped"] != 1 or statuses.count("dropped") != 1:
+        print("WARNING: drop_old did not evict the oldest queued job!")
+    del queue
+    
+    queue, ran, statuses = fill("block")
+    start = time.time()
+    queue.add_job(lambda: ran.append(3))
+    waited = time.time() - start
+    queue.wait_completion(10.0)
+    print(f"block ran {ran} after waiting {waited:.2f}s, dropped {queue.stats()['dropped']}")
+    
+    if ran != [1, 2, 3] or queue.stats()["dropped"] != 0 or waited < 0.05:
+        print("WARNING: block did not wait for room in the queue!")
+    del queue
+
+def diagnose_lazy_workers():
+    """Test that a lazy pool spawns workers on demand and retires idle ones"""
+    print("\n==== Testing lazy workers ====")
+    
+    queue = nornir.Aqueue(4, lazy=True, min_workers=1, idle_timeout=0.2, warn_oversubscribe=False)
+    initial = queue.active_workers
+    for _ in range(4):
+        queue.add_job(lambda: time.sleep(0.2))
+    time.sleep(0.1)
+    busy = queue.active_workers
+    queue.wait_completion(10.0)
+    
+    # Idle workers above min_workers retire once idle_timeout has passed
+    deadline = time.time() + 5.0
+    while queue.active_workers > 1 and time.time() < deadline:
+        time.sleep(0.05)
+    idle = queue.active_workers
+    print(f"Workers at start: {initial}, under load: {busy}, after idling: {idle}")
+    
+    if initial != 1:
+        print("WARNING: A lazy pool started more than min_workers workers!")
+    if busy != 4:
+        print("WARNING: A lazy pool did not spawn workers for the queued jobs!")
+    if idle != 1:
+        print("WARNING: Idle workers did not retire down to min_workers!")
+    
+    del queue
+
+def diagnose_job_groups():
+    """Test that wait_group tallies the outcomes of one group's jobs"""
+    print("\n==== Testing add_job_in_group and wait_group ====")
+    
+    def fail():
+        raise ValueError("group job failed")
+    
+    queue = nornir.Aqueue(2, hard_capacity=100)
+    for i in range(6):
+        queue.add_job_in_group(fail if i % 3 == 0 else (lambda: time.sleep(0.05)), 1)
+    # Jobs outside the group are not counted
+    queue.add_job_in_group(lambda: time.sleep(0.3), 2)
+    summary = queue.wait_group(1, 10.0)
+    print(f"Group 1: {summary}")
+    
+    if summary["completed"] != 4 or summary["failed"] != 2 or summary["pending"] != 0:
+        print("WARNING: wait_group miscounted the group's outcomes!")
+    
+    # The tally starts over once the group has finished
+    queue.add_job_in_group(lambda: None, 1)
+    again = queue.wait_group(1, 10.0)
+    print(f"Group 1 again: {again}")
+    
+    if again["completed"] != 1 or again["failed"] != 0:
+        print("WARNING: wait_group did not reset the group's tally!")
+    
+    # A timeout reports what is still unfinished
+    queue.add_job_in_group(lambda: time.sleep(0.5), 3)
+    partial = queue.wait_group(3, 0.05)
+    print(f"Group 3 after timing out: {partial}")
+    
+    if partial["pending"] != 1:
+        print("WARNING: wait_group did not report the pending job on timeout!")
+    
+    queue.wait_completion(10.0)
+    del queue
+
+def diagnose_keyed_jobs():
+    """Test that jobs sharing a key run one at a time and other keys in parallel"""
+    print("\n==== Testing add_job_keyed ====")
+    
+    lock = threading.Lock()
+    running = {"a": 0, "b": 0}
+    most = {"a": 0, "b": 0}
+    overlap = []
+    order = {"a": [], "b": []}
+    
+    def job(key, i):
+        with lock:
+            running[key] += 1
+            most[key] = max(most[key], running[key])
+            overlap.append(running["a"] > 0 and running["b"] > 0)
+            order[key].append(i)
+        time.sleep(0.02)
+        with lock:
+            running[key] -= 1
+    
+    queue = nornir.Aqueue(4, hard_capacity=100, warn_oversubscribe=False)
+    for i in range(8):
+        queue.add_job_keyed(lambda i=i: job("a", i), "a")
+        queue.add_job_keyed(lambda i=i: job("b", i), "b")
+    queue.wait_completion(10.0)
+    print(f"Most running at once per key: {most}, keys overlapped: {any(overlap)}")
+    print(f"Order per key: {order}")
+    
+    if most != {"a": 1, "b": 1}:
+        print("WARNING: Jobs with the same key ran at the same time!")
+    if not any(overlap):
+        print("WARNING: Jobs with different keys did not run in parallel!")
+    if order != {"a": list(range(8)), "b": list(range(8))}:
+        print("WARNING: Jobs with the same key ran out of submission order!")
+    
+    del queue
+
+def diagnose_idempotent_jobs():
+    """Test that add_job_idempotent dedupes a key until its job finishes"""
+    print("\n==== Testing add_job_idempotent ====")
+    
+    ran = []
+    queue = nornir.Aqueue(1, hard_capacity=100)
+    
+    # Hold the worker so the first keyed job is still queued
+    queue.add_job(lambda: time.sleep(0.1))
+    first = queue.add_job_idempotent(lambda: ran.append("first"), "order-1")
+    duplicate = queue.add_job_idempotent(lambda: ran.append("duplicate"), "order-1")
+    other = queue.add_job_idempotent(lambda: ran.append("other"), "order-2")
+    queue.wait_completion(10.0)
+    print(f"Ids: first={first}, duplicate={duplicate}, other={other}; ran {ran}")
+    
+    if duplicate != first or ran != ["first", "other"]:
+        print("WARNING: add_job_idempotent queued a duplicate of a pending job!")
+    
+    # Once the job has finished the key can be used again
+    again = queue.add_job_idempotent(lambda: ran.append("again"), "order-1")
+    queue.wait_completion(10.0)
+    print(f"Resubmitted after it finished: id {again}, ran {ran}")
+    
+    if again == first or ran[-1] != "again":
+        print("WARNING: add_job_idempotent kept the key after the job finished!")
+    
+    del queue
+
+def diagnose_replace_jobs():
+    """Test that add_job_replace supersedes a queued job with the same key"""
+    print("\n==== Testing add_job_replace ====")
+    
+    ran = []
+    statuses = {}
+    queue = nornir.Aqueue(1, hard_capacity=100, on_finally=lambda job_id, status: statuses.update({job_id: status}))
+    
+    # The first job for the key is running, so it is left alone
+    running = queue.add_job_replace(lambda: (time.sleep(0.1), ran.append("running")), "price")
+    time.sleep(0.05)
+    stale = queue.add_job_replace(lambda: ran.append("stale"), "price")
+    latest = queue.add_job_replace(lambda: ran.append("latest"), "price")
+    other = queue.add_job_replace(lambda: ran.append("other"), "volume")
+    queue.wait_completion(10.0)
+    print(f"Ran {ran}; statuses {[statuses.get(i) for i in (running, stale, latest, other)]}")
+    
+    if ran != ["running", "latest", "other"]:
+        print("WARNING: add_job_replace did not run only the latest waiting job per key!")
+    if statuses.get(stale) != "cancelled" or statuses.get(running) != "completed":
+        print("WARNING: The superseded job did not settle as cancelled!")
+    
+    del queue
+    
+    # A replaced job gives up its queue slot, so a full queue never blocks
+    # or rejects a replacement
+    for overflow in ["block", "drop_new"]:
+        queue = nornir.Aqueue(1, hard_capacity=2, overflow=overflow)
+        queue.add_job(lambda: time.sleep(0.5))
+        time.sleep(0.05)
+        queue.add_job(lambda: None)
+        start = time.time()
+        try:
+            for i in range(5):
+                queue.add_job_replace(lambda i=i: None, "k")
+            rejected = False
+        except nornir.QueueFullError:
+            rejected = True
+        waited = time.time() - start
+        print(f"overflow={overflow}: five replacements took {waited:.2f}s, rejected: {rejected}")
+        
+        if waited > 0.2 or rejected:
+            print("WARNING: Replaced jobs kept their queue slots!")
+        queue.wait_completion(10.0)
+        del queue
+
+def diagnose_full_callbacks():
+    """Test that on_full and on_not_full fire once per saturation"""
+    print("\n==== Testing on_full and on_not_full ====")
+    
+    events = []
+    queue = nornir.Aqueue(
+        1,
+        hard_capacity=4,
+        overflow="drop_new",
+        on_full=lambda: events.append("full"),
+        on_not_full=lambda: events.append("not_full"),
+    )
+    
+    def saturate():
+        # The busy worker leaves the four slots to the waiting jobs
+        queue.add_job(lambda: time.sleep(0.1))
+        time.sleep(0.05)
+        for _ in range(3):
+            queue.add_job(lambda: None)
+        after_three = list(events)
+        queue.add_job(lambda: None)
+        try:
+            queue.add_job(lambda: None)
+        except nornir.QueueFullError:
+            pass
+        return after_three
+    
+    after_three = saturate()
+    full_events = list(events)
+    queue.wait_completion(10.0)
+    drained_events = list(events)
+    saturate()
+    queue.wait_completion(10.0)
+    print(f"Before the last slot: {after_three}, when full: {full_events}, drained: {drained_events}")
+    print(f"After filling it again: {events}")
+    
+    if after_three != [] or full_events != ["full"]:
+        print("WARNING: on_full did not fire exactly when the last slot was taken!")
+    if drained_events != ["full", "not_full"]:
+        print("WARNING: on_not_full did not fire once the queue drained!")
+    if events != ["full", "not_full", "full", "not_full"]:
+        print("WARNING: The callbacks did not fire once per saturation!")
+    
+    del queue
+
+def diagnose_set_queue_capacity():
+    """Test resizing the queue of a running pool"""
+    print("\n==== Testing set_queue_capacity ====")
+    
+    ran = []
+    queue = nornir.Aqueue(1, hard_capacity=2, overflow="drop_new")
+    
+    # Fill both slots behind a busy worker, then make room for more
+    queue.add_job(lambda: time.sleep(0.2))
+    time.sleep(0.05)
+    for i in range(2):
+        queue.add_job(lambda i=i: ran.append(i))
+    queue.set_queue_capacity(4)
+    grown = queue.queue_capacity()
+    for i in range(2, 4):
+        queue.add_job(lambda i=i: ran.append(i))
+    
+    # Shrinking keeps what is queued and just stops admitting jobs
+    queue.set_queue_capacity(1)
+    try:
+        queue.add_job(lambda: ran.append("rejected"))
+        admitted = True
+    except nornir.QueueFullError:
+        admitted = False
+    queue.wait_completion(10.0)
+    queue.add_job(lambda: ran.append("after"))
+    queue.wait_completion(10.0)
+    print(f"Capacity after growing: {grown}, after shrinking: {queue.queue_capacity()}")
+    print(f"Admitted over the shrunk capacity: {admitted}; ran {ran}")
+    
+    if grown != 4 or queue.queue_capacity() != 1:
+        print("WARNING: queue_capacity did not report the new capacity!")
+    if admitted or ran != [0, 1, 2, 3, "after"]:
+        print("WARNING: Resizing the queue dropped jobs or ignored the new capacity!")
+    
+    try:
+        queue.set_queue_capacity(0)
+        print("WARNING: set_queue_capacity accepted 0!")
+    except ValueError:
+        pass
+    
+    del queue
+
+def diagnose_keyed_priority():
+    """Test that a high-priority keyed job runs next on its key without preempting"""
+    print("\n==== Testing add_job_keyed priority ====")
+    
+    order = []
+    finished = {}
+    queue = nornir.Aqueue(2, hard_capacity=100)
+    
+    def run(name, duration=0.0):
+        order.append((name, time.time()))
+        time.sleep(duration)
+        finished[name] = time.time()
+    
+    queue.add_job_keyed(lambda: run("running", 0.2), "account")
+    time.sleep(0.05)
+    queue.add_job_keyed(lambda: run("low1"), "account", priority=0)
+    queue.add_job_keyed(lambda: run("low2"), "account", priority=0)
+    queue.add_job_keyed(lambda: run("high"), "account", priority=10)
+    queue.wait_completion(10.0)
+    names = [name for name, _ in order]
+    high_start = dict(order)["high"]
+    print(f"Order on the key: {names}")
+    
+    if names != ["running", "high", "low1", "low2"]:
+        print("WARNING: The high-priority job did not run next on its key!")
+    if high_start < finished["running"]:
+        print("WARNING: The high-priority job preempted the job holding the key!")
+    
+    del queue
+
+def diagnose_reschedule_routes():
+    """Test that a rescheduled job keeps its limiter and its key"""
+    print("\n==== Testing reschedule with limiters and keys ====")
+    
+    queue = nornir.Aqueue(4)
+    limiter = queue.new_limiter(1)
+    running = 0
+    peak = 0
+    runs = []
+    running_lock = threading.Lock()
+    
+    def polling_job(name):
+        nonlocal running, peak
+        with running_lock:
+            running += 1
+            peak = max(peak, running)
+            runs.append(name)
+            again = runs.count(name) < 3
+        time.sleep(0.02)
+        with running_lock:
+            running -= 1
+        if again:
+            queue.reschedule()
+    
+    for i in range(3):
+        queue.add_job_limited(lambda name=f"limited{i}": polling_job(name), limiter)
+    queue.wait_completion(10.0)
+    print(f"Limited runs: {len(runs)}, peak concurrency: {peak} (limit 1)")
+    
+    if len(runs) != 9 or peak > 1 or limiter.available != 1:
+        print("WARNING: A rescheduled job ran without its limiter's permit!")
+    
+    running = 0
+    peak = 0
+    runs.clear()
+    for i in range(3):
+        queue.add_job_keyed(lambda name=f"keyed{i}": polling_job(name), "key")
+    queue.wait_completion(10.0)
+    print(f"Keyed runs: {len(runs)}, peak concurrency: {peak} (one key)")
+    
+    if len(runs) != 9 or peak > 1:
+        print("WARNING: A rescheduled job ran alongside another job for its key!")
+    
+    del queue
+
+def diagnose_queue_len():
+    """Test that queue_len counts waiting jobs, not stale messages"""
+    print("\n==== Testing queue_len ====")
+    
+    queue = nornir.Aqueue(1, hard_capacity=8)
+    release = threading.Event()
+    queue.add_job(release.wait)
+    time.sleep(0.1)
+    
+    # Each replacement leaves the superseded job's message behind
+    for i in range(5):
+        queue.add_job_replace(lambda: None, "k")
+    queue.add_job_grouped(lambda: None, 1)
+    queue.add_job_keyed(lambda: None, "key")
+    queue_len = queue.stats()["queue_len"]
+    print(f"queue_len with one replaced, one grouped and one keyed job: {queue_len}")
+    
+    if queue_len != 3:
+        print("WARNING: queue_len counted stale messages or missed a sub-queue!")
+    
+    release.set()
+    queue.wait_completion(5.0)
+    queue_len = queue.stats()["queue_len"]
+    print(f"queue_len once drained: {queue_len}")
+    
+    if queue_len != 0 or queue.estimated_drain_time() != 0.0:
+        print("WARNING: queue_len did not return to 0!")
+    
+    del queue
+
+def diagnose_watermark():
+    """Test that on_watermark follows the waiting jobs, not stale messages"""
+    print("\n==== Testing on_watermark ====")
+    
+    levels = []
+    queue = nornir.Aqueue(1, soft_capacity=2, hard_capacity=8, on_watermark=levels.append)
+    release = threading.Event()
+    queue.add_job(release.wait)
+    time.sleep(0.1)
+    
+    # Only one replaced job is ever waiting, however many messages it leaves
+    for i in range(5):
+        queue.add_job_replace(lambda: None, "k")
+    print(f"Levels after five replacements: {levels}")
+    
+    if levels:
+        print("WARNING: Replaced jobs pushed the queue past soft_capacity!")
+    
+    for i in range(3):
+        queue.add_job(lambda: None)
+    release.set()
+    queue.wait_completion(5.0)
+    print(f"Levels after three more jobs drained: {levels}")
+    
+    if levels != ["high", "low"]:
+        print("WARNING: on_watermark did not report the crossing both ways!")
+    
+    del queue
+
+def diagnose_backlog_alarm():
+    """Test that the backlog alarm follows the waiting jobs, not stale messages"""
+    print("\n==== Testing backlog_alarm ====")
+    
+    states = []
+    queue = nornir.Aqueue(1, hard_capacity=8, backlog_alarm=(2, 0.0), on_backlog_alarm=states.append)
+    release = threading.Event()
+    queue.add_job(release.wait)
+    time.sleep(0.1)
+    
+    # Only one replaced job is ever waiting, however many messages it leaves
+    for i in range(5):
+        queue.add_job_replace(lambda: None, "k")
+    print(f"States after five replacements: {states}")
+    
+    if states:
+        print("WARNING: Replaced jobs raised the backlog alarm!")
+    
+    for i in range(3):
+        queue.add_job(lambda: None)
+    release.set()
+    queue.wait_completion(5.0)
+    time.sleep(0.1)
+    print(f"States after three more jobs drained: {states}")
+    
+    if states != ["raised", "cleared"]:
+        print("WARNING: The backlog alarm was not raised and cleared!")
+    
+    del queue
+
+def diagnose_add_job_with_depth():
+    """Test that add_job_with_depth reports the waiting jobs"""
+    print("\n==== Testing add_job_with_depth ====")
+    
+    queue = nornir.Aqueue(1, hard_capacity=8)
+    release = threading.Event()
+    queue.add_job(release.wait)
+    time.sleep(0.1)
+    
+    depths = [queue.add_job_with_depth(lambda: None)[1] for i in range(2)]
+    # Stale messages from the superseded jobs are not counted
+    for i in range(3):
+        queue.add_job_replace(lambda: None, "k")
+    depths.append(queue.add_job_with_depth(lambda: None)[1])
+    print(f"Depths reported: {depths}")
+    
+    if depths != [1, 2, 4]:
+        print("WARNING: add_job_with_depth reported the wrong depth!")
+    
+    release.set()
+    queue.wait_completion(5.0)
+    del queue
+
+def diagnose_submit_tracked():
+    """Test that submit_tracked's id and future belong to the same job"""
+    print("\n==== Testing submit_tracked ====")
+    
+    settled = {}
+    queue = nornir.Aqueue(2, on_finally=lambda job_id, status: settled.update({job_id: status}))
+    
+    tracked = [queue.submit_tracked(lambda i=i: i * 10) for i in range(5)]
+    results = {job_id: future.result(5.0) for job_id, future in tracked}
+    queue.wait_completion(5.0)
+    time.sleep(0.1)
+    print(f"Results by id: {results}")
+    
+    ids = [job_id for job_id, _ in tracked]
+    if len(set(ids)) != 5 or sorted(results.values()) != [0, 10, 20, 30, 40]:
+        print("WARNING: submit_tracked returned duplicate ids or wrong results!")
+    if any(settled.get(job_id) != "completed" for job_id in ids):
+        print("WARNING: on_finally did not report the ids submit_tracked returned!")
+    
+    del queue
+
+def diagnose_future_running():
+    """Test that a submit future reports running only while its job runs"""
+    print("\n==== Testing Future.running ====")
+    
+    queue = nornir.Aqueue(1)
+    started = threading.Event()
+    release = threading.Event()
+    
+    def blocking_job():
+        started.set()
+        release.wait(5.0)
+        return "done"
+    
+    running_future = queue.submit(blocking_job)
+    queued_future = queue.submit(lambda: None)
+    started.wait(5.0)
+    states = [running_future.running(), queued_future.running()]
+    release.set()
+    running_future.result(5.0)
+    queued_future.result(5.0)
+    states.append(running_future.running())
+    print(f"running() while running, while queued, once done: {states}")
+    
+    if states != [True, False, False]:
+        print("WARNING: Future.running() did not follow the job!")
+    
+    del queue
+
+# checkpoint() pickles its jobs, so they live at module level
+CHECKPOINT_RELEASE = threading.Event()
+CHECKPOINT_RAN = []
+
+def checkpoint_block():
+    CHECKPOINT_RELEASE.wait(5.0)
+
+def checkpoint_record(i):
+    CHECKPOINT_RAN.append(i)
+
+def diagnose_checkpoint():
+    """Test that checkpointed jobs are restored into a new pool and run"""
+    print("\n==== Testing checkpoint and restore ====")
+    
+    path = os.path.join(tempfile.mkdtemp(), "queue.ckpt")
+    queue = nornir.Aqueue(1, picklable=True, hard_capacity=8)
+    queue.add_job(checkpoint_block)
+    time.sleep(0.1)
+    for i in range(3):
+        queue.add_job(functools.partial(checkpoint_record, i))
+    saved = queue.checkpoint(path)
+    print(f"Saved {saved} jobs behind the blocked worker")
+    
+    if saved != 3:
+        print("WARNING: checkpoint saved the wrong number of jobs!")
+    
+    CHECKPOINT_RELEASE.set()
+    queue.wait_completion(5.0)
+    del queue
+    CHECKPOINT_RAN.clear()
+    
+    restored = nornir.Aqueue(1, picklable=True, restore=path)
+    restored.wait_completion(5.0)
+    print(f"Restored pool ran {CHECKPOINT_RAN}")
+    
+    if CHECKPOINT_RAN != [0, 1, 2]:
+        print("WARNING: The restored jobs did not all run in order!")
+    
+    del restored
+    os.remove(path)
+
+def diagnose_category_rate():
+    """Test that set_category_rate spaces jobs past the burst and keeps order"""
+    print("\n==== Testing set_category_rate ====")
+    
+    queue = nornir.Aqueue(2, hard_capacity=16)
+    queue.set_category_rate("email", 5.0)
+    starts = []
+    starts_lock = threading.Lock()
+    
+    def email_job(i):
+        with starts_lock:
+            starts.append((i, time.monotonic()))
+    
+    begin = time.monotonic()
+    for i in range(8):
+        queue.add_job(lambda i=i: email_job(i), category="email")
+    queue.wait_completion(10.0)
+    
+    order = [i for i, _ in starts]
+    offsets = [round(start - begin, 2) for _, start in starts]
+    print(f"Start order {order}, offsets {offsets}")
+    
+    # A second's worth starts at once, then one job every 0.2s
+    if len(starts) != 8 or max(offsets[:5]) > 0.15:
+        print("WARNING: The burst allowance did not start at once!")
+    gaps = [later - earlier for earlier, later in zip(offsets[4:], offsets[5:])]
+    if any(gap < 0.15 for gap in gaps):
+        print("WARNING: Jobs over the rate were not spaced out!")
+    if order[5:] != [5, 6, 7]:
+        print("WARNING: Throttled jobs did not keep their order!")
+    
+    del queue
+
+def diagnose_weighted_queues():
+    """Test that add_job_to serves sub-queues by weight without starving any"""
+    print("\n==== Testing add_job_to weights ====")
+    
+    queue = nornir.Aqueue(1, hard_capacity=64, sub_queues={"bulk": 3, "interactive": 1})
+    release = threading.Event()
+    ran = []
+    queue.add_job(release.wait)
+    time.sleep(0.1)
+    
+    # Flood the heavy sub-queue first, then add a few light jobs behind it
+    for i in range(30):
+        queue.add_job_to("bulk", lambda: ran.append("bulk"))
+    for i in range(5):
+        queue.add_job_to("interactive", lambda: ran.append("interactive"))
+    release.set()
+    queue.wait_completion(10.0)
+    
+    first = ran[:20]
+    last_interactive = max(i for i, name in enumerate(ran) if name == "interactive")
+    print(f"First 20 jobs: {first.count('bulk')} bulk, {first.count('interactive')} interactive")
+    print(f"Last interactive job ran at position {last_interactive} of {len(ran)}")
+    
+    if len(ran) != 35 or last_interactive >= 25:
+        print("WARNING: The flooded sub-queue starved the light one!")
+    if not 13 <= first.count("bulk") <= 17:
+        print("WARNING: The sub-queues were not served about 3:1!")
+    
+    del queue
+
+def diagnose_max_in_flight():
+    """Test that max_in_flight caps how many jobs run at once"""
+    print("\n==== Testing max_in_flight ====")
+    
+    queue = nornir.Aqueue(4, max_in_flight=2)
+    running = 0
+    peak = 0
+    running_lock = threading.Lock()
+    
+    def tracked_job():
+        nonlocal running, peak
+        with running_lock:
+            running += 1
+            peak = max(peak, running)
+        time.sleep(0.05)
+        with running_lock:
+            running -= 1
+    
+    for i in range(8):
+        queue.add_job(tracked_job)
+    
+    queue.wait_completion(10.0)
+    print(f"Peak concurrency: {peak} (max_in_flight 2, 4 workers)")
+    print(f"In flight after completion: {queue.stats()['in_flight']}")
+    
+    if peak != 2 or queue.stats()["in_flight"] != 0:
+        print("WARNING: max_in_flight did not cap or release the running jobs!")
+    
+    del queue
+
+class PriorityJobQueue:
+    """A custom queue= that hands out the highest-priority job first"""
+    
+    def __init__(self):
+        self.lock = threading.Lock()
+        self.heap = []
+        self.count = 0
+    
+    def put(self, job):
+        with self.lock:
+            self.count += 1
+            heapq.heappush(self.heap, (-getattr(job, "priority", 0), self.count, job))
+    
+    def get(self):
+        with self.lock:
+            return heapq.heappop(self.heap)[2]
+
+class PrioritizedJob:
+    def __init__(self, priority, ran):
+        self.priority = priority
+        self.ran = ran
+    
+    def __call__(self):
+        self.ran.append(self.priority)
+
+def diagnose_custom_queue():
+    """Test a custom queue= deciding the order, and queues that misbehave"""
+    print("\n==== Testing custom queue ====")
+    
+    ran = []
+    queue = nornir.Aqueue(1, hard_capacity=8, queue=PriorityJobQueue())
+    release = threading.Event()
+    queue.add_job(release.wait)
+    time.sleep(0.1)
+    for priority in [1, 5, 3, 4, 2]:
+        queue.add_job(PrioritizedJob(priority, ran))
+    release.set()
+    queue.wait_completion(5.0)
+    print(f"Priorities in the order they ran: {ran}")
+    
+    if ran != [5, 4, 3, 2, 1]:
+        print("WARNING: The custom queue did not decide the order!")
+    
+    del queue
+    
+    # get() raising loses that message's turn; the worker stays up and the
+    # next message runs whichever job the queue hands out then
+    class FailingOnceQueue(PriorityJobQueue):
+        failed = False
+        
+        def get(self):
+            if not self.failed:
+                self.failed = True
+                raise RuntimeError("Intentional get() failure")
+            return super().get()
+    
+    ran = []
+    queue = nornir.Aqueue(1, hard_capacity=8, queue=FailingOnceQueue())
+    queue.add_job(PrioritizedJob(1, ran))
+    time.sleep(0.1)
+    queue.add_job(PrioritizedJob(2, ran))
+    time.sleep(0.1)
+    print(f"After a failed get(), ran {ran}")
+    
+    if ran != [2]:
+        print("WARNING: A failed get() stopped the worker!")
+    
+    del queue
+    
+    # An object that was never put() is not run, and the worker stays up
+    # for the next message
+    class StrangerQueue(PriorityJobQueue):
+        handed_stranger = False
+        
+        def get(self):
+            if not self.handed_stranger:
+                self.handed_stranger = True
+                return PrioritizedJob(99, ran)
+            return super().get()
+    
+    ran = []
+    queue = nornir.Aqueue(1, hard_capacity=8, queue=StrangerQueue())
+    queue.add_job(PrioritizedJob(1, ran))
+    time.sleep(0.1)
+    queue.add_job(PrioritizedJob(2, ran))
+    time.sleep(0.1)
+    print(f"After get() returned a stranger, ran {ran}")
+    
+    if ran != [2]:
+        print("WARNING: A job that was never put() ran, or the worker stopped!")
+    
+    del queue
+
+def diagnose_drain_pending():
+    """Test that drain_pending hands back the unstarted jobs in order"""
+    print("\n==== Testing drain_pending ====")
+    
+    queue = nornir.Aqueue(1, hard_capacity=8)
+    started = threading.Event()
+    release = threading.Event()
+    ran = []
+    
+    def running_job():
+        started.set()
+        release.wait(5.0)
+        ran.append("running")
+    
+    queue.add_job(running_job)
+    started.wait(5.0)
+    queued = [lambda i=i: ran.append(i) for i in range(4)]
+    for job in queued:
+        queue.add_job(job)
+    
+    drained = queue.drain_pending()
+    release.set()
+    queue.join()
+    print(f"Drained {len(drained)} jobs; the pool ran {ran}")
+    
+    if drained != queued:
+        print("WARNING: drain_pending did not return the unstarted jobs in order!")
+    if ran != ["running"]:
+        print("WARNING: The running job did not finish, or a drained job ran!")
+    try:
+        queue.add_job(lambda: None)
+        print("WARNING: The pool accepted a job after drain_pending!")
+    except nornir.PoolShutdownError:
+        pass
+    
+    del queue
+
+SUBINTERPRETER_SCRIPT = """
+try:
+    import _xxsubinterpreters as interpreters
+    run = interpreters.run_string
+except ImportError:
+    import _interpreters as interpreters
+    run = interpreters.exec
+iid = interpreters.create()
+run(iid, '''
+import sys
+sys.path.insert(0, %r)
+import nornir
+marker = "sub"
+def job():
+    import __main__
+    return getattr(__main__, "marker", "main")
+queue = nornir.Aqueue(2)
+print("Interpreter seen by job:", queue.submit(job).result(timeout=10.0))
+queue.shutdown()
+''')
+interpreters.destroy(iid)
+"""
+
+def diagnose_subinterpreter():
+    """Test that a pool created in a subinterpreter runs its jobs there"""
+    print("\n==== Testing subinterpreter ====")
+    
+    # The module can only be initialized in one interpreter per process, and
+    # this one already has it, so the subinterpreter gets a fresh process
+    module_dir = os.path.dirname(os.path.abspath(nornir.__file__))
+    result = subprocess.run(
+        [sys.executable, "-c", SUBINTERPRETER_SCRIPT % module_dir],
+        capture_output=True, text=True, timeout=30,
+    )
+    if "No module named '_interpreters'" in result.stderr:
+        print("Subinterpreters not available, skipping")
+        return
+    
+    seen = [line for line in result.stdout.splitlines() if line.startswith("Interpreter seen")]
+    print(seen[0] if seen else result.stderr)
+    
+    if seen != ["Interpreter seen by job: sub"]:
+        print("WARNING: job did not run in the pool's subinterpreter!")
+
 if __name__ == "__main__":
     print("Starting Nornir diagnostic tests...")
     
@@ -212,6 +1591,123 @@ if __name__ == "__main__":
         # Test multiple jobs
         diagnose_multiple_jobs()
         
+        # Test blocking until completion
+        diagnose_wait_completion()
+        
+        # Test grouped job batching
+        diagnose_grouped_jobs()
+        
+        # Test per-subsystem concurrency caps
+        diagnose_limiter()
+        
+        # Test map-reduce helper
+        diagnose_reduce()
+        
+        # Test newest-first scheduling
+        diagnose_lifo()
+        
+        # Test concurrent.futures integration
+        diagnose_submit()
+        
+        # Test cancelling queued jobs at shutdown
+        diagnose_cancel_futures()
+        
+        # Test per-interval stats
+        diagnose_drain_stats()
+        
+        # Test waiting for the first results
+        diagnose_wait_for_count()
+        
+        # Test draining within a budget
+        diagnose_graceful_stop()
+        
+        # Test cancelling a job through submit_cancellable
+        diagnose_submit_cancellable()
+        
+        # Test metadata attached to jobs
+        diagnose_job_metadata()
+        
+        # Test routing jobs to the main thread
+        diagnose_main_thread_queue()
+        
+        # Test running several jobs per GIL acquisition
+        diagnose_coalesce()
+        
+        # Test reserving queue slots
+        diagnose_reserve()
+        
+        # Test bounding how often a keyed job is overtaken
+        diagnose_max_overtakes()
+        
+        # Test the overflow policies for a full queue
+        diagnose_overflow_policies()
+        
+        # Test spawning workers on demand
+        diagnose_lazy_workers()
+        
+        # Test waiting on a group of jobs
+        diagnose_job_groups()
+        
+        # Test serializing jobs by key
+        diagnose_keyed_jobs()
+        
+        # Test deduping jobs by key
+        diagnose_idempotent_jobs()
+        
+        # Test latest-value-wins submissions
+        diagnose_replace_jobs()
+        
+        # Test the queue saturation callbacks
+        diagnose_full_callbacks()
+        
+        # Test resizing the queue at runtime
+        diagnose_set_queue_capacity()
+        
+        # Test priority among jobs waiting on a key
+        diagnose_keyed_priority()
+        
+        # Test that rescheduled jobs keep their limiter and key
+        diagnose_reschedule_routes()
+        
+        # Test that queue_len leaves out stale messages
+        diagnose_queue_len()
+        
+        # Test on_watermark against the waiting jobs
+        diagnose_watermark()
+        
+        # Test the backlog alarm against the waiting jobs
+        diagnose_backlog_alarm()
+        
+        # Test the depth add_job_with_depth reports
+        diagnose_add_job_with_depth()
+        
+        # Test submit_tracked
+        diagnose_submit_tracked()
+        
+        # Test Future.running on submit futures
+        diagnose_future_running()
+        
+        # Test checkpoint and restore
+        diagnose_checkpoint()
+        
+        # Test category rate limits
+        diagnose_category_rate()
+        
+        # Test weighted round-robin across add_job_to sub-queues
+        diagnose_weighted_queues()
+        
+        # Test the max_in_flight cap
+        diagnose_max_in_flight()
+        
+        # Test a custom queue= and misbehaving ones
+        diagnose_custom_queue()
+        
+        # Test drain_pending
+        diagnose_drain_pending()
+        
+        # Test pools created inside a subinterpreter
+        diagnose_subinterpreter()
+        
     except KeyboardInterrupt:
         print("\nTests interrupted by user")
     except Exception as e: